@@ -0,0 +1,589 @@
+use crate::{
+    BitPattern, Calculation, ComparisonOperator, Constraint, ConstraintComparison,
+    ConstraintRValue, Constructor, FieldMeaning, LValue, RValue, Spec, Token, TokenField,
+};
+use std::{cell::RefCell, collections::HashMap};
+
+/// Subtables can refer to each other, this bounds how deeply they are nested
+/// when assembling, disassembling or generating encodings.
+pub(crate) const MAX_DEPTH: usize = 32;
+
+impl Spec {
+    pub fn assemble(&self, text: &str) -> Option<Vec<u8>> {
+        Assembler::new(self).assemble(text)
+    }
+}
+
+pub struct Assembler<'s> {
+    spec: &'s Spec,
+    address: i128,
+    context: HashMap<String, i128>,
+    /// The patterns of a table for a slice of the text at a depth, operands
+    /// are split in every possible way so the same subtables are matched
+    /// against the same slices over and over.
+    cache: RefCell<Cache>,
+}
+
+impl<'s> Assembler<'s> {
+    pub fn new(spec: &'s Spec) -> Self {
+        Assembler {
+            spec,
+            address: 0,
+            context: HashMap::new(),
+            cache: RefCell::default(),
+        }
+    }
+
+    /// Sets the address of the instruction, used for operands computed
+    /// from `inst_start`.
+    pub fn set_address(&mut self, address: u64) {
+        self.address = address as i128;
+    }
+
+    pub fn set_context(&mut self, name: &str, value: i128) {
+        self.context.insert(name.to_string(), value);
+    }
+
+    /// Encodes an instruction like `MOV RAX, 0x1`. Bits that aren't
+    /// constrained by the chosen constructors are left as zero.
+    pub fn assemble(&self, text: &str) -> Option<Vec<u8>> {
+        let text = normalize(text);
+        self.cache.borrow_mut().clear();
+        self.table("instruction", &text, 0)
+            .into_iter()
+            .next()
+            .map(|pattern| pattern.value)
+    }
+
+    fn table(&self, table: &str, text: &[char], depth: usize) -> Vec<BitPattern> {
+        if depth > MAX_DEPTH {
+            return Vec::new();
+        }
+        let key = (table.to_string(), text.to_vec(), depth);
+        if let Some(patterns) = self.cache.borrow().get(&key) {
+            return patterns.clone();
+        }
+        let patterns: Vec<_> = self
+            .spec
+            .constructors
+            .iter()
+            .filter(|c| c.header.table == table)
+            .flat_map(|c| self.constructor(c, text, depth))
+            .collect();
+        self.cache.borrow_mut().insert(key, patterns.clone());
+        patterns
+    }
+
+    fn constructor(
+        &self,
+        constructor: &Constructor,
+        text: &[char],
+        depth: usize,
+    ) -> Vec<BitPattern> {
+        let template = self.template(constructor);
+        let mut bindings = Vec::new();
+        self.bind(
+            constructor,
+            &template,
+            text,
+            HashMap::new(),
+            &mut bindings,
+            depth,
+        );
+        bindings
+            .iter()
+            .flat_map(|bindings| self.constraint(&constructor.constraint, bindings, depth))
+            .collect()
+    }
+
+    /// Splits the display section of a constructor into literal text and
+    /// operands.
+    fn template(&self, constructor: &'s Constructor) -> Vec<Piece<'s>> {
+        let mnemonic = constructor.header.mnemonic.as_str();
+        let mut pieces = Vec::new();
+        let mut chars = mnemonic.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c == '"' {
+                for (_, c) in chars.by_ref().take_while(|(_, c)| *c != '"') {
+                    pieces.push(Piece::Char(c));
+                }
+            } else if c == '^' {
+                // joins adjacent pieces without a space, nothing to match
+            } else if c.is_whitespace() {
+                pieces.push(Piece::Space);
+            } else if is_word(c) {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| is_word(*c)) {
+                    end = i + c.len_utf8();
+                }
+                let word = &mnemonic[start..end];
+                if self.is_operand(constructor, word) {
+                    pieces.push(Piece::Operand(word));
+                } else {
+                    pieces.extend(word.chars().map(Piece::Char));
+                }
+            } else {
+                pieces.push(Piece::Char(c));
+            }
+        }
+
+        // spaces only separate words, the same as in `normalize`
+        let is_separator = |piece: Option<&Piece>| match piece {
+            Some(Piece::Char(c)) => is_word(*c),
+            Some(Piece::Operand(_)) => true,
+            Some(Piece::Space) | None => false,
+        };
+        let mut template: Vec<Piece> = Vec::with_capacity(pieces.len());
+        for (i, piece) in pieces.iter().enumerate() {
+            if let Piece::Space = piece {
+                if !is_separator(template.last()) || !is_separator(pieces.get(i + 1)) {
+                    continue;
+                }
+            }
+            template.push(*piece);
+        }
+        template
+    }
+
+    fn is_operand(&self, constructor: &Constructor, name: &str) -> bool {
+        self.is_table(name)
+            || self.token_field(name).is_some()
+            || self.context_field(name)
+            || constructor
+                .calculations
+                .iter()
+                .any(|c| matches!(c, Calculation::Assignment(a) if a.lhs == name))
+    }
+
+    fn is_table(&self, name: &str) -> bool {
+        self.spec
+            .constructors
+            .iter()
+            .any(|c| c.header.table == name)
+    }
+
+    fn token_field(&self, name: &str) -> Option<(&'s Token, &'s TokenField)> {
+        self.spec.tokens.iter().find_map(|token| {
+            let field = token.fields.iter().find(|f| f.name == name)?;
+            Some((token, field))
+        })
+    }
+
+    fn context_field(&self, name: &str) -> bool {
+        self.spec
+            .contexts
+            .iter()
+            .flat_map(|c| c.fields.iter())
+            .any(|f| f.name == name)
+    }
+
+    /// Collects every way of assigning parts of `text` to the operands of
+    /// the template.
+    fn bind(
+        &self,
+        constructor: &'s Constructor,
+        template: &[Piece<'s>],
+        text: &[char],
+        bindings: Bindings<'s>,
+        out: &mut Vec<Bindings<'s>>,
+        depth: usize,
+    ) {
+        match template.split_first() {
+            None => {
+                if text.is_empty() {
+                    out.push(bindings);
+                }
+            }
+            Some((Piece::Char(c), template)) => {
+                if text.first().is_some_and(|t| t.eq_ignore_ascii_case(c)) {
+                    self.bind(constructor, template, &text[1..], bindings, out, depth);
+                }
+            }
+            Some((Piece::Space, template)) => {
+                let text = text.strip_prefix(&[' ']).unwrap_or(text);
+                self.bind(constructor, template, text, bindings, out, depth);
+            }
+            Some((Piece::Operand(name), template)) => {
+                for end in 0..=text.len() {
+                    // an operand can only end where the literal after it starts
+                    if let Some(Piece::Char(c)) = template.first() {
+                        if !text.get(end).is_some_and(|t| t.eq_ignore_ascii_case(c)) {
+                            continue;
+                        }
+                    }
+                    let binding = match self.operand(constructor, name, &text[..end], depth) {
+                        Some(binding) => binding,
+                        None => continue,
+                    };
+                    let mut bindings = bindings.clone();
+                    bindings.insert(binding.0, binding.1);
+                    self.bind(constructor, template, &text[end..], bindings, out, depth);
+                }
+            }
+        }
+    }
+
+    fn operand(
+        &self,
+        constructor: &'s Constructor,
+        name: &'s str,
+        text: &[char],
+        depth: usize,
+    ) -> Option<(&'s str, Binding)> {
+        if self.is_table(name) {
+            let patterns = self.table(name, text, depth + 1);
+            return if patterns.is_empty() {
+                None
+            } else {
+                Some((name, Binding::Table(patterns)))
+            };
+        }
+
+        let text: String = text.iter().collect();
+        if let Some((_, field)) = self.token_field(name) {
+            let value = match &field.meaning {
+                FieldMeaning::Variables(names) | FieldMeaning::Names(names) => names
+                    .iter()
+                    .position(|n| n != "_" && n.eq_ignore_ascii_case(&text))?
+                    as i128,
+                FieldMeaning::Values(values) => {
+                    let value = parse_integer(&text)?;
+                    values
+                        .iter()
+                        .position(|v| v.map(|v| v as i128) == Some(value))?
+                        as i128
+                }
+                FieldMeaning::Default => parse_integer(&text)?,
+            };
+            return encode_field(field, value).map(|value| (name, Binding::Field(value)));
+        }
+
+        // operands computed in the calculation block are solved for the
+        // field they're computed from
+        let rhs = constructor.calculations.iter().find_map(|c| match c {
+            Calculation::Assignment(a) if a.lhs == name => Some(&a.rhs),
+            _ => None,
+        })?;
+        let (field, value) = self.solve(rhs, parse_integer(&text)?)?;
+        let (_, token_field) = self.token_field(field)?;
+        encode_field(token_field, value).map(|value| (field, Binding::Field(value)))
+    }
+
+    fn solve<'r>(&self, rvalue: &'r RValue, target: i128) -> Option<(&'r str, i128)> {
+        match rvalue {
+            RValue::LValue(LValue::Ident(ident)) => Some((&ident.field, target)),
+            RValue::Parenthesized(inner) => self.solve(&inner.op, target),
+            RValue::Neg(inner) => self.solve(&inner.op, -target),
+            RValue::Add(inner) => match (self.eval(&inner.lhs), self.eval(&inner.rhs)) {
+                (Some(lhs), None) => self.solve(&inner.rhs, target - lhs),
+                (None, Some(rhs)) => self.solve(&inner.lhs, target - rhs),
+                _ => None,
+            },
+            RValue::Sub(inner) => match (self.eval(&inner.lhs), self.eval(&inner.rhs)) {
+                (Some(lhs), None) => self.solve(&inner.rhs, lhs - target),
+                (None, Some(rhs)) => self.solve(&inner.lhs, target + rhs),
+                _ => None,
+            },
+            RValue::Mult(inner) => match (self.eval(&inner.lhs), self.eval(&inner.rhs)) {
+                (Some(factor), None) if factor != 0 && target % factor == 0 => {
+                    self.solve(&inner.rhs, target / factor)
+                }
+                (None, Some(factor)) if factor != 0 && target % factor == 0 => {
+                    self.solve(&inner.lhs, target / factor)
+                }
+                _ => None,
+            },
+            RValue::LShift(inner) => {
+                let shift = self.eval(&inner.rhs)?;
+                if self.eval(&inner.lhs).is_none() && target & ((1 << shift) - 1) == 0 {
+                    self.solve(&inner.lhs, target >> shift)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn eval(&self, rvalue: &RValue) -> Option<i128> {
+        match rvalue {
+            RValue::Constant(constant) => Some(constant.value),
+            RValue::LValue(LValue::Ident(ident)) if ident.field == "inst_start" => {
+                Some(self.address)
+            }
+            RValue::Parenthesized(inner) => self.eval(&inner.op),
+            RValue::Neg(inner) => Some(-self.eval(&inner.op)?),
+            RValue::Add(inner) => Some(self.eval(&inner.lhs)? + self.eval(&inner.rhs)?),
+            RValue::Sub(inner) => Some(self.eval(&inner.lhs)? - self.eval(&inner.rhs)?),
+            RValue::Mult(inner) => Some(self.eval(&inner.lhs)? * self.eval(&inner.rhs)?),
+            RValue::LShift(inner) => Some(self.eval(&inner.lhs)? << self.eval(&inner.rhs)?),
+            _ => None,
+        }
+    }
+
+    fn constraint(
+        &self,
+        constraint: &Constraint,
+        bindings: &Bindings,
+        depth: usize,
+    ) -> Vec<BitPattern> {
+        match constraint {
+            Constraint::Ellipsis(inner) => self.constraint(&inner.op, bindings, depth),
+            Constraint::And(inner) => {
+                let lhs = self.constraint(&inner.lhs, bindings, depth);
+                let rhs = self.constraint(&inner.rhs, bindings, depth);
+                lhs.iter()
+                    .flat_map(|l| rhs.iter().filter_map(move |r| l.merge(r, 0)))
+                    .collect()
+            }
+            Constraint::Or(inner) => {
+                let mut patterns = self.constraint(&inner.lhs, bindings, depth);
+                patterns.extend(self.constraint(&inner.rhs, bindings, depth));
+                patterns
+            }
+            Constraint::Semi(inner) => {
+                let lhs = self.constraint(&inner.lhs, bindings, depth);
+                let rhs = self.constraint(&inner.rhs, bindings, depth);
+                lhs.iter()
+                    .flat_map(|l| rhs.iter().filter_map(move |r| l.merge(r, l.len())))
+                    .collect()
+            }
+            Constraint::Parenthesized(inner) => self.constraint(inner, bindings, depth),
+            Constraint::Comparison(inner) => self.comparison(inner, bindings).into_iter().collect(),
+            Constraint::Exists(inner) => self.exists(&inner.name, bindings, depth),
+            Constraint::Constructor(inner) => self.exists(&inner.name, bindings, depth),
+        }
+    }
+
+    fn exists(&self, name: &str, bindings: &Bindings, depth: usize) -> Vec<BitPattern> {
+        match bindings.get(name) {
+            Some(Binding::Table(patterns)) => patterns.clone(),
+            Some(Binding::Field(value)) => {
+                let (token, field) = self.token_field(name).unwrap();
+                vec![self.field_pattern(token, field, Some(*value))]
+            }
+            // subtables that aren't displayed still have to be encoded
+            None if self.is_table(name) => self.table(name, &[], depth + 1),
+            None => match self.token_field(name) {
+                Some((token, field)) => vec![self.field_pattern(token, field, None)],
+                None => vec![BitPattern::default()],
+            },
+        }
+    }
+
+    fn comparison(
+        &self,
+        comparison: &ConstraintComparison,
+        bindings: &Bindings,
+    ) -> Option<BitPattern> {
+        let name = match &comparison.lhs {
+            ConstraintRValue::Field(name) => name,
+            _ => return None,
+        };
+        let rhs = self.constraint_value(&comparison.rhs, bindings)?;
+        let op = comparison.comparison;
+
+        if self.context_field(name) {
+            let value = self.context.get(name).copied().unwrap_or_default();
+            return if op.compare(value, rhs) {
+                Some(BitPattern::default())
+            } else {
+                None
+            };
+        }
+
+        let (token, field) = self.token_field(name)?;
+        let value = match bindings.get(name.as_str()) {
+            Some(Binding::Field(value)) => field.decode(*value),
+            _ => match op {
+                ComparisonOperator::Equal
+                | ComparisonOperator::LessEqual
+                | ComparisonOperator::GreaterEqual => rhs,
+                ComparisonOperator::NotEqual if rhs == 0 => 1,
+                ComparisonOperator::Greater => rhs + 1,
+                ComparisonOperator::Less => rhs - 1,
+                ComparisonOperator::NotEqual => 0,
+            },
+        };
+        if !op.compare(value, rhs) {
+            return None;
+        }
+        let value = encode_field(field, value)?;
+        Some(self.field_pattern(token, field, Some(value)))
+    }
+
+    fn constraint_value(&self, rvalue: &ConstraintRValue, bindings: &Bindings) -> Option<i128> {
+        rvalue.evaluate(&mut |name| match bindings.get(name) {
+            Some(Binding::Field(value)) => {
+                let (_, field) = self.token_field(name)?;
+                Some(field.decode(*value))
+            }
+            _ => None,
+        })
+    }
+
+    fn field_pattern(&self, token: &Token, field: &TokenField, value: Option<i128>) -> BitPattern {
+        let len = token.size as usize / 8;
+        let mut pattern = BitPattern::default();
+        pattern.extend(len);
+        if let Some(value) = value {
+            pattern
+                .set_field(
+                    0,
+                    len,
+                    &field.range,
+                    value,
+                    self.spec.token_endianness(token),
+                )
+                .unwrap();
+        }
+        pattern
+    }
+}
+
+type Bindings<'s> = HashMap<&'s str, Binding>;
+
+type Cache = HashMap<(String, Vec<char>, usize), Vec<BitPattern>>;
+
+#[derive(Clone)]
+enum Binding {
+    /// The raw, unsigned bits of a token field.
+    Field(i128),
+    Table(Vec<BitPattern>),
+}
+
+#[derive(Copy, Clone)]
+enum Piece<'s> {
+    Char(char),
+    Space,
+    Operand(&'s str),
+}
+
+pub(crate) fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Collapses whitespace, keeping a single space only between two words.
+fn normalize(text: &str) -> Vec<char> {
+    let mut normalized = Vec::new();
+    let mut space = false;
+    for c in text.trim().chars() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && is_word(c) && normalized.last().is_some_and(|c| is_word(*c)) {
+            normalized.push(' ');
+        }
+        space = false;
+        normalized.push(c);
+    }
+    normalized
+}
+
+fn parse_integer(s: &str) -> Option<i128> {
+    let (s, sign) = match s.strip_prefix('-') {
+        Some(s) => (s, -1),
+        None => (s, 1),
+    };
+    let value = if let Some(hex) = s.strip_prefix("0x") {
+        i128::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        i128::from_str_radix(bin, 2).ok()?
+    } else if s.starts_with(|c: char| c.is_ascii_digit()) {
+        s.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(sign * value)
+}
+
+/// Returns the raw bits for `value`, or `None` if it doesn't fit the field.
+fn encode_field(field: &TokenField, value: i128) -> Option<i128> {
+    let bits = (field.range.end - field.range.start + 1) as u32;
+    let (min, max) = if field.signed {
+        (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+    } else {
+        (0, (1 << bits) - 1)
+    };
+    if value < min || value > max {
+        return None;
+    }
+    Some(value & ((1 << bits) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Assembler;
+    use crate::Spec;
+
+    #[test]
+    fn test_assemble() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define token instr(16)
+    op = (12,15)
+    dst = (8,9)
+    src = (6,7)
+    imm = (0,7)
+    simm = (0,7) signed
+;
+attach variables [ dst src ] [ r0 r1 r2 r3 ];
+
+Addr: "[" src "]" is src { export *:4 src; }
+Rel: reloc is simm [ reloc = inst_start + simm * 2; ] { export *:4 reloc; }
+
+:mov dst, src is op=1 & dst & src { dst = src; }
+:li dst, imm is op=2 & dst & imm { dst = imm; }
+:ld dst, Addr is op=3 & dst & Addr { dst = Addr; }
+:b Rel is op=4 & Rel { goto Rel; }
+:nop is op=5 & imm=0 { }
+"#,
+        );
+
+        assert_eq!(spec.assemble("mov r1, r2"), Some(vec![0x80, 0x11]));
+        assert_eq!(spec.assemble("MOV  r1,r2"), Some(vec![0x80, 0x11]));
+        assert_eq!(spec.assemble("li r3, 0x7f"), Some(vec![0x7f, 0x23]));
+        assert_eq!(spec.assemble("ld r0, [r3]"), Some(vec![0xc0, 0x30]));
+        assert_eq!(spec.assemble("nop"), Some(vec![0x00, 0x50]));
+        assert_eq!(spec.assemble("mov r4, r0"), None);
+        assert_eq!(spec.assemble("li r0, 0x100"), None);
+
+        let mut assembler = Assembler::new(&spec);
+        assembler.set_address(0x100);
+        assert_eq!(assembler.assemble("b 0x104"), Some(vec![0x02, 0x40]));
+        assert_eq!(assembler.assemble("b 0xfc"), Some(vec![0xfe, 0x40]));
+        assert_eq!(assembler.assemble("b 0x101"), None);
+    }
+
+    #[test]
+    fn test_assemble_nested() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define token instr(8)
+    op = (0,7)
+;
+define token imm8(8)
+    imm = (0,7)
+;
+
+List: imm is imm { }
+List: List","imm is List; imm { }
+
+:push List is op=1; List { }
+"#,
+        );
+
+        // every prefix of the list is a candidate for the nested `List`
+        let list: Vec<_> = (1..=24).map(|i| i.to_string()).collect();
+        let bytes = spec.assemble(&format!("push {}", list.join(",")));
+        let mut expected = vec![1];
+        expected.extend(1..=24);
+        assert_eq!(bytes, Some(expected));
+    }
+}
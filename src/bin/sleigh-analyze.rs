@@ -0,0 +1,34 @@
+use sleigh::{preprocess, Spec};
+use std::{env, path::Path, process::exit};
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: sleigh-analyze <file.slaspec>");
+            exit(2);
+        }
+    };
+    let path = Path::new(&path);
+    let source = preprocess(path.parent().unwrap(), path.file_name().unwrap());
+    let spec = Spec::parse(&source);
+
+    let diagnostics = spec.analyze_patterns();
+    for diagnostic in diagnostics.iter() {
+        match diagnostic.constructor {
+            Some(i) => {
+                let header = &spec.constructors[i].header;
+                println!(
+                    "{}:{}: {}",
+                    header.table,
+                    header.mnemonic.trim(),
+                    diagnostic.kind
+                );
+            }
+            None => println!("{}", diagnostic.kind),
+        }
+    }
+    if !diagnostics.is_empty() {
+        exit(1);
+    }
+}
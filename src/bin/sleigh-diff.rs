@@ -0,0 +1,42 @@
+use sleigh::{preprocess, Constructor, Spec, SpecChange};
+use std::{env, path::Path, process::exit};
+
+fn load(path: &str) -> Spec {
+    let path = Path::new(path);
+    let source = preprocess(path.parent().unwrap(), path.file_name().unwrap());
+    Spec::parse(&source)
+}
+
+fn name(constructor: &Constructor) -> String {
+    let header = &constructor.header;
+    format!("{}:{}", header.table, header.mnemonic.trim())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: sleigh-diff <old.slaspec> <new.slaspec>");
+        exit(2);
+    }
+    let old = load(&args[0]);
+    let new = load(&args[1]);
+
+    let changes = old.diff(&new);
+    for change in changes.iter() {
+        match change {
+            SpecChange::AddedConstructor { new: i } => {
+                println!("{}: {}", name(&new.constructors[*i]), change)
+            }
+            SpecChange::RemovedConstructor { old: i } => {
+                println!("{}: {}", name(&old.constructors[*i]), change)
+            }
+            SpecChange::ChangedConstructor { new: i, .. } => {
+                println!("{}: {}", name(&new.constructors[*i]), change)
+            }
+            _ => println!("{}", change),
+        }
+    }
+    if !changes.is_empty() {
+        exit(1);
+    }
+}
@@ -0,0 +1,832 @@
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidOpenTextDocument, Notification as LspNotification,
+        PublishDiagnostics,
+    },
+    request::{DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as LspRequest},
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse, GotoDefinitionResponse,
+    Hover, HoverContents, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf,
+    Position, PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use pest::{
+    error::InputLocation,
+    iterators::{Pair, Pairs},
+    Parser, Span,
+};
+use sleigh::{preprocess_with_reader, DiagnosticKind, Rule, SleighParser, SourceMap, Spec};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fs::{read_dir, read_to_string},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+fn main() {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities).unwrap())
+        .unwrap();
+
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request).unwrap() {
+                    break;
+                }
+                let response = server.request(request);
+                connection.sender.send(Message::Response(response)).unwrap();
+            }
+            Message::Notification(notification) => {
+                if let Some(diagnostics) = server.notification(notification) {
+                    connection
+                        .sender
+                        .send(Message::Notification(diagnostics))
+                        .unwrap();
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    io_threads.join().unwrap();
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<PathBuf, Document>,
+    /// The text of open documents, which may differ from the disk.
+    buffers: HashMap<PathBuf, String>,
+    /// The file each file was last preprocessed as part of.
+    roots: HashMap<PathBuf, PathBuf>,
+}
+
+impl Server {
+    fn request(&mut self, request: Request) -> Response {
+        let id = request.id.clone();
+        match self.respond(request) {
+            Ok(result) => Response::new_ok(id, result),
+            Err((code, message)) => Response::new_err(id, code as i32, message),
+        }
+    }
+
+    fn respond(&mut self, request: Request) -> Result<serde_json::Value, (ErrorCode, String)> {
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => {
+                let params = params::<GotoDefinition>(request)?;
+                let position = params.text_document_position_params;
+                let locations = self.definition(
+                    &position.text_document.uri,
+                    position.position,
+                    |uri, definition| Location::new(uri.clone(), definition.range),
+                );
+                serde_json::to_value(GotoDefinitionResponse::Array(locations))
+            }
+            HoverRequest::METHOD => {
+                let params = params::<HoverRequest>(request)?;
+                let position = params.text_document_position_params;
+                let details = self.definition(
+                    &position.text_document.uri,
+                    position.position,
+                    |_, definition| definition.detail.clone(),
+                );
+                let hover = if details.is_empty() {
+                    None
+                } else {
+                    Some(Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: details.join("\n\n"),
+                        }),
+                        range: None,
+                    })
+                };
+                serde_json::to_value(hover)
+            }
+            DocumentSymbolRequest::METHOD => {
+                let params = params::<DocumentSymbolRequest>(request)?;
+                let symbols = self
+                    .documents
+                    .get(&path(&params.text_document.uri))
+                    .map(|document| document.symbols.clone())
+                    .unwrap_or_default();
+                serde_json::to_value(DocumentSymbolResponse::Nested(symbols))
+            }
+            method => {
+                let message = format!("unknown method `{}`", method);
+                return Err((ErrorCode::MethodNotFound, message));
+            }
+        };
+        result.map_err(|e| (ErrorCode::InternalError, e.to_string()))
+    }
+
+    fn notification(&mut self, notification: Notification) -> Option<Notification> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = notification
+                    .extract::<<DidOpenTextDocument as LspNotification>::Params>(
+                        DidOpenTextDocument::METHOD,
+                    )
+                    .ok()?;
+                (params.text_document.uri, params.text_document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params = notification
+                    .extract::<<DidChangeTextDocument as LspNotification>::Params>(
+                        DidChangeTextDocument::METHOD,
+                    )
+                    .ok()?;
+                let text = params.content_changes.pop()?.text;
+                (params.text_document.uri, text)
+            }
+            _ => return None,
+        };
+
+        let path = path(&uri);
+        self.buffers.insert(path.clone(), text);
+        let root = self.root(&path);
+        self.analyze(&root);
+        if self.roots.get(&path) != Some(&root) {
+            // the file isn't included from where it used to be anymore
+            self.analyze(&path);
+        }
+
+        let diagnostics = self
+            .documents
+            .get(&path)
+            .map(|document| document.diagnostics.clone())
+            .unwrap_or_default();
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        Some(Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            params,
+        ))
+    }
+
+    /// The file `path` has to be preprocessed as part of to see the
+    /// definitions it uses: itself for a `.slaspec`, otherwise a `.slaspec`
+    /// next to it that includes it.
+    fn root(&mut self, path: &Path) -> PathBuf {
+        if is_root(path) {
+            return path.to_path_buf();
+        }
+        if let Some(root) = self.roots.get(path) {
+            return root.clone();
+        }
+
+        let mut candidates: Vec<_> = match path.parent().map(read_dir) {
+            Some(Ok(entries)) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| is_root(path))
+                .collect(),
+            _ => Vec::new(),
+        };
+        candidates.sort();
+        for candidate in candidates {
+            self.analyze(&candidate);
+            if self.roots.get(path) == Some(&candidate) {
+                return candidate;
+            }
+        }
+        path.to_path_buf()
+    }
+
+    /// Preprocesses and indexes `root` and every file it includes.
+    fn analyze(&mut self, root: &Path) {
+        let dir = root.parent().unwrap_or_else(|| Path::new(""));
+        let file = root.file_name().unwrap_or_default();
+        let buffers = &self.buffers;
+        let mut texts = HashMap::new();
+        let preprocessed = catch_unwind(AssertUnwindSafe(|| {
+            preprocess_with_reader(dir, file, |path| {
+                let text = match buffers.get(path) {
+                    Some(text) => text.clone(),
+                    None => read_to_string(path)?,
+                };
+                texts.insert(path.to_path_buf(), text.clone());
+                Ok(text)
+            })
+        }));
+
+        let (source, map) = match preprocessed {
+            Ok(preprocessed) => preprocessed,
+            Err(payload) => {
+                let mut document = Document {
+                    text: texts.remove(root).unwrap_or_default(),
+                    ..Default::default()
+                };
+                document.error(Range::default(), panic_message(payload));
+                self.documents.insert(root.to_path_buf(), document);
+                return;
+            }
+        };
+
+        let mut index = Index {
+            map: &map,
+            root,
+            documents: HashMap::new(),
+        };
+        index.parse(&source);
+        for (path, text) in texts {
+            let mut document = index.documents.remove(&path).unwrap_or_default();
+            document.text = text;
+            self.roots.insert(path.clone(), root.to_path_buf());
+            self.documents.insert(path, document);
+        }
+    }
+
+    fn definition<T>(
+        &self,
+        uri: &Url,
+        position: Position,
+        f: impl Fn(&Url, &Definition) -> T,
+    ) -> Vec<T> {
+        let current = path(uri);
+        let name = match self
+            .documents
+            .get(&current)
+            .and_then(|document| word_at(&document.text, position))
+        {
+            Some(name) => name,
+            None => return Vec::new(),
+        };
+
+        // prefer definitions from the current document
+        let documents = self
+            .documents
+            .get_key_value(&current)
+            .into_iter()
+            .chain(self.documents.iter().filter(|(p, _)| **p != current));
+        for (path, document) in documents {
+            let uri = match self::uri(path) {
+                Some(uri) => uri,
+                None => continue,
+            };
+            let definitions: Vec<_> = document
+                .definitions
+                .iter()
+                .filter(|d| d.name == name)
+                .map(|d| f(&uri, d))
+                .collect();
+            if !definitions.is_empty() {
+                return definitions;
+            }
+        }
+        Vec::new()
+    }
+}
+
+#[derive(Default)]
+struct Document {
+    text: String,
+    definitions: Vec<Definition>,
+    symbols: Vec<DocumentSymbol>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+struct Definition {
+    name: String,
+    range: Range,
+    detail: String,
+}
+
+impl Document {
+    fn error(&mut self, range: Range, message: String) {
+        self.diagnostics.push(Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            message,
+            ..Default::default()
+        });
+    }
+}
+
+/// The definitions, symbols and diagnostics of a preprocessed spec, sorted
+/// into the files they came from.
+struct Index<'a> {
+    map: &'a SourceMap,
+    root: &'a Path,
+    documents: HashMap<PathBuf, Document>,
+}
+
+impl Index<'_> {
+    fn parse(&mut self, source: &str) {
+        match SleighParser::parse(Rule::file, source) {
+            Ok(stmts) => {
+                self.index_stmts(stmts, None);
+                self.validate(source);
+            }
+            Err(e) => {
+                let (start, end) = match e.location {
+                    InputLocation::Pos(pos) => (pos, pos),
+                    InputLocation::Span(span) => span,
+                };
+                let (path, range) = self.locate(start, end);
+                let message = e.variant.message().to_string();
+                self.document(path).error(range, message);
+            }
+        }
+    }
+
+    fn index_stmts(&mut self, stmts: Pairs<Rule>, table: Option<&str>) {
+        for stmt in stmts {
+            let span = stmt.as_span();
+            let rule = stmt.as_rule();
+            let mut tokens = stmt.into_inner();
+            match rule {
+                Rule::define_space => {
+                    let name = tokens.next().unwrap();
+                    self.define(
+                        name.as_str(),
+                        name.as_span(),
+                        format!("space `{}`", name.as_str()),
+                    );
+                }
+                Rule::define_register => {
+                    let offset = parse_integer(tokens.next().unwrap().as_str());
+                    let size = parse_integer(tokens.next().unwrap().as_str());
+                    for (i, name) in tokens.next().unwrap().into_inner().enumerate() {
+                        if name.as_rule() == Rule::placeholder {
+                            continue;
+                        }
+                        let name = name.into_inner().next().unwrap();
+                        let span = name.as_span();
+                        let name = name.as_str().trim_matches('"');
+                        let offset = offset + i as u128 * size;
+                        let detail =
+                            format!("register `{}`: offset {:#x}, size {}", name, offset, size);
+                        self.define(name, span, detail);
+                    }
+                }
+                Rule::define_token => {
+                    let name = tokens.next().unwrap();
+                    let size = tokens.next().unwrap().as_str();
+                    let mut children = Vec::new();
+                    for field in tokens.filter(|t| t.as_rule() == Rule::field) {
+                        let detail = format!("token `{}`({})", name.as_str(), size);
+                        children.push(self.field(field, detail));
+                    }
+                    self.symbol(
+                        name.as_str(),
+                        SymbolKind::STRUCT,
+                        span,
+                        name.as_span(),
+                        children,
+                    );
+                    self.define(
+                        name.as_str(),
+                        name.as_span(),
+                        format!("token `{}`({})", name.as_str(), size),
+                    );
+                }
+                Rule::define_context => {
+                    let register = tokens.next().unwrap();
+                    let mut children = Vec::new();
+                    for field in tokens {
+                        let detail = format!("context `{}`", register.as_str());
+                        children.push(self.field(field, detail));
+                    }
+                    self.symbol(
+                        register.as_str(),
+                        SymbolKind::STRUCT,
+                        span,
+                        register.as_span(),
+                        children,
+                    );
+                }
+                Rule::define_pcodeop => {
+                    let name = tokens.next().unwrap();
+                    self.define(
+                        name.as_str(),
+                        name.as_span(),
+                        format!("pcodeop `{}`", name.as_str()),
+                    );
+                }
+                Rule::stmt_macro => {
+                    let name = tokens.next().unwrap();
+                    let args: Vec<_> = tokens
+                        .take_while(|t| t.as_rule() == Rule::ident)
+                        .map(|t| t.as_str())
+                        .collect();
+                    let detail = format!("macro `{}({})`", name.as_str(), args.join(", "));
+                    self.symbol(
+                        name.as_str(),
+                        SymbolKind::FUNCTION,
+                        span,
+                        name.as_span(),
+                        Vec::new(),
+                    );
+                    self.define(name.as_str(), name.as_span(), detail);
+                }
+                Rule::with_block => {
+                    let mut table = table;
+                    let first = tokens.peek().unwrap();
+                    if first.as_rule() == Rule::ident {
+                        table = Some(first.as_str());
+                        tokens.next();
+                    }
+                    let table = table.map(str::to_string);
+                    self.index_stmts(tokens, table.as_deref());
+                }
+                Rule::constructor => {
+                    let header = tokens.next().unwrap();
+                    let mut header_tokens = header.clone().into_inner();
+                    let first = header_tokens.next().unwrap();
+                    let (table_name, mnemonic) = if first.as_rule() == Rule::ident {
+                        (first.as_str(), header_tokens.next().unwrap().as_str())
+                    } else {
+                        (table.unwrap_or("instruction"), first.as_str())
+                    };
+                    let name = format!("{}:{}", table_name, mnemonic.trim());
+                    self.symbol(
+                        &name,
+                        SymbolKind::METHOD,
+                        span,
+                        header.as_span(),
+                        Vec::new(),
+                    );
+                    let detail = format!("table `{}`", table_name);
+                    self.define(table_name, header.as_span(), detail);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn field(&mut self, field: Pair<Rule>, detail: String) -> DocumentSymbol {
+        let span = field.as_span();
+        let mut tokens = field.into_inner();
+        let name = tokens.next().unwrap();
+        let start = tokens.next().unwrap().as_str();
+        let end = tokens.next().unwrap().as_str();
+        let attributes: Vec<_> = tokens.map(|t| t.as_str()).collect();
+
+        let mut detail = format!(
+            "field `{}` = ({},{}) in {}\n\nbits {}..={}",
+            name.as_str(),
+            start,
+            end,
+            detail,
+            start,
+            end
+        );
+        if !attributes.is_empty() {
+            detail += &format!(", {}", attributes.join(" "));
+        }
+        self.define(name.as_str(), name.as_span(), detail);
+
+        #[allow(deprecated)]
+        DocumentSymbol {
+            name: name.as_str().to_string(),
+            detail: Some(format!("({},{})", start, end)),
+            kind: SymbolKind::FIELD,
+            tags: None,
+            deprecated: None,
+            range: self.span(span).1,
+            selection_range: self.span(name.as_span()).1,
+            children: None,
+        }
+    }
+
+    fn define(&mut self, name: &str, span: Span, detail: String) {
+        let (path, range) = self.span(span);
+        self.document(path).definitions.push(Definition {
+            name: name.to_string(),
+            range,
+            detail,
+        });
+    }
+
+    fn symbol(
+        &mut self,
+        name: &str,
+        kind: SymbolKind,
+        span: Span,
+        selection: Span,
+        children: Vec<DocumentSymbol>,
+    ) {
+        let (path, range) = self.span(span);
+        let (_, selection_range) = self.span(selection);
+        #[allow(deprecated)]
+        self.document(path).symbols.push(DocumentSymbol {
+            name: name.to_string(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range,
+            children: if children.is_empty() {
+                None
+            } else {
+                Some(children)
+            },
+        });
+    }
+
+    fn validate(&mut self, source: &str) {
+        // the parser panics on constructs it doesn't support yet
+        let spec = match catch_unwind(AssertUnwindSafe(|| Spec::parse(source))) {
+            Ok(spec) => spec,
+            Err(payload) => {
+                let root = self.root.to_path_buf();
+                self.document(root)
+                    .error(Range::default(), panic_message(payload));
+                return;
+            }
+        };
+
+        for diagnostic in spec.validate() {
+            let name = match &diagnostic.kind {
+                DiagnosticKind::UndefinedSymbol(name)
+                | DiagnosticKind::DuplicateDefinition(name)
+                | DiagnosticKind::RecursiveMacro(name) => Some(name.as_str()),
+                DiagnosticKind::FieldOutOfRange { field, .. }
+                | DiagnosticKind::AttachLengthMismatch { field, .. } => Some(field.as_str()),
+                DiagnosticKind::MacroArgumentCount { r#macro, .. } => Some(r#macro.as_str()),
+                DiagnosticKind::SizeMismatch { .. }
+                | DiagnosticKind::DeadConstructor
+                | DiagnosticKind::AmbiguousConstructors { .. }
+                | DiagnosticKind::UncoveredEncoding { .. } => None,
+            };
+            let span = match diagnostic.constructor {
+                Some(i) if diagnostic.span.is_empty() => spec.constructors[i].span,
+                _ => diagnostic.span,
+            };
+            let (path, range) = if !span.is_empty() {
+                self.locate(span.start, span.end)
+            } else {
+                name.and_then(|name| self.definition(name))
+                    .unwrap_or_else(|| (self.root.to_path_buf(), Range::default()))
+            };
+            self.document(path)
+                .error(range, diagnostic.kind.to_string());
+        }
+    }
+
+    fn definition(&self, name: &str) -> Option<(PathBuf, Range)> {
+        self.documents.iter().find_map(|(path, document)| {
+            let definition = document.definitions.iter().rev().find(|d| d.name == name)?;
+            Some((path.clone(), definition.range))
+        })
+    }
+
+    fn document(&mut self, path: PathBuf) -> &mut Document {
+        self.documents.entry(path).or_default()
+    }
+
+    fn span(&self, span: Span) -> (PathBuf, Range) {
+        self.locate(span.start(), span.end())
+    }
+
+    /// Maps a byte range of the preprocessed source back to the file it was
+    /// read from, ranges that can't be mapped are put at the start of the
+    /// root.
+    fn locate(&self, start: usize, end: usize) -> (PathBuf, Range) {
+        let position = |location: sleigh::Location| {
+            Position::new(location.line as u32 - 1, location.column as u32 - 1)
+        };
+        let start = match self.map.locate(start) {
+            Some(start) => start,
+            None => return (self.root.to_path_buf(), Range::default()),
+        };
+        let path = start.file.to_path_buf();
+        let start = position(start);
+        // the end is one past the last byte, which may already be in the
+        // next file
+        let end = match self.map.locate(end.saturating_sub(1)) {
+            Some(last) if end > 0 && last.file == path => {
+                let last = position(last);
+                Position::new(last.line, last.character + 1).max(start)
+            }
+            _ => start,
+        };
+        (path, Range::new(start, end))
+    }
+}
+
+fn params<R: LspRequest>(request: Request) -> Result<R::Params, (ErrorCode, String)> {
+    match request.extract(R::METHOD) {
+        Ok((_, params)) => Ok(params),
+        Err(e) => Err((ErrorCode::InvalidParams, e.to_string())),
+    }
+}
+
+fn is_root(path: &Path) -> bool {
+    path.extension() == Some("slaspec".as_ref())
+}
+
+/// Documents are keyed by path so they can be matched up with the files
+/// the preprocessor reads, documents that aren't files keep their URI.
+fn path(uri: &Url) -> PathBuf {
+    uri.to_file_path()
+        .unwrap_or_else(|_| PathBuf::from(uri.as_str()))
+}
+
+fn uri(path: &Path) -> Option<Url> {
+    Url::from_file_path(path)
+        .ok()
+        .or_else(|| Url::parse(path.to_str()?).ok())
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "panicked without a message".to_string(),
+        },
+    }
+}
+
+/// `position.character` counts UTF-16 code units, as LSP clients send it.
+fn word_at(text: &str, position: Position) -> Option<String> {
+    let line = text.lines().nth(position.line as usize)?;
+    let chars: Vec<_> = line.chars().collect();
+    let is_ident = |c: &char| c.is_ascii_alphanumeric() || *c == '_' || *c == '.';
+
+    let mut units = 0;
+    let column = chars
+        .iter()
+        .position(|c| {
+            units += c.len_utf16();
+            units > position.character as usize
+        })
+        .unwrap_or(chars.len());
+    let start = chars[..column]
+        .iter()
+        .rposition(|c| !is_ident(c))
+        .map_or(0, |i| i + 1);
+    let end = chars[column..]
+        .iter()
+        .position(|c| !is_ident(c))
+        .map_or(chars.len(), |i| column + i);
+    if start == end {
+        None
+    } else {
+        Some(chars[start..end].iter().collect())
+    }
+}
+
+fn parse_integer(s: &str) -> u128 {
+    let s = s.trim_matches('"');
+    if let Some(hex) = s.strip_prefix("0x") {
+        u128::from_str_radix(hex, 16).unwrap()
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        u128::from_str_radix(bin, 2).unwrap()
+    } else {
+        s.parse().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{word_at, Server};
+    use lsp_server::{ErrorCode, Notification, Request, RequestId};
+    use lsp_types::{
+        notification::{DidOpenTextDocument, Notification as LspNotification},
+        request::{HoverRequest, Request as LspRequest},
+        DidOpenTextDocumentParams, Position, Range, TextDocumentItem, Url,
+    };
+    use std::{env::temp_dir, fs, process};
+
+    #[test]
+    fn test_index() {
+        let dir = temp_dir().join(format!("sleigh-lsp-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("main.slaspec"),
+            r#"@define SIZE "4"
+define endian=little;
+define space ram type=ram_space size=$(SIZE) default;
+@include "insn.sinc"
+"#,
+        )
+        .unwrap();
+        let insn = r#"define register offset=0 size=$(SIZE) [ r0 r1 ];
+define token instr(8)
+    op = (4,7)
+    reg = (0,0)
+;
+attach variables reg [ r0 r1 ];
+
+macro clear(x) { x = 0; }
+
+Src: reg is reg { export reg; }
+:mov reg is op=1 & reg { clear(reg); }
+:bad is op=2 { r0 = r3; }
+"#;
+        fs::write(dir.join("insn.sinc"), insn).unwrap();
+
+        let mut server = Server::default();
+        let uri = Url::from_file_path(dir.join("insn.sinc")).unwrap();
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri, "sleigh".to_string(), 0, insn.to_string()),
+        };
+        server
+            .notification(Notification::new(
+                DidOpenTextDocument::METHOD.to_string(),
+                params,
+            ))
+            .unwrap();
+        let main = server.documents.remove(&dir.join("main.slaspec")).unwrap();
+        let document = server.documents.remove(&dir.join("insn.sinc")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(main.definitions[0].name, "ram");
+        assert!(main.symbols.is_empty());
+
+        let definition = document
+            .definitions
+            .iter()
+            .find(|d| d.name == "op")
+            .unwrap();
+        assert_eq!(definition.range.start, Position::new(2, 4));
+        assert!(definition.detail.contains("bits 4..=7"));
+
+        let names: Vec<_> = document.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "instr",
+                "clear",
+                "Src:reg",
+                "instruction:mov reg",
+                "instruction:bad"
+            ]
+        );
+
+        assert_eq!(document.diagnostics.len(), 1);
+        assert_eq!(document.diagnostics[0].message, "undefined symbol `r3`");
+        assert_eq!(
+            document.diagnostics[0].range,
+            Range::new(Position::new(11, 20), Position::new(11, 22))
+        );
+
+        assert_eq!(
+            word_at(&document.text, Position::new(10, 6)).as_deref(),
+            Some("reg")
+        );
+        // `é` is one UTF-16 code unit but two bytes, `𝔵` is two code units
+        let text = "x é 𝔵 reg";
+        assert_eq!(word_at(text, Position::new(0, 6)), None);
+        assert_eq!(word_at(text, Position::new(0, 7)).as_deref(), Some("reg"));
+    }
+
+    #[test]
+    fn test_parse_panic() {
+        let dir = temp_dir().join(format!("sleigh-lsp-panic-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let main = "define endian=little;\ndefine alignment=999999;\n";
+        fs::write(dir.join("main.slaspec"), main).unwrap();
+
+        let mut server = Server::default();
+        let uri = Url::from_file_path(dir.join("main.slaspec")).unwrap();
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri, "sleigh".to_string(), 0, main.to_string()),
+        };
+        server
+            .notification(Notification::new(
+                DidOpenTextDocument::METHOD.to_string(),
+                params,
+            ))
+            .unwrap();
+        let document = server.documents.remove(&dir.join("main.slaspec")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(document.diagnostics.len(), 1);
+        assert!(document.diagnostics[0].message.contains("TryFromIntError"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut server = Server::default();
+        let error = |server: &mut Server, method: &str| {
+            let request = Request::new(RequestId::from(1), method.to_string(), ());
+            server.request(request).error.map(|e| e.code)
+        };
+        assert_eq!(
+            error(&mut server, "textDocument/formatting"),
+            Some(ErrorCode::MethodNotFound as i32)
+        );
+        assert_eq!(
+            error(&mut server, HoverRequest::METHOD),
+            Some(ErrorCode::InvalidParams as i32)
+        );
+
+        let open = Notification::new(DidOpenTextDocument::METHOD.to_string(), ());
+        assert!(server.notification(open).is_none());
+    }
+}
@@ -0,0 +1,476 @@
+use crate::{Register, Spec};
+use roxmltree::{Document, Node};
+use std::{
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::Path,
+};
+
+#[derive(Debug)]
+pub enum CompilerSpecError {
+    Io(std::io::Error),
+    Xml(roxmltree::Error),
+    MissingElement(&'static str),
+    MissingAttribute {
+        tag: String,
+        attribute: &'static str,
+    },
+    InvalidAttribute {
+        tag: String,
+        attribute: &'static str,
+        value: String,
+    },
+    UnknownStorage(String),
+}
+
+impl Display for CompilerSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompilerSpecError::Io(e) => write!(f, "{}", e),
+            CompilerSpecError::Xml(e) => write!(f, "{}", e),
+            CompilerSpecError::MissingElement(tag) => write!(f, "missing <{}>", tag),
+            CompilerSpecError::MissingAttribute { tag, attribute } => {
+                write!(f, "<{}> is missing `{}`", tag, attribute)
+            }
+            CompilerSpecError::InvalidAttribute {
+                tag,
+                attribute,
+                value,
+            } => write!(f, "invalid `{}` of <{}>: {:?}", attribute, tag, value),
+            CompilerSpecError::UnknownStorage(tag) => write!(f, "unknown storage <{}>", tag),
+        }
+    }
+}
+
+impl std::error::Error for CompilerSpecError {}
+
+#[derive(PartialEq, Debug)]
+pub struct CompilerSpec {
+    pub stack_pointer: StackPointer,
+    pub return_address: Option<Storage>,
+    pub default_prototype: String,
+    pub prototypes: Vec<Prototype>,
+}
+
+impl CompilerSpec {
+    pub fn parse(s: &str) -> Result<Self, CompilerSpecError> {
+        let document = Document::parse(s).map_err(CompilerSpecError::Xml)?;
+        let root = document.root_element();
+        if !root.has_tag_name("compiler_spec") {
+            return Err(CompilerSpecError::MissingElement("compiler_spec"));
+        }
+
+        let stack_pointer = child(root, "stackpointer")
+            .ok_or(CompilerSpecError::MissingElement("stackpointer"))
+            .and_then(StackPointer::parse)?;
+        let return_address = child(root, "returnaddress")
+            .and_then(|node| node.children().find(Node::is_element))
+            .map(Storage::parse)
+            .transpose()?;
+
+        let mut default_prototype = None;
+        let mut prototypes = Vec::new();
+        for node in root.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "default_proto" => {
+                    let prototype = child(node, "prototype")
+                        .ok_or(CompilerSpecError::MissingElement("prototype"))
+                        .and_then(Prototype::parse)?;
+                    default_prototype = Some(prototype.name.clone());
+                    prototypes.push(prototype);
+                }
+                "prototype" => prototypes.push(Prototype::parse(node)?),
+                _ => {}
+            }
+        }
+
+        Ok(CompilerSpec {
+            stack_pointer,
+            return_address,
+            default_prototype: default_prototype
+                .ok_or(CompilerSpecError::MissingElement("default_proto"))?,
+            prototypes,
+        })
+    }
+
+    pub fn prototype(&self, name: &str) -> Option<&Prototype> {
+        self.prototypes.iter().find(|p| p.name == name)
+    }
+
+    pub fn default_prototype(&self) -> &Prototype {
+        self.prototype(&self.default_prototype).unwrap()
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct StackPointer {
+    pub register: String,
+    pub space: String,
+    pub growth: StackGrowth,
+}
+
+impl StackPointer {
+    fn parse(node: Node) -> Result<Self, CompilerSpecError> {
+        Ok(StackPointer {
+            register: attribute(node, "register")?.to_string(),
+            space: attribute(node, "space")?.to_string(),
+            growth: match node.attribute("growth") {
+                None | Some("negative") => StackGrowth::Negative,
+                Some("positive") => StackGrowth::Positive,
+                Some(growth) => return Err(invalid(node, "growth", growth)),
+            },
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StackGrowth {
+    Negative,
+    Positive,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Prototype {
+    pub name: String,
+    /// The number of bytes popped from the stack by a return, `None` if it
+    /// isn't known statically.
+    pub extrapop: Option<i64>,
+    pub stackshift: i64,
+    pub inputs: Vec<ParamEntry>,
+    pub outputs: Vec<ParamEntry>,
+    pub unaffected: Vec<Storage>,
+    pub killed_by_call: Vec<Storage>,
+    pub likely_trash: Vec<Storage>,
+}
+
+impl Prototype {
+    fn parse(node: Node) -> Result<Self, CompilerSpecError> {
+        let name = attribute(node, "name")?.to_string();
+        let extrapop = match node.attribute("extrapop") {
+            None | Some("unknown") => None,
+            Some(_) => Some(int_attribute(node, "extrapop")?),
+        };
+        let stackshift = match node.attribute("stackshift") {
+            None => 0,
+            Some(_) => int_attribute(node, "stackshift")?,
+        };
+
+        let entries = |tag| {
+            child(node, tag)
+                .into_iter()
+                .flat_map(|node| node.children().filter(|n| n.has_tag_name("pentry")))
+                .map(ParamEntry::parse)
+                .collect::<Result<_, _>>()
+        };
+        let storages = |tag| {
+            child(node, tag)
+                .into_iter()
+                .flat_map(|node| node.children().filter(Node::is_element))
+                .map(Storage::parse)
+                .collect::<Result<_, _>>()
+        };
+
+        Ok(Prototype {
+            name,
+            extrapop,
+            stackshift,
+            inputs: entries("input")?,
+            outputs: entries("output")?,
+            unaffected: storages("unaffected")?,
+            killed_by_call: storages("killedbycall")?,
+            likely_trash: storages("likelytrash")?,
+        })
+    }
+
+    pub fn input_registers(&self) -> impl Iterator<Item = &str> {
+        self.inputs.iter().filter_map(|p| p.storage.register())
+    }
+
+    pub fn output_registers(&self) -> impl Iterator<Item = &str> {
+        self.outputs.iter().filter_map(|p| p.storage.register())
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ParamEntry {
+    pub min_size: u32,
+    pub max_size: u32,
+    pub align: Option<u32>,
+    pub meta_type: MetaType,
+    pub storage: Storage,
+}
+
+impl ParamEntry {
+    fn parse(node: Node) -> Result<Self, CompilerSpecError> {
+        let meta_type = match node.attribute("metatype") {
+            None | Some("unknown") => MetaType::Default,
+            Some("float") => MetaType::Float,
+            Some("ptr") => MetaType::Pointer,
+            Some("int") => MetaType::Integer,
+            Some("uint") => MetaType::Unsigned,
+            Some("code") => MetaType::Code,
+            Some("struct") => MetaType::Struct,
+            Some(meta_type) => MetaType::Other(meta_type.to_string()),
+        };
+        let align = match node.attribute("align") {
+            None => None,
+            Some(_) => Some(int_attribute(node, "align")?),
+        };
+        let storage = node
+            .children()
+            .find(Node::is_element)
+            .ok_or(CompilerSpecError::MissingElement("register"))?;
+
+        Ok(ParamEntry {
+            min_size: int_attribute(node, "minsize")?,
+            max_size: int_attribute(node, "maxsize")?,
+            align,
+            meta_type,
+            storage: Storage::parse(storage)?,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum MetaType {
+    Default,
+    Float,
+    Pointer,
+    Integer,
+    Unsigned,
+    Code,
+    Struct,
+    /// A metatype this crate doesn't know, by its name in the cspec.
+    Other(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Storage {
+    Register(String),
+    Address {
+        space: String,
+        offset: i64,
+        size: Option<u32>,
+    },
+    Join(Vec<String>),
+}
+
+impl Storage {
+    fn parse(node: Node) -> Result<Self, CompilerSpecError> {
+        Ok(match node.tag_name().name() {
+            "register" => Storage::Register(attribute(node, "name")?.to_string()),
+            "addr" | "varnode" if node.attribute("space") == Some("join") => Storage::Join(
+                (1..)
+                    .map_while(|i| node.attribute(format!("piece{}", i).as_str()))
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            "addr" | "varnode" => Storage::Address {
+                space: attribute(node, "space")?.to_string(),
+                offset: match node.attribute("offset") {
+                    None => 0,
+                    Some(_) => int_attribute(node, "offset")?,
+                },
+                size: match node.attribute("size") {
+                    None => None,
+                    Some(_) => Some(int_attribute(node, "size")?),
+                },
+            },
+            tag => return Err(CompilerSpecError::UnknownStorage(tag.to_string())),
+        })
+    }
+
+    pub fn register(&self) -> Option<&str> {
+        if let Storage::Register(name) = self {
+            Some(name)
+        } else {
+            None
+        }
+    }
+}
+
+impl Spec {
+    /// Reads the compiler spec at `path` into `Spec::compiler_spec`.
+    pub fn load_compiler_spec(&mut self, path: impl AsRef<Path>) -> Result<(), CompilerSpecError> {
+        let raw = read_to_string(path).map_err(CompilerSpecError::Io)?;
+        self.compiler_spec = Some(CompilerSpec::parse(&raw)?);
+        Ok(())
+    }
+
+    pub fn register(&self, name: &str) -> Option<&Register> {
+        self.registers.iter().find(|r| r.name == name)
+    }
+
+    pub fn stack_pointer(&self) -> Option<&Register> {
+        let cspec = self.compiler_spec.as_ref()?;
+        self.register(&cspec.stack_pointer.register)
+    }
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, tag: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| n.has_tag_name(tag))
+}
+
+fn attribute<'a>(
+    node: Node<'a, '_>,
+    attribute: &'static str,
+) -> Result<&'a str, CompilerSpecError> {
+    node.attribute(attribute)
+        .ok_or_else(|| CompilerSpecError::MissingAttribute {
+            tag: node.tag_name().name().to_string(),
+            attribute,
+        })
+}
+
+fn invalid(node: Node, attribute: &'static str, value: &str) -> CompilerSpecError {
+    CompilerSpecError::InvalidAttribute {
+        tag: node.tag_name().name().to_string(),
+        attribute,
+        value: value.to_string(),
+    }
+}
+
+fn int_attribute<I: std::convert::TryFrom<i128>>(
+    node: Node,
+    name: &'static str,
+) -> Result<I, CompilerSpecError> {
+    let value = attribute(node, name)?;
+    parse_int(value).ok_or_else(|| invalid(node, name, value))
+}
+
+fn parse_int<I: std::convert::TryFrom<i128>>(s: &str) -> Option<I> {
+    let (s, factor) = if let Some(s) = s.strip_prefix('-') {
+        (s, -1)
+    } else {
+        (s, 1)
+    };
+    let value = if let Some(hex) = s.strip_prefix("0x") {
+        i128::from_str_radix(hex, 16).ok()?
+    } else {
+        s.parse().ok()?
+    };
+    I::try_from(value * factor).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompilerSpec, CompilerSpecError, MetaType, StackGrowth, Storage};
+
+    #[test]
+    fn test_parse() {
+        let cspec = CompilerSpec::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<compiler_spec>
+  <global>
+    <range space="ram"/>
+  </global>
+  <stackpointer register="RSP" space="ram"/>
+  <returnaddress>
+    <varnode space="stack" offset="0" size="8"/>
+  </returnaddress>
+  <default_proto>
+    <prototype name="__fastcall" extrapop="8" stackshift="8">
+      <input>
+        <pentry minsize="4" maxsize="8" metatype="float">
+          <register name="XMM0_Qa"/>
+        </pentry>
+        <pentry minsize="1" maxsize="8">
+          <register name="RCX"/>
+        </pentry>
+        <pentry minsize="1" maxsize="500" align="8">
+          <addr offset="40" space="stack"/>
+        </pentry>
+      </input>
+      <output>
+        <pentry minsize="1" maxsize="8">
+          <register name="RAX"/>
+        </pentry>
+      </output>
+      <unaffected>
+        <register name="RBX"/>
+      </unaffected>
+      <killedbycall>
+        <register name="RAX"/>
+      </killedbycall>
+    </prototype>
+  </default_proto>
+  <prototype name="syscall" extrapop="unknown" stackshift="8">
+    <input>
+      <pentry minsize="1" maxsize="8" metatype="int">
+        <register name="RDI"/>
+      </pentry>
+      <pentry minsize="1" maxsize="8" metatype="array">
+        <register name="RSI"/>
+      </pentry>
+    </input>
+  </prototype>
+</compiler_spec>"#,
+        )
+        .unwrap();
+
+        assert_eq!(cspec.stack_pointer.register, "RSP");
+        assert_eq!(cspec.stack_pointer.growth, StackGrowth::Negative);
+        assert_eq!(
+            cspec.return_address,
+            Some(Storage::Address {
+                space: "stack".to_string(),
+                offset: 0,
+                size: Some(8),
+            })
+        );
+
+        let proto = cspec.default_prototype();
+        assert_eq!(proto.name, "__fastcall");
+        assert_eq!(proto.extrapop, Some(8));
+        assert_eq!(proto.inputs[0].meta_type, MetaType::Float);
+        assert_eq!(proto.inputs[2].align, Some(8));
+        assert_eq!(
+            proto.input_registers().collect::<Vec<_>>(),
+            ["XMM0_Qa", "RCX"]
+        );
+        assert_eq!(proto.output_registers().collect::<Vec<_>>(), ["RAX"]);
+        assert_eq!(proto.unaffected, [Storage::Register("RBX".to_string())]);
+
+        let syscall = cspec.prototype("syscall").unwrap();
+        assert_eq!(syscall.extrapop, None);
+        assert_eq!(syscall.inputs[0].meta_type, MetaType::Integer);
+        assert_eq!(
+            syscall.inputs[1].meta_type,
+            MetaType::Other("array".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |s: &str| CompilerSpec::parse(s).unwrap_err().to_string();
+        assert_eq!(
+            error(
+                r#"<compiler_spec><stackpointer register="SP" space="ram" growth="up"/></compiler_spec>"#
+            ),
+            r#"invalid `growth` of <stackpointer>: "up""#
+        );
+        assert_eq!(
+            error(r#"<compiler_spec><stackpointer space="ram"/></compiler_spec>"#),
+            "<stackpointer> is missing `register`"
+        );
+        assert_eq!(
+            error(r#"<compiler_spec><stackpointer register="SP" space="ram"/></compiler_spec>"#),
+            "missing <default_proto>"
+        );
+        assert!(matches!(
+            CompilerSpec::parse(
+                r#"<compiler_spec>
+  <stackpointer register="SP" space="ram"/>
+  <default_proto>
+    <prototype name="std" extrapop="x"/>
+  </default_proto>
+</compiler_spec>"#
+            ),
+            Err(CompilerSpecError::InvalidAttribute { .. })
+        ));
+        assert!(matches!(
+            CompilerSpec::parse("<compiler_spec></prototype>"),
+            Err(CompilerSpecError::Xml(_))
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::{
+    assembler::{is_word, MAX_DEPTH},
+    pcode::Lifter,
+    Calculation, Constructor, FieldDisplay, FieldMeaning, LValue, PCode, RValue, Spec, State,
+    Visit,
+};
+use std::{collections::HashMap, convert::TryFrom};
+
+impl Spec {
+    pub fn disassemble(&self, code: &[u8]) -> Option<Instruction> {
+        Disassembler::new(self).disassemble(code)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instruction {
+    /// The number of bytes the instruction takes up.
+    pub len: usize,
+    pub text: String,
+    pub pcode: Vec<PCode>,
+}
+
+pub struct Disassembler<'s> {
+    spec: &'s Spec,
+    address: u64,
+    context: Vec<(String, i128)>,
+}
+
+impl<'s> Disassembler<'s> {
+    pub fn new(spec: &'s Spec) -> Self {
+        Disassembler {
+            spec,
+            address: 0,
+            context: Vec::new(),
+        }
+    }
+
+    /// Sets the address of the instruction, the value of `inst_start`.
+    pub fn set_address(&mut self, address: u64) {
+        self.address = address;
+    }
+
+    pub fn set_context(&mut self, name: &str, value: i128) {
+        self.context.push((name.to_string(), value));
+    }
+
+    /// Decodes the instruction at the start of `code` into its display text
+    /// and p-code. Returns `None` if no constructor matches or its semantics
+    /// can't be lifted.
+    pub fn disassemble(&self, code: &'s [u8]) -> Option<Instruction> {
+        let mut state = State::new(self.spec, code);
+        for (name, value) in self.context.iter() {
+            state.set_context(name, *value);
+        }
+        let constructor = state.match_constructor(None)?;
+        let len = constructor
+            .constraint
+            .len(state.clone())
+            .unwrap_or_default();
+
+        let walker = Walker {
+            spec: self.spec,
+            inst_start: self.address as i128,
+            inst_next: self.address as i128 + len as i128,
+        };
+        let root = walker.node(constructor, state, 0)?;
+        let text = walker.display(&root);
+        let pcode = Lifter::new(self.spec, self.address, len).lift(&root)?;
+        Some(Instruction { len, text, pcode })
+    }
+}
+
+/// A matched constructor together with the constructors matched for its
+/// subtable operands.
+pub(crate) struct Node<'s> {
+    pub(crate) constructor: &'s Constructor,
+    /// The state with `code` starting where the constructor matched.
+    pub(crate) state: State<'s>,
+    /// Operands computed in the disassembly action section.
+    pub(crate) calculated: HashMap<String, i128>,
+    /// Subtable operands in the order they appear in the constraint.
+    pub(crate) tables: Vec<(String, Node<'s>)>,
+}
+
+impl<'s> Node<'s> {
+    pub(crate) fn table(&self, name: &str) -> Option<&Node<'s>> {
+        self.tables
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, node)| node)
+    }
+}
+
+/// The meaning and display format of a token or context field.
+pub(crate) fn field_info<'s>(
+    spec: &'s Spec,
+    name: &str,
+) -> Option<(&'s FieldMeaning, &'s FieldDisplay)> {
+    let token_field = spec
+        .tokens
+        .iter()
+        .flat_map(|t| t.fields.iter())
+        .find(|f| f.name == name)
+        .map(|f| (&f.meaning, &f.display));
+    token_field.or_else(|| {
+        spec.contexts
+            .iter()
+            .flat_map(|c| c.fields.iter())
+            .find(|f| f.name == name)
+            .map(|f| (&f.meaning, &f.display))
+    })
+}
+
+struct Walker<'s> {
+    spec: &'s Spec,
+    inst_start: i128,
+    inst_next: i128,
+}
+
+impl<'s> Walker<'s> {
+    fn is_table(&self, name: &str) -> bool {
+        name != "instruction"
+            && self
+                .spec
+                .constructors
+                .iter()
+                .any(|c| c.header.table == name)
+    }
+
+    fn node(
+        &self,
+        constructor: &'s Constructor,
+        state: State<'s>,
+        depth: usize,
+    ) -> Option<Node<'s>> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+
+        struct Idents(Vec<String>);
+        impl Visit for Idents {
+            fn visit_ident(&mut self, ident: &str) {
+                if !self.0.iter().any(|i| i == ident) {
+                    self.0.push(ident.to_string());
+                }
+            }
+        }
+        let mut idents = Idents(Vec::new());
+        idents.visit_constraint(&constructor.constraint);
+
+        let mut tables = Vec::new();
+        for name in idents.0 {
+            if !self.is_table(&name) {
+                continue;
+            }
+            let mut sub = state.clone();
+            if let Some(offset) = constructor.constraint.field_offset(&name, state.clone()) {
+                sub.code = state.code.get(offset..)?;
+            }
+            let matched = sub.match_constructor(Some(&name))?;
+            let node = self.node(matched, sub, depth + 1)?;
+            tables.push((name, node));
+        }
+
+        let mut node = Node {
+            constructor,
+            state,
+            calculated: HashMap::new(),
+            tables,
+        };
+        for calculation in constructor.calculations.iter() {
+            if let Calculation::Assignment(assignment) = calculation {
+                if let Some(value) = self.eval(&node, &assignment.rhs) {
+                    node.calculated.insert(assignment.lhs.clone(), value);
+                }
+            }
+        }
+        Some(node)
+    }
+
+    fn value(&self, node: &Node, name: &str) -> Option<i128> {
+        match name {
+            "inst_start" => Some(self.inst_start),
+            "inst_next" => Some(self.inst_next),
+            _ => node
+                .calculated
+                .get(name)
+                .copied()
+                .or_else(|| node.state.operand_value(node.constructor, name)),
+        }
+    }
+
+    /// Evaluates an expression of the disassembly action section.
+    fn eval(&self, node: &Node, rvalue: &RValue) -> Option<i128> {
+        let binary = |lhs, rhs, f: fn(i128, i128) -> Option<i128>| {
+            f(self.eval(node, lhs)?, self.eval(node, rhs)?)
+        };
+        match rvalue {
+            RValue::Add(inner) => binary(&inner.lhs, &inner.rhs, i128::checked_add),
+            RValue::Sub(inner) => binary(&inner.lhs, &inner.rhs, i128::checked_sub),
+            RValue::Mult(inner) => binary(&inner.lhs, &inner.rhs, i128::checked_mul),
+            RValue::Div(inner) => binary(&inner.lhs, &inner.rhs, i128::checked_div),
+            RValue::Rem(inner) => binary(&inner.lhs, &inner.rhs, i128::checked_rem),
+            RValue::IntOr(inner) => binary(&inner.lhs, &inner.rhs, |l, r| Some(l | r)),
+            RValue::IntAnd(inner) => binary(&inner.lhs, &inner.rhs, |l, r| Some(l & r)),
+            RValue::IntXor(inner) => binary(&inner.lhs, &inner.rhs, |l, r| Some(l ^ r)),
+            RValue::LShift(inner) => binary(&inner.lhs, &inner.rhs, |l, r| {
+                l.checked_shl(u32::try_from(r).ok()?)
+            }),
+            RValue::RShift(inner) => binary(&inner.lhs, &inner.rhs, |l, r| {
+                l.checked_shr(u32::try_from(r).ok()?)
+            }),
+            RValue::Not(inner) => Some(!self.eval(node, &inner.op)?),
+            RValue::Neg(inner) => self.eval(node, &inner.op)?.checked_neg(),
+            RValue::Parenthesized(inner) => self.eval(node, &inner.op),
+            RValue::Constant(constant) => Some(constant.value),
+            RValue::LValue(LValue::Ident(ident)) => self.value(node, &ident.field),
+            _ => None,
+        }
+    }
+
+    /// Renders the display section, collapsing whitespace like Ghidra does.
+    fn display(&self, node: &Node) -> String {
+        let mnemonic = node.constructor.header.mnemonic.as_str();
+        let mut text = String::new();
+        let mut chars = mnemonic.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c == '"' {
+                text.extend(chars.by_ref().map(|(_, c)| c).take_while(|c| *c != '"'));
+            } else if c == '^' {
+                // joins adjacent pieces without a space
+            } else if c.is_whitespace() {
+                if !text.is_empty() && !text.ends_with(' ') {
+                    text.push(' ');
+                }
+            } else if is_word(c) {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| is_word(*c)) {
+                    end = i + c.len_utf8();
+                }
+                let word = &mnemonic[start..end];
+                match self.operand(node, word) {
+                    Some(operand) => text += &operand,
+                    None => text += word,
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        text.trim_end().to_string()
+    }
+
+    fn operand(&self, node: &Node, name: &str) -> Option<String> {
+        if let Some(table) = node.table(name) {
+            return Some(self.display(table));
+        }
+        if let Some(value) = node.calculated.get(name) {
+            return Some(format_value(*value, &FieldDisplay::Default));
+        }
+        if name == "inst_start" || name == "inst_next" {
+            return Some(format_value(
+                self.value(node, name)?,
+                &FieldDisplay::Default,
+            ));
+        }
+
+        let (meaning, display) = field_info(self.spec, name)?;
+        let value = node.state.operand_value(node.constructor, name)?;
+        let index = usize::try_from(value).ok();
+        match meaning {
+            FieldMeaning::Variables(names) | FieldMeaning::Names(names) => {
+                index.and_then(|i| names.get(i)).cloned()
+            }
+            FieldMeaning::Values(values) => index
+                .and_then(|i| *values.get(i)?)
+                .map(|v| format_value(v as i128, display)),
+            FieldMeaning::Default => Some(format_value(value, display)),
+        }
+    }
+}
+
+fn format_value(value: i128, display: &FieldDisplay) -> String {
+    match display {
+        FieldDisplay::Decimal => value.to_string(),
+        FieldDisplay::Default | FieldDisplay::Hex if value < 0 => format!("-0x{:x}", -value),
+        FieldDisplay::Default | FieldDisplay::Hex => format!("0x{:x}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Spec;
+
+    #[test]
+    fn test_unliftable() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 ];
+define token instr(8)
+    op = (0,7)
+;
+
+:cond is op=1 { if (r0) r1 = 1; }
+:undefined is op=2 { r0 = missing; }
+:indirect is op=3 { if (r0) goto [r1]; }
+:ok is op=4 { r0 = 1; }
+"#,
+        );
+
+        for op in 1..4 {
+            assert_eq!(spec.disassemble(&[op]), None);
+        }
+        let instruction = spec.disassemble(&[4]).unwrap();
+        assert_eq!(instruction.text, "ok");
+        assert_eq!(instruction.pcode.len(), 1);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::{
+    assembler::MAX_DEPTH, BitPattern, ComparisonOperator, Constraint, ConstraintComparison,
+    ConstraintRValue, Constructor, Spec, State,
+};
+use std::{collections::BTreeMap, ops::Range};
+
+/// Encodings are drawn until one decodes to the requested constructor, a
+/// more specific constructor can shadow some or all of them.
+const MAX_ATTEMPTS: usize = 64;
+
+impl Spec {
+    /// Generates a random encoding of the constructor at `index` into
+    /// `Spec::constructors`.
+    pub fn generate(&self, index: usize, seed: u64) -> Option<Encoding> {
+        Generator::new(self, seed).constructor(index)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Encoding {
+    pub bytes: Vec<u8>,
+    /// The context fields the encoding has to be decoded with.
+    pub context: Vec<(String, i128)>,
+}
+
+pub struct Generator<'s> {
+    spec: &'s Spec,
+    rng: Rng,
+    context: BTreeMap<String, i128>,
+}
+
+impl<'s> Generator<'s> {
+    pub fn new(spec: &'s Spec, seed: u64) -> Self {
+        Generator {
+            spec,
+            rng: Rng::new(seed),
+            context: BTreeMap::new(),
+        }
+    }
+
+    /// Fixes a context field, only constructors matching it are generated.
+    pub fn set_context(&mut self, name: &str, value: i128) {
+        self.context.insert(name.to_string(), value);
+    }
+
+    /// Generates a random encoding of a random constructor of `table`.
+    pub fn table(&mut self, table: &str) -> Option<Encoding> {
+        let constructors: Vec<_> = (0..self.spec.constructors.len())
+            .filter(|i| self.spec.constructors[*i].header.table == table)
+            .collect();
+        for _ in 0..MAX_ATTEMPTS {
+            let index = constructors[self.rng.below(constructors.len() as u128)? as usize];
+            if let Some(encoding) = self.attempt(index) {
+                return Some(encoding);
+            }
+        }
+        None
+    }
+
+    /// Generates a random encoding that decodes to the constructor at
+    /// `index` into `Spec::constructors`. Bits that aren't fixed by the
+    /// constraints are random.
+    pub fn constructor(&mut self, index: usize) -> Option<Encoding> {
+        (0..MAX_ATTEMPTS).find_map(|_| self.attempt(index))
+    }
+
+    fn attempt(&mut self, index: usize) -> Option<Encoding> {
+        let constructor = &self.spec.constructors[index];
+        let mut context = self.context.clone();
+        let pattern = self.constraint(&constructor.constraint, &mut context, 0)?;
+        let bytes: Vec<u8> = pattern
+            .mask
+            .iter()
+            .zip(pattern.value.iter())
+            .map(|(mask, value)| self.rng.next() as u8 & !mask | value)
+            .collect();
+
+        let mut state = State::new(self.spec, &bytes);
+        for (name, value) in context.iter() {
+            state.set_context(name, *value);
+        }
+        let table = constructor.header.table.as_str();
+        let matched = state.match_constructor(Some(table))?;
+        if !std::ptr::eq(matched, constructor)
+            || constructor.constraint.len(state) != Some(bytes.len())
+        {
+            return None;
+        }
+        Some(Encoding {
+            bytes,
+            context: context.into_iter().collect(),
+        })
+    }
+
+    /// Draws one pattern satisfying `constraint`, context fields it
+    /// requires are added to `context`.
+    fn constraint(
+        &mut self,
+        constraint: &Constraint,
+        context: &mut BTreeMap<String, i128>,
+        depth: usize,
+    ) -> Option<BitPattern> {
+        match constraint {
+            Constraint::Ellipsis(inner) => self.constraint(&inner.op, context, depth),
+            Constraint::And(inner) => {
+                let lhs = self.constraint(&inner.lhs, context, depth)?;
+                let rhs = self.constraint(&inner.rhs, context, depth)?;
+                lhs.merge(&rhs, 0)
+            }
+            Constraint::Or(inner) => {
+                if self.rng.below(2)? == 0 {
+                    self.constraint(&inner.lhs, context, depth)
+                } else {
+                    self.constraint(&inner.rhs, context, depth)
+                }
+            }
+            Constraint::Semi(inner) => {
+                let lhs = self.constraint(&inner.lhs, context, depth)?;
+                let rhs = self.constraint(&inner.rhs, context, depth)?;
+                lhs.merge(&rhs, lhs.len())
+            }
+            Constraint::Parenthesized(inner) => self.constraint(inner, context, depth),
+            Constraint::Comparison(inner) => self.comparison(inner, context),
+            Constraint::Exists(inner) => self.exists(&inner.name, context, depth),
+            Constraint::Constructor(inner) => self.exists(&inner.name, context, depth),
+        }
+    }
+
+    fn exists(
+        &mut self,
+        name: &str,
+        context: &mut BTreeMap<String, i128>,
+        depth: usize,
+    ) -> Option<BitPattern> {
+        let mut pattern = BitPattern::default();
+        if let Some(token) = self
+            .spec
+            .tokens
+            .iter()
+            .find(|t| t.fields.iter().any(|f| f.name == name))
+        {
+            pattern.extend(token.size as usize / 8);
+            return Some(pattern);
+        }
+
+        let constructors: Vec<_> = self
+            .spec
+            .constructors
+            .iter()
+            .filter(|c| name != "instruction" && c.header.table == name)
+            .collect();
+        if constructors.is_empty() || depth > MAX_DEPTH {
+            // context fields and `epsilon` don't take up any bits
+            return (depth <= MAX_DEPTH).then_some(pattern);
+        }
+        let constructor: &Constructor =
+            constructors[self.rng.below(constructors.len() as u128)? as usize];
+        self.constraint(&constructor.constraint, context, depth + 1)
+    }
+
+    fn comparison(
+        &mut self,
+        comparison: &ConstraintComparison,
+        context: &mut BTreeMap<String, i128>,
+    ) -> Option<BitPattern> {
+        let name = match &comparison.lhs {
+            ConstraintRValue::Field(name) => name,
+            _ => return None,
+        };
+        let op = comparison.comparison;
+        let rhs = comparison.rhs.evaluate(&mut |_| None);
+
+        let mut pattern = BitPattern::default();
+        if let Some((token, field)) = self.spec.tokens.iter().find_map(|token| {
+            let field = token.fields.iter().find(|f| &f.name == name)?;
+            Some((token, field))
+        }) {
+            // comparisons against other fields are left to the check of
+            // the finished encoding
+            let value = match rhs {
+                Some(rhs) => self.value(&field.range, field.signed, op, rhs)?,
+                None => self.rng.below(1 << width(&field.range))? as i128,
+            };
+            let len = token.size as usize / 8;
+            pattern.extend(len);
+            let endianness = self.spec.token_endianness(token);
+            pattern.set_field(0, len, &field.range, value, endianness)?;
+            return Some(pattern);
+        }
+
+        let field = self
+            .spec
+            .contexts
+            .iter()
+            .flat_map(|c| c.fields.iter())
+            .find(|f| &f.name == name)?;
+        let rhs = rhs?;
+        match context.get(name) {
+            Some(value) if op.compare(*value, rhs) => Some(pattern),
+            Some(_) => None,
+            None => {
+                let value = self.value(&field.range, field.signed, op, rhs)?;
+                context.insert(name.clone(), field.decode(value));
+                Some(pattern)
+            }
+        }
+    }
+
+    /// Draws the raw bits of a field value satisfying `op` with `rhs`.
+    fn value(
+        &mut self,
+        range: &Range<u16>,
+        signed: bool,
+        op: ComparisonOperator,
+        rhs: i128,
+    ) -> Option<i128> {
+        let bits = width(range);
+        let (min, max) = if signed {
+            (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            (0, (1 << bits) - 1)
+        };
+        let (low, high) = match op {
+            ComparisonOperator::Equal => (rhs, rhs),
+            ComparisonOperator::NotEqual => (min, max),
+            ComparisonOperator::Less => (min, rhs - 1),
+            ComparisonOperator::LessEqual => (min, rhs),
+            ComparisonOperator::Greater => (rhs + 1, max),
+            ComparisonOperator::GreaterEqual => (rhs, max),
+        };
+        let (low, high) = (low.max(min), high.min(max));
+        if low > high {
+            return None;
+        }
+        let mut value = low + self.rng.below((high - low + 1) as u128)? as i128;
+        if !op.compare(value, rhs) {
+            value = if value < max { value + 1 } else { value - 1 };
+        }
+        (op.compare(value, rhs) && value >= min).then(|| value & ((1 << bits) - 1))
+    }
+}
+
+fn width(range: &Range<u16>) -> u32 {
+    (range.end - range.start + 1) as u32
+}
+
+/// A xorshift generator, good enough to pick bits and keeps the crate free
+/// of dependencies for it.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must never be zero
+        Rng((seed ^ 0x9e37_79b9_7f4a_7c15).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`, `None` if the range is empty.
+    fn below(&mut self, n: u128) -> Option<u128> {
+        if n == 0 {
+            return None;
+        }
+        let value = (self.next() as u128) << 64 | self.next() as u128;
+        Some(value % n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Generator;
+    use crate::{Spec, State};
+
+    #[test]
+    fn test_generate() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=2 default;
+define register offset=0 size=1 [ r0 r1 r2 r3 ];
+define register offset=8 size=1 [ ctx ];
+define token instr(8)
+    op = (4,7)
+    sub = (2,3)
+    reg = (0,1)
+;
+define token imm8(8)
+    simm = (0,7) signed
+;
+define context ctx
+    mode = (0,0)
+;
+attach variables reg [ r0 r1 r2 r3 ];
+
+src: reg is sub=0 & reg { export reg; }
+src: simm is sub=1; simm<0 { export *[const]:1 simm; }
+
+:add src is mode=0 & op=1 & src { }
+:sub src is mode=1 & op=1 & src { }
+:inc reg is op=1 & sub=2 & reg { }
+:nop is op>7 { }
+"#,
+        );
+
+        for (index, constructor) in spec.constructors.iter().enumerate() {
+            for seed in 0..32 {
+                let encoding = spec.generate(index, seed).unwrap();
+                let mut state = State::new(&spec, &encoding.bytes);
+                for (name, value) in encoding.context.iter() {
+                    state.set_context(name, *value);
+                }
+                let table = constructor.header.table.as_str();
+                let matched = state.match_constructor(Some(table)).unwrap();
+                assert!(std::ptr::eq(matched, constructor));
+            }
+        }
+
+        let sub = spec.generate(3, 0).unwrap();
+        assert_eq!(sub.context, vec![("mode".to_string(), 1)]);
+
+        let mut generator = Generator::new(&spec, 0);
+        generator.set_context("mode", 0);
+        assert_eq!(generator.constructor(3), None);
+        for _ in 0..32 {
+            let encoding = generator.table("instruction").unwrap();
+            assert_eq!(encoding.context, vec![("mode".to_string(), 0)]);
+        }
+    }
+}
@@ -1,7 +1,19 @@
+mod assembler;
+mod cspec;
+mod disassembler;
+mod generator;
+mod pcode;
 mod preprocessor;
 mod spec;
 mod state;
 
-pub use preprocessor::preprocess;
+pub use assembler::*;
+pub use cspec::*;
+pub use disassembler::*;
+pub use generator::*;
+pub use pcode::*;
+pub use preprocessor::{
+    preprocess, preprocess_with_reader, preprocess_with_source_map, Location, SourceMap,
+};
 pub use spec::*;
 pub use state::*;
@@ -0,0 +1,1196 @@
+use crate::{
+    disassembler::{field_info, Node},
+    Action, ActionGoto, ComparisonOperator, FieldMeaning, LValue, LValueSlice, NumTypePrefix,
+    RValue, RValueCall, SpaceType, Spec,
+};
+use std::{collections::HashMap, convert::TryFrom, fmt};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Varnode {
+    pub space: String,
+    pub offset: u64,
+    pub size: u8,
+}
+
+impl fmt::Display for Varnode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {:#x}, {})", self.space, self.offset, self.size)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OpCode {
+    Copy,
+    Load,
+    Store,
+    Branch,
+    CBranch,
+    BranchInd,
+    Call,
+    CallInd,
+    CallOther,
+    Return,
+    IntEqual,
+    IntNotEqual,
+    IntSLess,
+    IntSLessEqual,
+    IntLess,
+    IntLessEqual,
+    IntZExt,
+    IntSExt,
+    IntAdd,
+    IntSub,
+    IntCarry,
+    IntSCarry,
+    IntSBorrow,
+    Int2Comp,
+    IntNegate,
+    IntXor,
+    IntAnd,
+    IntOr,
+    IntLeft,
+    IntRight,
+    IntSRight,
+    IntMult,
+    IntDiv,
+    IntSDiv,
+    IntRem,
+    IntSRem,
+    BoolNegate,
+    BoolXor,
+    BoolAnd,
+    BoolOr,
+    FloatEqual,
+    FloatNotEqual,
+    FloatLess,
+    FloatLessEqual,
+    FloatNan,
+    FloatAdd,
+    FloatDiv,
+    FloatMult,
+    FloatSub,
+    FloatAbs,
+    FloatSqrt,
+    Int2Float,
+    Float2Float,
+    Trunc,
+    FloatCeil,
+    FloatFloor,
+    FloatRound,
+    SubPiece,
+    PopCount,
+}
+
+impl OpCode {
+    /// The name Ghidra uses for the operation.
+    pub fn name(self) -> &'static str {
+        match self {
+            OpCode::Copy => "COPY",
+            OpCode::Load => "LOAD",
+            OpCode::Store => "STORE",
+            OpCode::Branch => "BRANCH",
+            OpCode::CBranch => "CBRANCH",
+            OpCode::BranchInd => "BRANCHIND",
+            OpCode::Call => "CALL",
+            OpCode::CallInd => "CALLIND",
+            OpCode::CallOther => "CALLOTHER",
+            OpCode::Return => "RETURN",
+            OpCode::IntEqual => "INT_EQUAL",
+            OpCode::IntNotEqual => "INT_NOTEQUAL",
+            OpCode::IntSLess => "INT_SLESS",
+            OpCode::IntSLessEqual => "INT_SLESSEQUAL",
+            OpCode::IntLess => "INT_LESS",
+            OpCode::IntLessEqual => "INT_LESSEQUAL",
+            OpCode::IntZExt => "INT_ZEXT",
+            OpCode::IntSExt => "INT_SEXT",
+            OpCode::IntAdd => "INT_ADD",
+            OpCode::IntSub => "INT_SUB",
+            OpCode::IntCarry => "INT_CARRY",
+            OpCode::IntSCarry => "INT_SCARRY",
+            OpCode::IntSBorrow => "INT_SBORROW",
+            OpCode::Int2Comp => "INT_2COMP",
+            OpCode::IntNegate => "INT_NEGATE",
+            OpCode::IntXor => "INT_XOR",
+            OpCode::IntAnd => "INT_AND",
+            OpCode::IntOr => "INT_OR",
+            OpCode::IntLeft => "INT_LEFT",
+            OpCode::IntRight => "INT_RIGHT",
+            OpCode::IntSRight => "INT_SRIGHT",
+            OpCode::IntMult => "INT_MULT",
+            OpCode::IntDiv => "INT_DIV",
+            OpCode::IntSDiv => "INT_SDIV",
+            OpCode::IntRem => "INT_REM",
+            OpCode::IntSRem => "INT_SREM",
+            OpCode::BoolNegate => "BOOL_NEGATE",
+            OpCode::BoolXor => "BOOL_XOR",
+            OpCode::BoolAnd => "BOOL_AND",
+            OpCode::BoolOr => "BOOL_OR",
+            OpCode::FloatEqual => "FLOAT_EQUAL",
+            OpCode::FloatNotEqual => "FLOAT_NOTEQUAL",
+            OpCode::FloatLess => "FLOAT_LESS",
+            OpCode::FloatLessEqual => "FLOAT_LESSEQUAL",
+            OpCode::FloatNan => "FLOAT_NAN",
+            OpCode::FloatAdd => "FLOAT_ADD",
+            OpCode::FloatDiv => "FLOAT_DIV",
+            OpCode::FloatMult => "FLOAT_MULT",
+            OpCode::FloatSub => "FLOAT_SUB",
+            OpCode::FloatAbs => "FLOAT_ABS",
+            OpCode::FloatSqrt => "FLOAT_SQRT",
+            OpCode::Int2Float => "INT2FLOAT",
+            OpCode::Float2Float => "FLOAT2FLOAT",
+            OpCode::Trunc => "TRUNC",
+            OpCode::FloatCeil => "FLOAT_CEIL",
+            OpCode::FloatFloor => "FLOAT_FLOOR",
+            OpCode::FloatRound => "FLOAT_ROUND",
+            OpCode::SubPiece => "SUBPIECE",
+            OpCode::PopCount => "POPCOUNT",
+        }
+    }
+}
+
+/// A single p-code operation. `LOAD` and `STORE` take the index of their
+/// space in `Spec::spaces` as a constant first input, `CALLOTHER` the index
+/// of the user-defined op in `Spec::pcodeops`. Branches to labels take the
+/// number of operations to skip as a constant.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PCode {
+    pub opcode: OpCode,
+    pub output: Option<Varnode>,
+    pub inputs: Vec<Varnode>,
+}
+
+impl fmt::Display for PCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(output) = &self.output {
+            write!(f, "{} = ", output)?;
+        }
+        write!(f, "{}", self.opcode.name())?;
+        for (i, input) in self.inputs.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, input)?;
+        }
+        Ok(())
+    }
+}
+
+fn mask(size: u8) -> u64 {
+    if size >= 8 {
+        u64::MAX
+    } else {
+        (1 << (size as u32 * 8)) - 1
+    }
+}
+
+fn constant(value: i128, size: u8) -> Varnode {
+    Varnode {
+        space: "const".to_string(),
+        offset: value as u64 & mask(size),
+        size,
+    }
+}
+
+/// What an operand of a constructor refers to.
+#[derive(Clone)]
+enum Handle {
+    Varnode(Varnode),
+    /// `size` bytes at a dynamic address, `space` indexes `Spec::spaces`.
+    Pointer {
+        space: usize,
+        pointer: Varnode,
+        size: u8,
+    },
+}
+
+impl Handle {
+    fn size(&self) -> u8 {
+        match self {
+            Handle::Varnode(varnode) => varnode.size,
+            Handle::Pointer { size, .. } => *size,
+        }
+    }
+}
+
+enum Value {
+    Handle(Handle),
+    /// Fields and calculated operands, their size depends on where they are
+    /// used.
+    Const(i128),
+}
+
+struct Frame<'n, 's> {
+    node: &'n Node<'s>,
+    locals: HashMap<String, Varnode>,
+    /// The exports of subtables that have been built.
+    operands: HashMap<String, Option<Handle>>,
+    labels: HashMap<String, usize>,
+    /// Branches to labels that are resolved once all actions are lifted.
+    fixups: Vec<(usize, String)>,
+    export: Option<Handle>,
+}
+
+/// Translates the semantic sections of a matched constructor and its
+/// subtables into p-code.
+pub(crate) struct Lifter<'s> {
+    spec: &'s Spec,
+    inst_start: u64,
+    inst_next: u64,
+    ops: Vec<PCode>,
+    unique: u64,
+}
+
+impl<'s> Lifter<'s> {
+    pub(crate) fn new(spec: &'s Spec, address: u64, len: usize) -> Self {
+        Lifter {
+            spec,
+            inst_start: address,
+            inst_next: address + len as u64,
+            ops: Vec::new(),
+            unique: 0,
+        }
+    }
+
+    /// Returns `None` if the constructors use something that can't be
+    /// lifted, like undefined symbols or conditional actions other than
+    /// `goto`.
+    pub(crate) fn lift(mut self, root: &Node<'s>) -> Option<Vec<PCode>> {
+        self.constructor(root)?;
+        Some(self.ops)
+    }
+
+    /// Lifts `node`, returning its export.
+    fn constructor(&mut self, node: &Node<'s>) -> Option<Option<Handle>> {
+        let mut frame = Frame {
+            node,
+            locals: HashMap::new(),
+            operands: HashMap::new(),
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+            export: None,
+        };
+
+        // operands without a build directive are built before the
+        // constructor's own p-code
+        for (name, _) in node.tables.iter() {
+            let built = node
+                .constructor
+                .actions
+                .iter()
+                .any(|a| matches!(a, Action::Build(build) if build.field == *name));
+            if !built {
+                self.build(&mut frame, name)?;
+            }
+        }
+        for action in node.constructor.actions.iter() {
+            self.action(&mut frame, action)?;
+        }
+
+        for (index, label) in frame.fixups {
+            let target = *frame.labels.get(&label)?;
+            let skip = target as i128 - index as i128;
+            self.ops[index].inputs[0] = constant(skip, 4);
+        }
+        Some(frame.export)
+    }
+
+    fn build(&mut self, frame: &mut Frame<'_, 's>, name: &str) -> Option<()> {
+        if frame.operands.contains_key(name) {
+            return Some(());
+        }
+        let node = frame.node;
+        if let Some(table) = node.table(name) {
+            let export = self.constructor(table)?;
+            frame.operands.insert(name.to_string(), export);
+        }
+        Some(())
+    }
+
+    fn emit(&mut self, opcode: OpCode, output: Option<Varnode>, inputs: Vec<Varnode>) {
+        self.ops.push(PCode {
+            opcode,
+            output,
+            inputs,
+        });
+    }
+
+    /// Emits an operation writing to `out`, or to a new temporary.
+    fn op(
+        &mut self,
+        opcode: OpCode,
+        size: u8,
+        out: Option<Varnode>,
+        inputs: Vec<Varnode>,
+    ) -> Varnode {
+        let out = out.unwrap_or_else(|| self.fresh(size));
+        self.emit(opcode, Some(out.clone()), inputs);
+        out
+    }
+
+    fn fresh(&mut self, size: u8) -> Varnode {
+        let varnode = Varnode {
+            space: "unique".to_string(),
+            offset: self.unique,
+            size,
+        };
+        self.unique += size as u64;
+        varnode
+    }
+
+    /// Copies `varnode` to `out` if there is one.
+    fn finish(&mut self, varnode: Varnode, out: Option<Varnode>) -> Varnode {
+        match out {
+            Some(out) if out != varnode => {
+                self.emit(OpCode::Copy, Some(out.clone()), vec![varnode]);
+                out
+            }
+            _ => varnode,
+        }
+    }
+
+    fn space(&self, name: Option<&str>) -> Option<usize> {
+        self.spec
+            .spaces
+            .iter()
+            .position(|s| name.map_or(s.default, |name| s.name == name))
+    }
+
+    fn address_size(&self, space: Option<&str>) -> Option<u8> {
+        Some(self.spec.spaces[self.space(space)?].size)
+    }
+
+    fn register(&self, name: &str) -> Option<Varnode> {
+        let register = self.spec.registers.iter().find(|r| r.name == name)?;
+        let space = self
+            .spec
+            .spaces
+            .iter()
+            .find(|s| matches!(s.ty, SpaceType::Register))
+            .map_or("register", |s| s.name.as_str());
+        Some(Varnode {
+            space: space.to_string(),
+            offset: register.offset as u64,
+            size: register.size as u8,
+        })
+    }
+
+    /// The operand, local, register or builtin `name` refers to, `Some(None)`
+    /// if there is none.
+    fn lookup(&mut self, frame: &mut Frame<'_, 's>, name: &str) -> Option<Option<Value>> {
+        if let Some(local) = frame.locals.get(name) {
+            return Some(Some(Value::Handle(Handle::Varnode(local.clone()))));
+        }
+        let node = frame.node;
+        if node.table(name).is_some() {
+            self.build(frame, name)?;
+            let export = frame.operands[name].clone()?;
+            return Some(Some(Value::Handle(export)));
+        }
+        if let Some(value) = node.calculated.get(name) {
+            return Some(Some(Value::Const(*value)));
+        }
+        match name {
+            "inst_start" => return Some(Some(Value::Const(self.inst_start as i128))),
+            "inst_next" => return Some(Some(Value::Const(self.inst_next as i128))),
+            _ => {}
+        }
+        if let Some((meaning, _)) = field_info(self.spec, name) {
+            let value = node.state.operand_value(node.constructor, name)?;
+            let index = usize::try_from(value).ok();
+            let value = match meaning {
+                FieldMeaning::Variables(variables) => {
+                    let variable = index.and_then(|i| variables.get(i))?;
+                    Value::Handle(Handle::Varnode(self.register(variable)?))
+                }
+                FieldMeaning::Values(values) => {
+                    let value = index.and_then(|i| *values.get(i)?)?;
+                    Value::Const(value as i128)
+                }
+                FieldMeaning::Names(_) | FieldMeaning::Default => Value::Const(value),
+            };
+            return Some(Some(value));
+        }
+        Some(
+            self.register(name)
+                .map(|register| Value::Handle(Handle::Varnode(register))),
+        )
+    }
+
+    /// The size of `name` if it is known without lifting anything.
+    fn symbol_size(&self, frame: &Frame, name: &str) -> Option<u8> {
+        if let Some(local) = frame.locals.get(name) {
+            return Some(local.size);
+        }
+        if let Some(export) = frame.operands.get(name) {
+            return export.as_ref().map(Handle::size);
+        }
+        if let Some((FieldMeaning::Variables(variables), _)) = field_info(self.spec, name) {
+            return variables
+                .iter()
+                .find_map(|v| self.register(v))
+                .map(|r| r.size);
+        }
+        self.register(name).map(|r| r.size)
+    }
+
+    /// The size of `rvalue` if it is known without lifting anything.
+    fn hint(&self, frame: &Frame, rvalue: &RValue) -> Option<u8> {
+        let binary = |lhs, rhs| self.hint(frame, lhs).or_else(|| self.hint(frame, rhs));
+        match rvalue {
+            RValue::Add(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::Sub(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::Mult(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::Div(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::Rem(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::IntOr(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::IntAnd(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::IntXor(inner) => binary(&inner.lhs, &inner.rhs),
+            RValue::RShift(inner) => self.hint(frame, &inner.lhs),
+            RValue::LShift(inner) => self.hint(frame, &inner.lhs),
+            RValue::BoolOr(_) | RValue::BoolAnd(_) | RValue::BoolXor(_) => Some(1),
+            RValue::Comparison(_) => Some(1),
+            RValue::Not(inner) => self.hint(frame, &inner.op),
+            RValue::Neg(inner) => self.hint(frame, &inner.op),
+            RValue::Parenthesized(inner) => self.hint(frame, &inner.op),
+            RValue::Deref(inner) => self.hint(frame, &inner.op),
+            RValue::Constant(constant) => constant.size,
+            RValue::Call(call) => match (call.call.as_str(), call.args.as_slice()) {
+                ("carry", _) | ("scarry", _) | ("sborrow", _) | ("nan", _) => Some(1),
+                ("abs", [arg])
+                | ("sqrt", [arg])
+                | ("ceil", [arg])
+                | ("floor", [arg])
+                | ("round", [arg]) => self.hint(frame, arg),
+                _ => None,
+            },
+            RValue::Ref(inner) => inner.size.or_else(|| self.address_size(None)),
+            RValue::LValue(LValue::Ident(ident)) => {
+                ident.size.or_else(|| self.symbol_size(frame, &ident.field))
+            }
+            RValue::LValue(LValue::Slice(slice)) => Some(slice.size.div_ceil(8)),
+            RValue::LValue(LValue::Ref(inner)) => inner.size,
+        }
+    }
+
+    fn is_boolean(rvalue: &RValue) -> bool {
+        match rvalue {
+            RValue::Comparison(_) | RValue::BoolOr(_) | RValue::BoolAnd(_) | RValue::BoolXor(_) => {
+                true
+            }
+            RValue::Not(inner) => Self::is_boolean(&inner.op),
+            RValue::Parenthesized(inner) => Self::is_boolean(&inner.op),
+            RValue::Call(call) => {
+                matches!(call.call.as_str(), "carry" | "scarry" | "sborrow" | "nan")
+            }
+            _ => false,
+        }
+    }
+
+    fn action(&mut self, frame: &mut Frame<'_, 's>, action: &Action) -> Option<()> {
+        match action {
+            Action::Label(label) => {
+                frame.labels.insert(label.label.clone(), self.ops.len());
+            }
+            Action::LocalDecl(inner) => {
+                let size = inner
+                    .name
+                    .size
+                    .or_else(|| self.hint(frame, &inner.val))
+                    .or_else(|| self.address_size(None))?;
+                let local = self.fresh(size);
+                self.expr(frame, &inner.val, Some(size), Some(local.clone()))?;
+                frame.locals.insert(inner.name.field.clone(), local);
+            }
+            Action::Export(inner) => frame.export = Some(self.export(frame, &inner.op)?),
+            Action::Assignment(inner) => self.assign(frame, &inner.name, &inner.val)?,
+            Action::Build(inner) => self.build(frame, &inner.field)?,
+            Action::If(inner) => {
+                // only `goto` can be conditional
+                let goto = match &inner.action {
+                    Action::Goto(goto) => goto,
+                    _ => return None,
+                };
+                let cond = self.expr(frame, &inner.cond, Some(1), None)?;
+                self.goto(frame, goto, Some(cond))?;
+            }
+            Action::Goto(goto) => self.goto(frame, goto, None)?,
+            Action::Call(inner) => {
+                self.jump(frame, &inner.address, OpCode::Call, OpCode::CallInd)?
+            }
+            Action::Return(inner) => {
+                let address = match &inner.val {
+                    RValue::Deref(inner) => &inner.op,
+                    address => address,
+                };
+                let address = self.expr(frame, address, None, None)?;
+                self.emit(OpCode::Return, None, vec![address]);
+            }
+            Action::PCodeOp(inner) => {
+                let inputs = self.call_other_inputs(frame, &inner.pcopdeop, &inner.args)?;
+                self.emit(OpCode::CallOther, None, inputs);
+            }
+            // macros are expanded when parsing
+            Action::Macro(_) => return None,
+        }
+        Some(())
+    }
+
+    fn goto(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        goto: &ActionGoto,
+        cond: Option<Varnode>,
+    ) -> Option<()> {
+        let opcode = match cond {
+            Some(_) => OpCode::CBranch,
+            None => OpCode::Branch,
+        };
+        match goto {
+            ActionGoto::Label(label) => {
+                frame.fixups.push((self.ops.len(), label.label.clone()));
+                let inputs = Some(constant(0, 4)).into_iter().chain(cond).collect();
+                self.emit(opcode, None, inputs);
+            }
+            ActionGoto::Address(address) => match cond {
+                None => self.jump(frame, address, OpCode::Branch, OpCode::BranchInd)?,
+                // there is no conditional indirect branch
+                Some(_) if matches!(address, RValue::Deref(_)) => return None,
+                Some(cond) => match self.target(frame, address)? {
+                    Ok(target) => self.emit(OpCode::CBranch, None, vec![target, cond]),
+                    Err(_) => return None,
+                },
+            },
+        }
+        Some(())
+    }
+
+    /// Branches to `address`, indirectly if it's only known at runtime.
+    fn jump(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        address: &RValue,
+        direct: OpCode,
+        indirect: OpCode,
+    ) -> Option<()> {
+        if let RValue::Deref(inner) = address {
+            let address = self.expr(frame, &inner.op, None, None)?;
+            self.emit(indirect, None, vec![address]);
+            return Some(());
+        }
+        match self.target(frame, address)? {
+            Ok(target) => self.emit(direct, None, vec![target]),
+            Err(pointer) => self.emit(indirect, None, vec![pointer]),
+        }
+        Some(())
+    }
+
+    /// The location a branch to `address` goes to, or the pointer holding
+    /// it if it is dynamic.
+    fn target(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        address: &RValue,
+    ) -> Option<Result<Varnode, Varnode>> {
+        Some(match self.handle(frame, address)? {
+            Handle::Varnode(varnode) if varnode.space == "const" => {
+                let space = &self.spec.spaces[self.space(None)?];
+                Ok(Varnode {
+                    space: space.name.clone(),
+                    offset: varnode.offset,
+                    size: space.size,
+                })
+            }
+            Handle::Varnode(varnode) => Ok(varnode),
+            Handle::Pointer { pointer, .. } => Err(pointer),
+        })
+    }
+
+    /// Like `expr`, but keeps references to operands intact instead of
+    /// reading them.
+    fn handle(&mut self, frame: &mut Frame<'_, 's>, rvalue: &RValue) -> Option<Handle> {
+        match rvalue {
+            RValue::Parenthesized(inner) => self.handle(frame, &inner.op),
+            RValue::LValue(LValue::Ident(ident)) if ident.size.is_none() => {
+                match self.lookup(frame, &ident.field)? {
+                    Some(Value::Handle(handle)) => Some(handle),
+                    Some(Value::Const(value)) => {
+                        Some(Handle::Varnode(constant(value, self.address_size(None)?)))
+                    }
+                    None => None,
+                }
+            }
+            _ => Some(Handle::Varnode(self.expr(frame, rvalue, None, None)?)),
+        }
+    }
+
+    fn export(&mut self, frame: &mut Frame<'_, 's>, rvalue: &RValue) -> Option<Handle> {
+        let reference = match rvalue {
+            RValue::LValue(LValue::Ref(reference)) => reference,
+            _ => return self.handle(frame, rvalue),
+        };
+        let size = reference.size.or_else(|| self.address_size(None))?;
+        let address = self.handle(frame, &reference.op)?;
+        if reference.space.as_deref() == Some("const") {
+            let value = self.read(address, None);
+            return Some(Handle::Varnode(constant(value.offset as i128, size)));
+        }
+        let space = self.space(reference.space.as_deref())?;
+        Some(match address {
+            Handle::Varnode(varnode) if varnode.space == "const" => Handle::Varnode(Varnode {
+                space: self.spec.spaces[space].name.clone(),
+                offset: varnode.offset,
+                size,
+            }),
+            address => {
+                let pointer = self.read(address, None);
+                Handle::Pointer {
+                    space,
+                    pointer,
+                    size,
+                }
+            }
+        })
+    }
+
+    fn read(&mut self, handle: Handle, out: Option<Varnode>) -> Varnode {
+        match handle {
+            Handle::Varnode(varnode) => self.finish(varnode, out),
+            Handle::Pointer {
+                space,
+                pointer,
+                size,
+            } => self.op(
+                OpCode::Load,
+                size,
+                out,
+                vec![constant(space as i128, 8), pointer],
+            ),
+        }
+    }
+
+    fn write(&mut self, handle: Handle, rvalue: &RValue, frame: &mut Frame<'_, 's>) -> Option<()> {
+        match handle {
+            Handle::Varnode(varnode) => {
+                self.expr(frame, rvalue, Some(varnode.size), Some(varnode))?;
+            }
+            Handle::Pointer {
+                space,
+                pointer,
+                size,
+            } => {
+                let value = self.expr(frame, rvalue, Some(size), None)?;
+                self.emit(
+                    OpCode::Store,
+                    None,
+                    vec![constant(space as i128, 8), pointer, value],
+                );
+            }
+        }
+        Some(())
+    }
+
+    /// Narrows `handle` to its `size` least significant bytes.
+    fn truncate(&self, handle: Handle, size: Option<u8>) -> Handle {
+        let size = match size {
+            Some(size) if size != handle.size() => size,
+            _ => return handle,
+        };
+        match handle {
+            Handle::Varnode(varnode) if varnode.space == "const" => {
+                Handle::Varnode(constant(varnode.offset as i128, size))
+            }
+            Handle::Varnode(mut varnode) => {
+                if self.spec.endianness == crate::Endianness::Big {
+                    varnode.offset += varnode.size.saturating_sub(size) as u64;
+                }
+                varnode.size = size;
+                Handle::Varnode(varnode)
+            }
+            Handle::Pointer { space, pointer, .. } => Handle::Pointer {
+                space,
+                pointer,
+                size,
+            },
+        }
+    }
+
+    fn assign(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        lvalue: &LValue,
+        rvalue: &RValue,
+    ) -> Option<()> {
+        match lvalue {
+            LValue::Ident(ident) => {
+                let target = match self.lookup(frame, &ident.field)? {
+                    Some(Value::Handle(handle)) => self.truncate(handle, ident.size),
+                    Some(Value::Const(_)) => return None,
+                    None => {
+                        let size = ident
+                            .size
+                            .or_else(|| self.hint(frame, rvalue))
+                            .or_else(|| self.address_size(None))?;
+                        let local = self.fresh(size);
+                        frame.locals.insert(ident.field.clone(), local.clone());
+                        Handle::Varnode(local)
+                    }
+                };
+                self.write(target, rvalue, frame)
+            }
+            LValue::Slice(slice) => self.assign_slice(frame, slice, rvalue),
+            LValue::Ref(reference) => {
+                let space = self.space(reference.space.as_deref())?;
+                let address_size = self.spec.spaces[space].size;
+                let pointer = self.expr(frame, &reference.op, Some(address_size), None)?;
+                let size = reference
+                    .size
+                    .or_else(|| self.hint(frame, rvalue))
+                    .or_else(|| self.address_size(None))?;
+                let target = Handle::Pointer {
+                    space,
+                    pointer,
+                    size,
+                };
+                self.write(target, rvalue, frame)
+            }
+        }
+    }
+
+    /// Replaces the bits of the slice, keeping the others.
+    fn assign_slice(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        slice: &LValueSlice,
+        rvalue: &RValue,
+    ) -> Option<()> {
+        let target = match self.lookup(frame, &slice.field)? {
+            Some(Value::Handle(handle)) => handle,
+            _ => return None,
+        };
+        let size = target.size();
+        let bits = (1u64 << slice.size as u32).wrapping_sub(1) & mask(size);
+        let value_size = slice.size.div_ceil(8);
+
+        let mut value = self.expr(frame, rvalue, Some(value_size), None)?;
+        if value_size < size {
+            value = self.op(OpCode::IntZExt, size, None, vec![value]);
+        }
+        if !slice.size.is_multiple_of(8) {
+            value = self.op(
+                OpCode::IntAnd,
+                size,
+                None,
+                vec![value, constant(bits as i128, size)],
+            );
+        }
+        if slice.offset > 0 {
+            let shift = constant(slice.offset as i128, 4);
+            value = self.op(OpCode::IntLeft, size, None, vec![value, shift]);
+        }
+
+        let current = self.read(target.clone(), None);
+        let cleared = !(bits << slice.offset as u32) & mask(size);
+        let cleared = self.op(
+            OpCode::IntAnd,
+            size,
+            None,
+            vec![current, constant(cleared as i128, size)],
+        );
+        match target {
+            Handle::Varnode(varnode) => {
+                self.op(OpCode::IntOr, size, Some(varnode), vec![cleared, value]);
+            }
+            Handle::Pointer { space, pointer, .. } => {
+                let value = self.op(OpCode::IntOr, size, None, vec![cleared, value]);
+                self.emit(
+                    OpCode::Store,
+                    None,
+                    vec![constant(space as i128, 8), pointer, value],
+                );
+            }
+        }
+        Some(())
+    }
+
+    fn binary(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        opcode: OpCode,
+        lhs: &RValue,
+        rhs: &RValue,
+        size: u8,
+        out: Option<Varnode>,
+    ) -> Option<Varnode> {
+        let lhs = self.expr(frame, lhs, Some(size), None)?;
+        let rhs = self.expr(frame, rhs, Some(size), None)?;
+        Some(self.op(opcode, size, out, vec![lhs, rhs]))
+    }
+
+    /// Emits the p-code computing `rvalue`, writing the result to `out` if
+    /// given. `size` is the size the context expects.
+    fn expr(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        rvalue: &RValue,
+        size: Option<u8>,
+        out: Option<Varnode>,
+    ) -> Option<Varnode> {
+        let size = out
+            .as_ref()
+            .map(|out| out.size)
+            .or(size)
+            .or_else(|| self.hint(frame, rvalue))
+            .or_else(|| self.address_size(None))?;
+        let arithmetic = |prefix: NumTypePrefix, int, signed, float| match prefix {
+            NumTypePrefix::Default => int,
+            NumTypePrefix::Signed => signed,
+            NumTypePrefix::Float => float,
+        };
+
+        match rvalue {
+            RValue::Add(inner) => {
+                let opcode = arithmetic(
+                    inner.num_type_prefix,
+                    OpCode::IntAdd,
+                    OpCode::IntAdd,
+                    OpCode::FloatAdd,
+                );
+                self.binary(frame, opcode, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::Sub(inner) => {
+                let opcode = arithmetic(
+                    inner.num_type_prefix,
+                    OpCode::IntSub,
+                    OpCode::IntSub,
+                    OpCode::FloatSub,
+                );
+                self.binary(frame, opcode, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::Mult(inner) => {
+                let opcode = arithmetic(
+                    inner.num_type_prefix,
+                    OpCode::IntMult,
+                    OpCode::IntMult,
+                    OpCode::FloatMult,
+                );
+                self.binary(frame, opcode, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::Div(inner) => {
+                let opcode = arithmetic(
+                    inner.num_type_prefix,
+                    OpCode::IntDiv,
+                    OpCode::IntSDiv,
+                    OpCode::FloatDiv,
+                );
+                self.binary(frame, opcode, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::Rem(inner) => {
+                let opcode = arithmetic(
+                    inner.num_type_prefix,
+                    OpCode::IntRem,
+                    OpCode::IntSRem,
+                    OpCode::IntRem,
+                );
+                self.binary(frame, opcode, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::IntOr(inner) => {
+                self.binary(frame, OpCode::IntOr, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::IntAnd(inner) => {
+                self.binary(frame, OpCode::IntAnd, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::IntXor(inner) => {
+                self.binary(frame, OpCode::IntXor, &inner.lhs, &inner.rhs, size, out)
+            }
+            RValue::BoolOr(inner) => {
+                self.binary(frame, OpCode::BoolOr, &inner.lhs, &inner.rhs, 1, out)
+            }
+            RValue::BoolAnd(inner) => {
+                self.binary(frame, OpCode::BoolAnd, &inner.lhs, &inner.rhs, 1, out)
+            }
+            RValue::BoolXor(inner) => {
+                self.binary(frame, OpCode::BoolXor, &inner.lhs, &inner.rhs, 1, out)
+            }
+            RValue::LShift(inner) => {
+                let lhs = self.expr(frame, &inner.lhs, Some(size), None)?;
+                let rhs = self.shift_amount(frame, &inner.rhs)?;
+                Some(self.op(OpCode::IntLeft, size, out, vec![lhs, rhs]))
+            }
+            RValue::RShift(inner) => {
+                let opcode = arithmetic(
+                    inner.num_type_prefix,
+                    OpCode::IntRight,
+                    OpCode::IntSRight,
+                    OpCode::IntRight,
+                );
+                let lhs = self.expr(frame, &inner.lhs, Some(size), None)?;
+                let rhs = self.shift_amount(frame, &inner.rhs)?;
+                Some(self.op(opcode, size, out, vec![lhs, rhs]))
+            }
+            RValue::Comparison(inner) => {
+                let prefix = inner.num_type_prefix;
+                let (opcode, swap) = match inner.operator {
+                    ComparisonOperator::Equal => (
+                        arithmetic(
+                            prefix,
+                            OpCode::IntEqual,
+                            OpCode::IntEqual,
+                            OpCode::FloatEqual,
+                        ),
+                        false,
+                    ),
+                    ComparisonOperator::NotEqual => (
+                        arithmetic(
+                            prefix,
+                            OpCode::IntNotEqual,
+                            OpCode::IntNotEqual,
+                            OpCode::FloatNotEqual,
+                        ),
+                        false,
+                    ),
+                    ComparisonOperator::Less | ComparisonOperator::Greater => (
+                        arithmetic(prefix, OpCode::IntLess, OpCode::IntSLess, OpCode::FloatLess),
+                        inner.operator == ComparisonOperator::Greater,
+                    ),
+                    ComparisonOperator::LessEqual | ComparisonOperator::GreaterEqual => (
+                        arithmetic(
+                            prefix,
+                            OpCode::IntLessEqual,
+                            OpCode::IntSLessEqual,
+                            OpCode::FloatLessEqual,
+                        ),
+                        inner.operator == ComparisonOperator::GreaterEqual,
+                    ),
+                };
+                let operand_size = self
+                    .hint(frame, &inner.lhs)
+                    .or_else(|| self.hint(frame, &inner.rhs))
+                    .or_else(|| self.address_size(None))?;
+                let lhs = self.expr(frame, &inner.lhs, Some(operand_size), None)?;
+                let rhs = self.expr(frame, &inner.rhs, Some(operand_size), None)?;
+                let inputs = if swap { vec![rhs, lhs] } else { vec![lhs, rhs] };
+                Some(self.op(opcode, 1, out, inputs))
+            }
+            RValue::Not(inner) => {
+                if Self::is_boolean(&inner.op) {
+                    let op = self.expr(frame, &inner.op, Some(1), None)?;
+                    Some(self.op(OpCode::BoolNegate, 1, out, vec![op]))
+                } else {
+                    let op = self.expr(frame, &inner.op, Some(size), None)?;
+                    Some(self.op(OpCode::IntNegate, size, out, vec![op]))
+                }
+            }
+            RValue::Neg(inner) => {
+                let op = self.expr(frame, &inner.op, Some(size), None)?;
+                Some(self.op(OpCode::Int2Comp, size, out, vec![op]))
+            }
+            RValue::Parenthesized(inner) => self.expr(frame, &inner.op, Some(size), out),
+            RValue::Deref(inner) => {
+                let address_size = self.address_size(None)?;
+                self.expr(frame, &inner.op, Some(address_size), out)
+            }
+            RValue::Constant(inner) => {
+                let varnode = constant(inner.value, inner.size.unwrap_or(size));
+                Some(self.finish(varnode, out))
+            }
+            RValue::Call(call) => self.call(frame, call, size, out),
+            RValue::Ref(inner) => {
+                let size = inner.size.or_else(|| self.address_size(None))?;
+                let address = match self.lookup(frame, &inner.field)? {
+                    Some(Value::Handle(Handle::Varnode(varnode))) => {
+                        constant(varnode.offset as i128, size)
+                    }
+                    Some(Value::Handle(Handle::Pointer { pointer, .. })) => pointer,
+                    Some(Value::Const(value)) => constant(value, size),
+                    None => return None,
+                };
+                Some(self.finish(address, out))
+            }
+            RValue::LValue(LValue::Ident(ident)) => match self.lookup(frame, &ident.field)? {
+                Some(Value::Handle(handle)) => {
+                    let handle = self.truncate(handle, ident.size);
+                    Some(self.read(handle, out))
+                }
+                Some(Value::Const(value)) => {
+                    let varnode = constant(value, ident.size.unwrap_or(size));
+                    Some(self.finish(varnode, out))
+                }
+                None => None,
+            },
+            RValue::LValue(LValue::Slice(slice)) => {
+                let value = match self.lookup(frame, &slice.field)? {
+                    Some(Value::Handle(handle)) => self.read(handle, None),
+                    Some(Value::Const(value)) => constant(value, self.address_size(None)?),
+                    None => return None,
+                };
+                let full = value.size;
+                let mut value = value;
+                if slice.offset > 0 {
+                    let shift = constant(slice.offset as i128, 4);
+                    value = self.op(OpCode::IntRight, full, None, vec![value, shift]);
+                }
+                if (slice.size as u32) < full as u32 * 8 {
+                    let bits = (1u64 << slice.size as u32) - 1;
+                    let bits = constant(bits as i128, full);
+                    value = self.op(OpCode::IntAnd, full, None, vec![value, bits]);
+                }
+                let size = slice.size.div_ceil(8);
+                if size < full {
+                    Some(self.op(OpCode::SubPiece, size, out, vec![value, constant(0, 4)]))
+                } else {
+                    Some(self.finish(value, out))
+                }
+            }
+            RValue::LValue(LValue::Ref(reference)) => {
+                let space = self.space(reference.space.as_deref())?;
+                let address_size = self.spec.spaces[space].size;
+                let pointer = self.expr(frame, &reference.op, Some(address_size), None)?;
+                let handle = Handle::Pointer {
+                    space,
+                    pointer,
+                    size: reference.size.unwrap_or(size),
+                };
+                Some(self.read(handle, out))
+            }
+        }
+    }
+
+    fn shift_amount(&mut self, frame: &mut Frame<'_, 's>, rvalue: &RValue) -> Option<Varnode> {
+        let size = self.hint(frame, rvalue).unwrap_or(4);
+        self.expr(frame, rvalue, Some(size), None)
+    }
+
+    fn call(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        call: &RValueCall,
+        size: u8,
+        out: Option<Varnode>,
+    ) -> Option<Varnode> {
+        let unary = |opcode, lifter: &mut Self, frame: &mut Frame<'_, 's>, arg, arg_size| {
+            let arg = lifter.expr(frame, arg, arg_size, None)?;
+            Some(lifter.op(opcode, size, out.clone(), vec![arg]))
+        };
+        match (call.call.as_str(), call.args.as_slice()) {
+            ("zext", [arg]) => unary(OpCode::IntZExt, self, frame, arg, None),
+            ("sext", [arg]) => unary(OpCode::IntSExt, self, frame, arg, None),
+            ("popcount", [arg]) => unary(OpCode::PopCount, self, frame, arg, None),
+            ("int2float", [arg]) => unary(OpCode::Int2Float, self, frame, arg, None),
+            ("float2float", [arg]) => unary(OpCode::Float2Float, self, frame, arg, None),
+            ("trunc", [arg]) => unary(OpCode::Trunc, self, frame, arg, None),
+            ("abs", [arg]) => unary(OpCode::FloatAbs, self, frame, arg, Some(size)),
+            ("sqrt", [arg]) => unary(OpCode::FloatSqrt, self, frame, arg, Some(size)),
+            ("ceil", [arg]) => unary(OpCode::FloatCeil, self, frame, arg, Some(size)),
+            ("floor", [arg]) => unary(OpCode::FloatFloor, self, frame, arg, Some(size)),
+            ("round", [arg]) => unary(OpCode::FloatRound, self, frame, arg, Some(size)),
+            ("nan", [arg]) => {
+                let arg = self.expr(frame, arg, None, None)?;
+                Some(self.op(OpCode::FloatNan, 1, out, vec![arg]))
+            }
+            ("carry", [lhs, rhs]) | ("scarry", [lhs, rhs]) | ("sborrow", [lhs, rhs]) => {
+                let opcode = match call.call.as_str() {
+                    "carry" => OpCode::IntCarry,
+                    "scarry" => OpCode::IntSCarry,
+                    _ => OpCode::IntSBorrow,
+                };
+                let operand_size = self
+                    .hint(frame, lhs)
+                    .or_else(|| self.hint(frame, rhs))
+                    .or_else(|| self.address_size(None))?;
+                let lhs = self.expr(frame, lhs, Some(operand_size), None)?;
+                let rhs = self.expr(frame, rhs, Some(operand_size), None)?;
+                Some(self.op(opcode, 1, out, vec![lhs, rhs]))
+            }
+            (name, args) => {
+                let inputs = self.call_other_inputs(frame, name, args)?;
+                Some(self.op(OpCode::CallOther, size, out, inputs))
+            }
+        }
+    }
+
+    fn call_other_inputs(
+        &mut self,
+        frame: &mut Frame<'_, 's>,
+        name: &str,
+        args: &[RValue],
+    ) -> Option<Vec<Varnode>> {
+        let index = self.spec.pcodeops.iter().position(|p| p.name == name)?;
+        let mut inputs = vec![constant(index as i128, 4)];
+        for arg in args {
+            inputs.push(self.expr(frame, arg, None, None)?);
+        }
+        Some(inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Spec;
+
+    fn lift(spec: &Spec, code: &[u8]) -> Vec<String> {
+        let instruction = spec.disassemble(code).unwrap();
+        instruction.pcode.iter().map(|op| op.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lift() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 ];
+define register offset=8 size=1 [ flag ];
+define token instr(8)
+    op = (4,7)
+    reg = (0,0)
+    imm = (0,3)
+;
+attach variables reg [ r0 r1 ];
+
+Mem: [reg] is reg { export *:2 reg; }
+Rel: dest is imm [ dest = inst_next + imm; ] { export *:4 dest; }
+
+:add reg is op=1 & reg { reg = reg + 1; }
+:ld Mem is op=2 & Mem { r0 = sext(Mem); }
+:st Mem is op=3 & Mem { Mem = r1:2; }
+:dec is op=4 { r0 = r0 - 1; if (r0 != 0) goto <done>; flag = 1; <done> }
+:j Rel is op=5 & Rel { goto Rel; }
+:tmp is op=6 { local t = r0 * r1; r1 = t >> 2; }
+"#,
+        );
+        assert_eq!(
+            lift(&spec, &[0x11]),
+            ["(register, 0x4, 4) = INT_ADD (register, 0x4, 4), (const, 0x1, 4)"]
+        );
+        // the subtable is built before the constructor's own p-code
+        assert_eq!(
+            lift(&spec, &[0x21]),
+            [
+                "(unique, 0x0, 2) = LOAD (const, 0x0, 8), (register, 0x4, 4)",
+                "(register, 0x0, 4) = INT_SEXT (unique, 0x0, 2)",
+            ]
+        );
+        assert_eq!(
+            lift(&spec, &[0x31]),
+            ["STORE (const, 0x0, 8), (register, 0x4, 4), (register, 0x4, 2)"]
+        );
+        // the label is past the last op, two ops after the branch
+        assert_eq!(
+            lift(&spec, &[0x41]),
+            [
+                "(register, 0x0, 4) = INT_SUB (register, 0x0, 4), (const, 0x1, 4)",
+                "(unique, 0x0, 1) = INT_NOTEQUAL (register, 0x0, 4), (const, 0x0, 4)",
+                "CBRANCH (const, 0x2, 4), (unique, 0x0, 1)",
+                "(register, 0x8, 1) = COPY (const, 0x1, 1)",
+            ]
+        );
+        // `inst_next` is 1 for a one byte instruction at address 0
+        assert_eq!(lift(&spec, &[0x51]), ["BRANCH (ram, 0x2, 4)"]);
+        assert_eq!(
+            lift(&spec, &[0x61]),
+            [
+                "(unique, 0x0, 4) = INT_MULT (register, 0x0, 4), (register, 0x4, 4)",
+                "(register, 0x4, 4) = INT_RIGHT (unique, 0x0, 4), (const, 0x2, 4)",
+            ]
+        );
+    }
+}
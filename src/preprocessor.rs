@@ -3,38 +3,137 @@ use pest::{
     Parser,
 };
 use pest_derive::Parser;
-use std::{collections::HashMap, fs::read_to_string, path::Path};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    io,
+    path::{Path, PathBuf},
+};
 
 #[derive(Parser)]
 #[grammar = "../pest/preprocessor.pest"] // relative to src
 struct Preprocessor;
 
 pub fn preprocess(dir: impl AsRef<Path>, file: impl AsRef<Path>) -> String {
-    let mut context = Context::new();
+    preprocess_with_source_map(dir, file).0
+}
+
+/// Like `preprocess`, but also returns a map from offsets in the result back
+/// to the files they came from.
+pub fn preprocess_with_source_map(
+    dir: impl AsRef<Path>,
+    file: impl AsRef<Path>,
+) -> (String, SourceMap) {
+    preprocess_with_reader(dir, file, |path| read_to_string(path))
+}
+
+/// Like `preprocess_with_source_map`, but reads files through `read`, e.g. to
+/// see the unsaved contents of an editor.
+pub fn preprocess_with_reader(
+    dir: impl AsRef<Path>,
+    file: impl AsRef<Path>,
+    mut read: impl FnMut(&Path) -> io::Result<String>,
+) -> (String, SourceMap) {
+    let mut context = Context::new(&mut read);
     context.preprocess_file(dir.as_ref(), file.as_ref());
-    context.result
+    (context.result, context.source_map)
+}
+
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    segments: Vec<Segment>,
 }
 
 #[derive(Default)]
-struct Context {
+struct SourceFile {
+    path: PathBuf,
+    text: String,
+}
+
+/// A run of preprocessed output starting at `output` that was copied from
+/// `file` at byte `source`. Interpolated `$(...)` values aren't `verbatim`
+/// and map entirely to the start of the interpolation.
+struct Segment {
+    output: usize,
+    file: usize,
+    source: usize,
+    verbatim: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Location<'a> {
+    pub file: &'a Path,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, in bytes.
+    pub column: usize,
+}
+
+impl SourceMap {
+    /// Maps a byte offset in the preprocessed output, e.g. `Span::start`,
+    /// back to the file it was read from.
+    pub fn locate(&self, offset: usize) -> Option<Location<'_>> {
+        let idx = self.segments.partition_point(|s| s.output <= offset);
+        let segment = &self.segments[idx.checked_sub(1)?];
+        let file = &self.files[segment.file];
+        let mut source = segment.source;
+        if segment.verbatim {
+            source += offset - segment.output;
+        }
+        let before = file.text.get(..source)?;
+        let line = before.matches('\n').count() + 1;
+        let column = source - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        Some(Location {
+            file: &file.path,
+            line,
+            column,
+        })
+    }
+}
+
+struct Context<'r> {
+    read: &'r mut dyn FnMut(&Path) -> io::Result<String>,
     defines: HashMap<String, String>,
     result: String,
+    source_map: SourceMap,
 }
 
-impl Context {
-    fn new() -> Self {
-        Default::default()
+impl<'r> Context<'r> {
+    fn new(read: &'r mut dyn FnMut(&Path) -> io::Result<String>) -> Self {
+        Context {
+            read,
+            defines: HashMap::new(),
+            result: String::new(),
+            source_map: SourceMap::default(),
+        }
     }
 
     fn preprocess_file(&mut self, dir: &Path, file: impl AsRef<Path>) {
-        let file = dir.join(file);
+        let path = dir.join(file);
 
-        let raw = read_to_string(file).unwrap();
+        let raw = (self.read)(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let file = self.source_map.files.len();
+        self.source_map.files.push(SourceFile {
+            path,
+            text: String::new(),
+        });
         let lines = Preprocessor::parse(Rule::file, &raw).unwrap_or_else(|e| panic!("{}", e));
-        self.preprocess_items(dir, lines);
+        self.preprocess_items(dir, file, lines);
+        self.source_map.files[file].text = raw;
     }
 
-    fn preprocess_items(&mut self, dir: &Path, lines: Pairs<Rule>) {
+    fn push(&mut self, s: &str, file: usize, source: usize, verbatim: bool) {
+        self.source_map.segments.push(Segment {
+            output: self.result.len(),
+            file,
+            source,
+            verbatim,
+        });
+        self.result += s;
+    }
+
+    fn preprocess_items(&mut self, dir: &Path, file: usize, lines: Pairs<Rule>) {
         for line in lines {
             match line.as_rule() {
                 Rule::define => {
@@ -58,7 +157,7 @@ impl Context {
                         1
                     };
                     let block = tokens.nth(idx).unwrap();
-                    self.preprocess_items(dir, block.into_inner());
+                    self.preprocess_items(dir, file, block.into_inner());
                 }
                 Rule::if_block => {
                     let mut tokens = line.into_inner();
@@ -89,7 +188,7 @@ impl Context {
                         };
                         let block = tokens.next().unwrap();
                         if res {
-                            self.preprocess_items(dir, block.into_inner());
+                            self.preprocess_items(dir, file, block.into_inner());
                             break;
                         }
                     }
@@ -97,16 +196,19 @@ impl Context {
                 Rule::sleigh_line => {
                     // to interpolation
                     let mut s = line.as_str();
+                    let mut source = line.as_span().start();
                     while let Some(pos) = s.find("$(") {
-                        self.result += &s[..pos];
+                        self.push(&s[..pos], file, source, true);
+                        source += pos;
                         s = &s[pos + 2..];
                         let end = s.find(')').unwrap();
-                        let name = &s[..end];
-                        self.result += &self.defines[name];
+                        let value = self.defines[&s[..end]].clone();
+                        self.push(&value, file, source, false);
+                        source += end + 3;
                         s = &s[end + 1..];
                     }
 
-                    self.result += s;
+                    self.push(s, file, source, true);
                 }
                 Rule::EOI => {}
                 rule => {
@@ -150,3 +252,59 @@ impl Context {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::preprocess_with_source_map;
+    use crate::Spec;
+    use std::{env::temp_dir, fs, path::Path, process};
+
+    #[test]
+    fn test_source_map() {
+        let dir = temp_dir().join(format!("sleigh-source-map-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("main.slaspec"),
+            r#"@define SIZE "4"
+define endian=little;
+define space ram type=ram_space size=$(SIZE) default;
+@include "insn.sinc"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("insn.sinc"),
+            r#"define token instr(8)
+    op = (0,7)
+;
+
+:nop is op=0 { }
+:ld is op=1 { local x = op + 1; }
+"#,
+        )
+        .unwrap();
+
+        let (source, map) = preprocess_with_source_map(&dir, "main.slaspec");
+        fs::remove_dir_all(&dir).unwrap();
+        let spec = Spec::parse(&source);
+
+        let ld = spec
+            .constructors
+            .iter()
+            .find(|c| c.header.mnemonic.trim() == "ld")
+            .unwrap();
+        let location = map.locate(ld.span.start).unwrap();
+        assert_eq!(location.file, dir.join("insn.sinc"));
+        assert_eq!((location.line, location.column), (6, 1));
+        assert_eq!(&source[ld.constraint.span().range()], "op=1");
+        assert_eq!(&source[ld.actions[0].span().range()], "local x = op + 1");
+        let location = map.locate(ld.actions[0].span().start).unwrap();
+        assert_eq!((location.line, location.column), (6, 15));
+
+        // interpolated text maps to the `$(...)` it came from
+        let size = source.find("size=4").unwrap() + "size=".len();
+        let location = map.locate(size).unwrap();
+        assert_eq!(location.file, Path::new(&dir.join("main.slaspec")));
+        assert_eq!((location.line, location.column), (3, 38));
+    }
+}
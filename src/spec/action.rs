@@ -1,8 +1,8 @@
-use crate::{LValue, LValueIdent, RValue};
+use crate::{LValue, LValueIdent, RValue, Span};
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Action {
-    Label(String),
+    Label(ActionLabel),
     LocalDecl(ActionLocalDecl),
     Export(ActionExport),
     Assignment(ActionAssignment),
@@ -15,58 +15,92 @@ pub enum Action {
     Return(ActionReturn),
 }
 
-#[derive(Clone)]
+impl Action {
+    pub fn span(&self) -> Span {
+        match self {
+            Action::Label(inner) => inner.span,
+            Action::LocalDecl(inner) => inner.span,
+            Action::Export(inner) => inner.span,
+            Action::Assignment(inner) => inner.span,
+            Action::Build(inner) => inner.span,
+            Action::If(inner) => inner.span,
+            Action::Goto(ActionGoto::Label(inner)) => inner.span,
+            Action::Goto(ActionGoto::Address(inner)) => inner.span(),
+            Action::Macro(inner) => inner.span,
+            Action::PCodeOp(inner) => inner.span,
+            Action::Call(inner) => inner.span,
+            Action::Return(inner) => inner.span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ActionLabel {
+    pub label: String,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq)]
 pub struct ActionLocalDecl {
     pub name: LValueIdent,
     pub val: RValue,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionExport {
     pub op: RValue,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionAssignment {
     pub name: LValue,
     pub val: RValue,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionBuild {
     pub field: String,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionIf {
     pub cond: RValue,
     pub action: Action,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum ActionGoto {
-    Label(String),
+    Label(ActionLabel),
     Address(RValue),
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionMacro {
     pub r#macro: String,
     pub args: Vec<RValue>,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionPCodeOp {
     pub pcopdeop: String,
     pub args: Vec<RValue>,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionCall {
     pub address: RValue,
+    pub span: Span,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ActionReturn {
     pub val: RValue,
+    pub span: Span,
 }
@@ -0,0 +1,364 @@
+use super::*;
+
+/// Tables whose gaps need more pieces than this aren't reported.
+const MAX_UNCOVERED: usize = 4096;
+
+impl Spec {
+    /// Reports constructors that can never match, pairs of constructors that
+    /// overlap without one being more specific and encodings no constructor
+    /// of a table matches. Nothing is reported until `Spec::compile_patterns`
+    /// has run.
+    pub fn analyze_patterns(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        if self.constructors.iter().any(|c| c.pattern.is_none()) {
+            return diagnostics;
+        }
+        let mut tables: Vec<&str> = Vec::new();
+        for constructor in self.constructors.iter() {
+            if !tables.contains(&constructor.header.table.as_str()) {
+                tables.push(&constructor.header.table);
+            }
+        }
+
+        let alternatives = |table: &str| -> Vec<(usize, &DisjointPattern)> {
+            self.constructors
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.header.table == table)
+                .flat_map(|(i, c)| {
+                    let alternatives = c.pattern.iter().flat_map(|p| p.alternatives.iter());
+                    alternatives.map(move |a| (i, a))
+                })
+                .collect()
+        };
+
+        // a subtable operand covers everything if its table does, tables
+        // that refer to each other are only covered if that can be shown
+        // without assuming it
+        let mut covered: HashSet<&str> = HashSet::new();
+        loop {
+            let newly: Vec<&str> = tables
+                .iter()
+                .filter(|t| !covered.contains(*t))
+                .filter(|t| {
+                    let alternatives = alternatives(t);
+                    uncovered(&alternatives, &covered).is_some_and(|u| u.is_empty())
+                })
+                .copied()
+                .collect();
+            if newly.is_empty() {
+                break;
+            }
+            covered.extend(newly);
+        }
+
+        for table in tables {
+            let alternatives = alternatives(table);
+            self.dead_constructors(table, &alternatives, &mut diagnostics);
+            ambiguous_constructors(&self.constructors, &alternatives, &mut diagnostics);
+            uncovered_encodings(table, &alternatives, &covered, &mut diagnostics);
+        }
+        diagnostics
+    }
+
+    fn dead_constructors(
+        &self,
+        table: &str,
+        alternatives: &[(usize, &DisjointPattern)],
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        for (i, constructor) in self.constructors.iter().enumerate() {
+            let pattern = match &constructor.pattern {
+                Some(pattern) if constructor.header.table == table => pattern,
+                _ => continue,
+            };
+            // a constructor is dead if each of its alternatives is covered
+            // by more specific ones, which always take precedence
+            let is_dead = pattern.alternatives.iter().all(|alternative| {
+                let covering: Vec<_> = alternatives
+                    .iter()
+                    .filter(|(j, other)| {
+                        *j != i
+                            && other.specializes(alternative)
+                            && other
+                                .residual
+                                .iter()
+                                .all(|r| alternative.residual.contains(r))
+                    })
+                    .map(|(_, other)| *other)
+                    .collect();
+                subtract(alternative, &covering).is_empty()
+            });
+            if is_dead {
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::DeadConstructor,
+                    constructor: Some(i),
+                    span: constructor.span,
+                });
+            }
+        }
+    }
+}
+
+fn ambiguous_constructors(
+    constructors: &[Constructor],
+    alternatives: &[(usize, &DisjointPattern)],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut reported = Vec::new();
+    for (n, (i, a)) in alternatives.iter().enumerate() {
+        for (j, b) in alternatives[n + 1..].iter() {
+            if i == j || reported.contains(&(*i, *j)) {
+                continue;
+            }
+            let intersection = match a.and(b) {
+                Some(intersection) => intersection,
+                None => continue,
+            };
+            if a.specializes(b) || b.specializes(a) {
+                continue;
+            }
+            // the overlap is fine if constructors more specific than both
+            // cover it
+            let resolving: Vec<_> = alternatives
+                .iter()
+                .filter(|(_, c)| {
+                    c.specializes(a)
+                        && c.specializes(b)
+                        && c.residual.iter().all(|r| intersection.residual.contains(r))
+                })
+                .map(|(_, c)| *c)
+                .collect();
+            if !subtract(&intersection, &resolving).is_empty() {
+                reported.push((*i, *j));
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::AmbiguousConstructors { other: *j },
+                    constructor: Some(*i),
+                    span: constructors[*i].span,
+                });
+            }
+        }
+    }
+}
+
+fn uncovered_encodings(
+    table: &str,
+    alternatives: &[(usize, &DisjointPattern)],
+    covered: &HashSet<&str>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for piece in uncovered(alternatives, covered).unwrap_or_default() {
+        diagnostics.push(Diagnostic {
+            kind: DiagnosticKind::UncoveredEncoding {
+                table: table.to_string(),
+                pattern: piece.to_string(),
+            },
+            constructor: None,
+            span: Span::default(),
+        });
+    }
+}
+
+/// Returns the encodings none of `alternatives` match, `None` if there are
+/// too many pieces to tell.
+fn uncovered(
+    alternatives: &[(usize, &DisjointPattern)],
+    covered: &HashSet<&str>,
+) -> Option<Vec<DisjointPattern>> {
+    // alternatives with other residual checks don't match all of their
+    // bits, so they can't be relied on to cover anything
+    let covering = alternatives.iter().filter(|(_, a)| {
+        a.residual.iter().all(|(_, residual)| match residual {
+            Constraint::Constructor(inner) => covered.contains(inner.name.as_str()),
+            _ => false,
+        })
+    });
+    let mut uncovered = vec![DisjointPattern::default()];
+    for (_, pattern) in covering {
+        uncovered = uncovered
+            .iter()
+            .flat_map(|piece| subtract(piece, &[pattern]))
+            .collect();
+        if uncovered.len() > MAX_UNCOVERED {
+            return None;
+        }
+    }
+    Some(uncovered)
+}
+
+/// Returns the parts of `pattern` that none of `others` match, as patterns
+/// that don't overlap each other.
+fn subtract(pattern: &DisjointPattern, others: &[&DisjointPattern]) -> Vec<DisjointPattern> {
+    let mut remaining = vec![DisjointPattern {
+        residual: Vec::new(),
+        ..pattern.clone()
+    }];
+    for other in others {
+        let mut next = Vec::new();
+        for piece in remaining {
+            if piece.and(other).is_none() {
+                next.push(piece);
+                continue;
+            }
+            // split off one piece for every bit `other` fixes that `piece`
+            // doesn't, what's left afterwards is inside `other`
+            let mut inside = piece;
+            for (source, byte, bit, value) in fixed_bits(other) {
+                let bits = bits_mut(&mut inside, &source);
+                bits.extend(byte + 1);
+                if bits.mask[byte] & bit != 0 {
+                    continue;
+                }
+                let mut outside = inside.clone();
+                let outside_bits = bits_mut(&mut outside, &source);
+                outside_bits.mask[byte] |= bit;
+                outside_bits.value[byte] |= !value & bit;
+                next.push(outside);
+
+                let bits = bits_mut(&mut inside, &source);
+                bits.mask[byte] |= bit;
+                bits.value[byte] |= value & bit;
+            }
+        }
+        remaining = next;
+    }
+    remaining
+}
+
+fn fixed_bits(pattern: &DisjointPattern) -> Vec<(BitSource, usize, u8, u8)> {
+    Some((BitSource::Instruction, &pattern.instruction))
+        .into_iter()
+        .chain(
+            pattern
+                .context
+                .iter()
+                .map(|(r, p)| (BitSource::Context(r.clone()), p)),
+        )
+        .flat_map(|(source, bits)| {
+            bits.mask
+                .iter()
+                .zip(bits.value.iter())
+                .enumerate()
+                .flat_map(move |(byte, (mask, value))| {
+                    let source = source.clone();
+                    (0..8)
+                        .map(|bit| 1 << bit)
+                        .filter(move |bit| mask & bit != 0)
+                        .map(move |bit| (source.clone(), byte, bit, *value))
+                })
+        })
+        .collect()
+}
+
+fn bits_mut<'p>(pattern: &'p mut DisjointPattern, source: &BitSource) -> &'p mut BitPattern {
+    match source {
+        BitSource::Instruction => &mut pattern.instruction,
+        BitSource::Context(register) => pattern.context.entry(register.clone()).or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SleighParser, Spec};
+
+    #[test]
+    fn test_analyze_patterns() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define token instr(8)
+    op = (4,7)
+    sub = (2,3)
+    reg = (0,1)
+;
+
+:a is op=1 { }
+:b is op=1 & sub=0 { }
+:c is op=1 & sub!=0 { }
+:d is op=2 & sub=1 { }
+:e is op=2 & reg=1 { }
+:f is op=3 & op=4 { }
+:g is op=0 | op>3 { }
+"#,
+        );
+
+        let diagnostics: Vec<_> = spec
+            .analyze_patterns()
+            .into_iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "constructor can never match in constructor #0",
+                "constructor can never match in constructor #5",
+                "overlaps constructor #4 without either being more specific in constructor #3",
+                "no constructor of `instruction` matches 0010.0.0",
+                "no constructor of `instruction` matches 0010.011",
+                "no constructor of `instruction` matches 001011.0",
+                "no constructor of `instruction` matches 00101111",
+                "no constructor of `instruction` matches 0011....",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_subtable_coverage() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define token instr(8)
+    op = (7,7)
+    m = (6,6)
+    n = (5,5)
+    reg = (0,1)
+;
+attach variables reg [ r0 r1 r2 r3 ];
+
+Src: reg is m=0 & reg { export reg; }
+Src: "imm"^reg is m=1 & reg { export *[const]:4 reg; }
+Dst: reg is n=0 & reg { export reg; }
+
+:mov Src is op=0 & Src { }
+:st Dst is op=1 & Dst { }
+"#,
+        );
+
+        let diagnostics: Vec<_> = spec
+            .analyze_patterns()
+            .into_iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "no constructor of `Dst` matches ..1.....",
+                "no constructor of `instruction` matches 1.......",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uncompiled() {
+        let source = r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define token instr(8)
+    op = (4,7)
+;
+
+:a is op=1 { }
+:b is op=1 | op=2 { }
+"#;
+        let spec = SleighParser::parse_file(source);
+        assert!(spec.constructors.iter().all(|c| c.pattern.is_none()));
+        assert!(spec.analyze_patterns().is_empty());
+        assert!(spec.table_encodings("instruction").is_empty());
+        assert!(spec.diff(&SleighParser::parse_file(source)).is_empty());
+        assert!(spec.diff(&Spec::parse(source)).is_empty());
+    }
+}
@@ -0,0 +1,505 @@
+use super::{and_constraints, *};
+use std::ops::RangeInclusive;
+
+/// Builds a `Spec` from Rust code instead of SLEIGH source.
+///
+/// `build` expands macros and compiles the decision tree like `Spec::parse`,
+/// and also runs `Spec::validate` and `Spec::analyze_patterns`, returning
+/// their diagnostics on failure. Uncovered encodings aren't failures.
+pub struct SpecBuilder {
+    spec: Spec,
+    attachments: Vec<(Vec<String>, FieldMeaning)>,
+    constructors: Vec<ConstructorBuilder>,
+}
+
+impl SpecBuilder {
+    pub fn new(endianness: Endianness) -> Self {
+        SpecBuilder {
+            spec: Spec {
+                endianness,
+                alignment: 1,
+                spaces: Vec::new(),
+                registers: Vec::new(),
+                tokens: Vec::new(),
+                contexts: Vec::new(),
+                pcodeops: Vec::new(),
+                constructors: Vec::new(),
+                macros: Vec::new(),
+                compiler_spec: None,
+                decision_tree: DecisionTree::default(),
+            },
+            attachments: Vec::new(),
+            constructors: Vec::new(),
+        }
+    }
+
+    pub fn alignment(mut self, alignment: u8) -> Self {
+        self.spec.alignment = alignment;
+        self
+    }
+
+    pub fn space(mut self, name: &str, ty: SpaceType, size: u8, default: bool) -> Self {
+        self.spec.spaces.push(Space {
+            name: name.to_string(),
+            ty,
+            size,
+            default,
+            wordsize: 1,
+        });
+        self
+    }
+
+    /// Defines consecutive registers of `size` bytes starting at `offset`.
+    /// Names of `_` skip a slot.
+    pub fn registers(mut self, offset: u32, size: u16, names: &[&str]) -> Self {
+        for (i, name) in names.iter().enumerate() {
+            if *name != "_" {
+                self.spec.registers.push(Register {
+                    name: name.to_string(),
+                    offset: offset + i as u32 * size as u32,
+                    size,
+                });
+            }
+        }
+        self
+    }
+
+    pub fn token(mut self, token: TokenBuilder) -> Self {
+        self.spec.tokens.push(token.token);
+        self
+    }
+
+    pub fn context(mut self, context: ContextBuilder) -> Self {
+        self.spec.contexts.push(context.context);
+        self
+    }
+
+    pub fn pcodeop(mut self, name: &str) -> Self {
+        self.spec.pcodeops.push(PCodeOp {
+            name: name.to_string(),
+        });
+        self
+    }
+
+    pub fn attach_variables(self, fields: &[&str], variables: &[&str]) -> Self {
+        let variables = variables.iter().map(|v| v.to_string()).collect();
+        self.attach(fields, FieldMeaning::Variables(variables))
+    }
+
+    pub fn attach_values(self, fields: &[&str], values: &[Option<u128>]) -> Self {
+        self.attach(fields, FieldMeaning::Values(values.to_vec()))
+    }
+
+    pub fn attach_names(self, fields: &[&str], names: &[&str]) -> Self {
+        let names = names.iter().map(|n| n.to_string()).collect();
+        self.attach(fields, FieldMeaning::Names(names))
+    }
+
+    fn attach(mut self, fields: &[&str], meaning: FieldMeaning) -> Self {
+        let fields = fields.iter().map(|f| f.to_string()).collect();
+        self.attachments.push((fields, meaning));
+        self
+    }
+
+    pub fn define_macro(mut self, name: &str, args: &[&str], actions: Vec<Action>) -> Self {
+        self.spec.macros.push(Macro {
+            name: name.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            actions,
+        });
+        self
+    }
+
+    pub fn constructor(mut self, constructor: ConstructorBuilder) -> Self {
+        self.constructors.push(constructor);
+        self
+    }
+
+    pub fn build(self) -> Result<Spec, Vec<Diagnostic>> {
+        let SpecBuilder {
+            mut spec,
+            attachments,
+            constructors,
+        } = self;
+
+        for (fields, meaning) in attachments {
+            for field in fields {
+                spec.set_meaning(&field, meaning.clone());
+            }
+        }
+
+        let mut operands = ResolveOperands(&spec.tokens);
+        let constructors = constructors
+            .into_iter()
+            .map(|builder| {
+                let mut constructor = builder.constructor;
+                operands.visit_constraint_mut(&mut constructor.constraint);
+                constructor
+            })
+            .collect();
+        spec.constructors = constructors;
+
+        spec.expand_macros();
+        spec.infer_sizes();
+        let diagnostics = spec.validate();
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        spec.compile_patterns();
+        // a table doesn't have to cover every encoding, so gaps aren't errors
+        let diagnostics: Vec<_> = spec
+            .analyze_patterns()
+            .into_iter()
+            .filter(|d| !matches!(d.kind, DiagnosticKind::UncoveredEncoding { .. }))
+            .collect();
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        spec.build_decision_tree();
+        Ok(spec)
+    }
+}
+
+impl Spec {
+    fn set_meaning(&mut self, field: &str, meaning: FieldMeaning) {
+        if let Some(field) = self
+            .tokens
+            .iter_mut()
+            .flat_map(|token| token.fields.iter_mut())
+            .find(|f| f.name == field)
+        {
+            field.meaning = meaning;
+        } else if let Some(field) = self
+            .contexts
+            .iter_mut()
+            .flat_map(|context| context.fields.iter_mut())
+            .find(|f| f.name == field)
+        {
+            field.meaning = meaning;
+        }
+    }
+}
+
+/// `ConstructorBuilder::operand` can't tell token fields from tables until
+/// all tokens are known, so operands start out as `Constraint::Exists` and are
+/// turned into `Constraint::Constructor` here, the same way the parser does.
+struct ResolveOperands<'a>(&'a [Token]);
+
+impl VisitMut for ResolveOperands<'_> {
+    fn visit_constraint_mut(&mut self, constraint: &mut Constraint) {
+        if let Constraint::Exists(inner) = constraint {
+            let is_field = self
+                .0
+                .iter()
+                .flat_map(|t| t.fields.iter())
+                .any(|f| f.name == inner.name);
+            if !is_field {
+                *constraint = Constraint::Constructor(ConstraintConstructor {
+                    name: inner.name.clone(),
+                    span: inner.span,
+                });
+            }
+        } else {
+            walk_constraint_mut(self, constraint);
+        }
+    }
+}
+
+pub struct TokenBuilder {
+    token: Token,
+}
+
+impl TokenBuilder {
+    pub fn new(name: &str, size: u16) -> Self {
+        TokenBuilder {
+            token: Token {
+                name: name.to_string(),
+                size,
+                endianness: None,
+                fields: Vec::new(),
+            },
+        }
+    }
+
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.token.endianness = Some(endianness);
+        self
+    }
+
+    pub fn field(self, name: &str, bits: RangeInclusive<u16>) -> Self {
+        self.push_field(name, bits, false)
+    }
+
+    pub fn signed_field(self, name: &str, bits: RangeInclusive<u16>) -> Self {
+        self.push_field(name, bits, true)
+    }
+
+    fn push_field(mut self, name: &str, bits: RangeInclusive<u16>, signed: bool) -> Self {
+        self.token.fields.push(TokenField {
+            name: name.to_string(),
+            range: *bits.start()..*bits.end(),
+            signed,
+            display: FieldDisplay::Default,
+            meaning: FieldMeaning::Default,
+        });
+        self
+    }
+}
+
+pub struct ContextBuilder {
+    context: Context,
+}
+
+impl ContextBuilder {
+    pub fn new(register: &str) -> Self {
+        ContextBuilder {
+            context: Context {
+                register: register.to_string(),
+                fields: Vec::new(),
+            },
+        }
+    }
+
+    pub fn field(self, name: &str, bits: RangeInclusive<u16>) -> Self {
+        self.push_field(name, bits, false, true)
+    }
+
+    pub fn signed_field(self, name: &str, bits: RangeInclusive<u16>) -> Self {
+        self.push_field(name, bits, true, true)
+    }
+
+    pub fn noflow_field(self, name: &str, bits: RangeInclusive<u16>) -> Self {
+        self.push_field(name, bits, false, false)
+    }
+
+    fn push_field(
+        mut self,
+        name: &str,
+        bits: RangeInclusive<u16>,
+        signed: bool,
+        flow: bool,
+    ) -> Self {
+        self.context.fields.push(ContextField {
+            name: name.to_string(),
+            range: *bits.start()..*bits.end(),
+            signed,
+            display: FieldDisplay::Default,
+            flow,
+            meaning: FieldMeaning::Default,
+        });
+        self
+    }
+}
+
+pub struct ConstructorBuilder {
+    constructor: Constructor,
+}
+
+impl ConstructorBuilder {
+    /// A constructor of `table` with the display section `display`, e.g.
+    /// `"add dst, src"`.
+    pub fn new(table: &str, display: &str) -> Self {
+        ConstructorBuilder {
+            constructor: Constructor {
+                header: TableHeader {
+                    table: table.to_string(),
+                    mnemonic: format!("{} ", display),
+                },
+                constraint: Constraint::Exists(ConstraintExists {
+                    name: "epsilon".to_string(),
+                    span: Span::default(),
+                }),
+                calculations: Vec::new(),
+                actions: Vec::new(),
+                pattern: None,
+                span: Span::default(),
+            },
+        }
+    }
+
+    /// A constructor of the root `instruction` table.
+    pub fn instruction(display: &str) -> Self {
+        Self::new("instruction", display)
+    }
+
+    /// Adds `constraint` to the constraints of this constructor with `&`.
+    pub fn constraint(mut self, constraint: Constraint) -> Self {
+        let previous = replace(
+            &mut self.constructor.constraint,
+            Constraint::Exists(ConstraintExists {
+                name: String::new(),
+                span: Span::default(),
+            }),
+        );
+        self.constructor.constraint = match previous {
+            Constraint::Exists(inner) if inner.name == "epsilon" => constraint,
+            previous => and_constraints(previous, constraint),
+        };
+        self
+    }
+
+    /// Requires token field `field` to equal `value`.
+    pub fn field_eq(self, field: &str, value: i128) -> Self {
+        self.constraint(Constraint::Comparison(ConstraintComparison {
+            lhs: ConstraintRValue::Field(field.to_string()),
+            num_type: NumTypePrefix::Default,
+            comparison: ComparisonOperator::Equal,
+            rhs: ConstraintRValue::Integer(value),
+            span: Span::default(),
+        }))
+    }
+
+    /// References a token field or a subtable by name.
+    pub fn operand(self, name: &str) -> Self {
+        self.constraint(Constraint::Exists(ConstraintExists {
+            name: name.to_string(),
+            span: Span::default(),
+        }))
+    }
+
+    pub fn calculation(mut self, calculation: Calculation) -> Self {
+        self.constructor.calculations.push(calculation);
+        self
+    }
+
+    pub fn action(mut self, action: Action) -> Self {
+        self.constructor.actions.push(action);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ident(name: &str) -> LValue {
+        LValue::Ident(LValueIdent {
+            field: name.to_string(),
+            size: None,
+            span: Span::default(),
+        })
+    }
+
+    fn constant(value: i128) -> RValue {
+        RValue::Constant(RValueConstant {
+            value,
+            size: None,
+            span: Span::default(),
+        })
+    }
+
+    #[test]
+    fn test_builder() {
+        let parsed = Spec::parse(
+            r#"
+define endian=big;
+define space ram type=ram_space size=2 default;
+define register offset=0 size=1 [ a _ x ];
+define token instr(8)
+    op = (4,7)
+    reg = (0,1)
+;
+attach variables reg [ a x _ _ ];
+
+macro bump(r) {
+    r = r + 1;
+}
+
+src:reg is reg { export reg; }
+
+:inc src is op=1 & src { bump(src); }
+"#,
+        );
+
+        let bump = Action::Assignment(ActionAssignment {
+            name: ident("r"),
+            val: RValueAdd {
+                lhs: RValue::LValue(ident("r")),
+                num_type_prefix: NumTypePrefix::Default,
+                rhs: RValue::Constant(RValueConstant {
+                    value: 1,
+                    size: None,
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            }
+            .into(),
+            span: Span::default(),
+        });
+        let builder = SpecBuilder::new(Endianness::Big)
+            .space("ram", SpaceType::Ram, 2, true)
+            .registers(0, 1, &["a", "_", "x"])
+            .token(
+                TokenBuilder::new("instr", 8)
+                    .field("op", 4..=7)
+                    .field("reg", 0..=1),
+            )
+            .attach_variables(&["reg"], &["a", "x", "_", "_"])
+            .define_macro("bump", &["r"], vec![bump])
+            .constructor(ConstructorBuilder::new("src", "reg").operand("reg").action(
+                Action::Export(ActionExport {
+                    op: RValue::LValue(ident("reg")),
+                    span: Span::default(),
+                }),
+            ))
+            .constructor(
+                ConstructorBuilder::instruction("inc src")
+                    .field_eq("op", 1)
+                    .operand("src")
+                    .action(Action::Macro(ActionMacro {
+                        r#macro: "bump".to_string(),
+                        args: vec![RValue::LValue(ident("src"))],
+                        span: Span::default(),
+                    })),
+            );
+        let built = builder.build().unwrap_or_else(|diagnostics| {
+            panic!("{}", diagnostics[0]);
+        });
+        assert!(built == parsed);
+
+        let invalid = SpecBuilder::new(Endianness::Little)
+            .constructor(ConstructorBuilder::instruction("nop").operand("missing"))
+            .build();
+        match invalid {
+            Err(diagnostics) => assert_eq!(
+                diagnostics[0].to_string(),
+                "undefined symbol `missing` in constructor #0"
+            ),
+            Ok(_) => panic!("expected a diagnostic"),
+        }
+
+        let arity = SpecBuilder::new(Endianness::Little)
+            .token(TokenBuilder::new("instr", 8).field("op", 0..=7))
+            .define_macro("m", &["a"], Vec::new())
+            .constructor(
+                ConstructorBuilder::instruction("nop")
+                    .field_eq("op", 0)
+                    .action(Action::Macro(ActionMacro {
+                        r#macro: "m".to_string(),
+                        args: vec![constant(1), constant(2)],
+                        span: Span::default(),
+                    })),
+            )
+            .build();
+        match arity {
+            Err(diagnostics) => assert_eq!(
+                diagnostics[0].to_string(),
+                "macro `m` takes 1 arguments, found 2 in constructor #0"
+            ),
+            Ok(_) => panic!("expected a diagnostic"),
+        }
+
+        let ambiguous = SpecBuilder::new(Endianness::Little)
+            .token(TokenBuilder::new("instr", 8).field("op", 0..=7))
+            .constructor(ConstructorBuilder::instruction("a").field_eq("op", 1))
+            .constructor(ConstructorBuilder::instruction("b").field_eq("op", 1))
+            .build();
+        match ambiguous {
+            Err(diagnostics) => assert!(matches!(
+                diagnostics[0].kind,
+                DiagnosticKind::AmbiguousConstructors { .. }
+            )),
+            Ok(_) => panic!("expected a diagnostic"),
+        }
+    }
+}
@@ -0,0 +1,1028 @@
+use super::*;
+use std::{collections::BTreeMap, fmt::Write};
+
+impl Spec {
+    /// Generates Rust source for a decoder specialized to this spec. A build
+    /// script can write it to `OUT_DIR` and `include!` it, so that decoding
+    /// doesn't interpret constraints at runtime.
+    ///
+    /// The generated `decode` picks the same constructor as
+    /// `State::match_constructor`, and `Instruction::operand` returns the same
+    /// values as `State::operand_value`. The source depends only on `std`.
+    pub fn generate_decoder(&self) -> String {
+        let mut codegen = Codegen::new(self);
+        codegen.spec();
+        codegen.out
+    }
+}
+
+const ALLOW: &str =
+    "#[allow(dead_code, unused_variables, unused_parens, unreachable_code, clippy::all)]";
+
+const HELPERS: &str = r#"
+#[allow(dead_code, clippy::all)]
+fn read(data: &[u8], len: usize, start: u32, end: u32, big_endian: bool) -> Option<i128> {
+    let bytes = data.get(..len)?;
+    let value = if big_endian {
+        bytes.iter().fold(0, |v, b| v << 8 | *b as i128)
+    } else {
+        bytes.iter().rev().fold(0, |v, b| v << 8 | *b as i128)
+    };
+    let bits = end - start + 1;
+    Some(value >> start & ((1 << bits) - 1))
+}
+
+#[allow(dead_code, clippy::all)]
+fn decode_field(raw: i128, start: u32, end: u32, signed: bool) -> i128 {
+    let bits = end - start + 1;
+    if signed && raw >> (bits - 1) != 0 {
+        raw - (1 << bits)
+    } else {
+        raw
+    }
+}
+
+#[allow(dead_code, clippy::all)]
+fn set_bits(data: &mut [u8], start: usize, end: usize, value: i128, big_endian: bool) {
+    for i in start..=end {
+        let offset = if big_endian { data.len() - i / 8 - 1 } else { i / 8 };
+        if value >> (i - start) & 1 != 0 {
+            data[offset] |= 1 << (i % 8);
+        } else {
+            data[offset] &= !(1 << (i % 8));
+        }
+    }
+}
+
+#[allow(dead_code, clippy::all)]
+fn bits(data: &[u8], mask: &[u8], value: &[u8]) -> bool {
+    mask.iter()
+        .zip(value.iter())
+        .enumerate()
+        .all(|(i, (mask, value))| match data.get(i) {
+            Some(b) => b & mask == *value,
+            None => *mask == 0,
+        })
+}
+
+#[allow(dead_code, clippy::all)]
+fn extract(value: u8, mask: u8) -> usize {
+    let mut index = 0;
+    let mut n = 0;
+    for bit in 0..8 {
+        if mask >> bit & 1 != 0 {
+            index |= ((value >> bit & 1) as usize) << n;
+            n += 1;
+        }
+    }
+    index
+}
+"#;
+
+/// Where the value of a field name comes from, the first definition wins
+/// like in `State::field_value`.
+enum FieldSource {
+    /// Index of the generated reader and the byte length of its token.
+    Token(usize, usize),
+    Context(usize),
+}
+
+struct Codegen<'s> {
+    spec: &'s Spec,
+    out: String,
+    tables: BTreeMap<&'s str, usize>,
+    fields: HashMap<&'s str, FieldSource>,
+    /// Context registers that are defined, by name, with their size.
+    registers: BTreeMap<&'s str, (usize, usize)>,
+    fresh: usize,
+}
+
+impl<'s> Codegen<'s> {
+    fn new(spec: &'s Spec) -> Self {
+        let mut tables = BTreeMap::new();
+        for constructor in spec.constructors.iter() {
+            tables.insert(constructor.header.table.as_str(), 0);
+        }
+        for (i, index) in tables.values_mut().enumerate() {
+            *index = i;
+        }
+
+        let mut registers = BTreeMap::new();
+        for context in spec.contexts.iter() {
+            if let Some(register) = spec.registers.iter().find(|r| r.name == context.register) {
+                let index = registers.len();
+                registers
+                    .entry(context.register.as_str())
+                    .or_insert((index, register.size as usize));
+            }
+        }
+
+        let mut fields = HashMap::new();
+        let token_fields = spec
+            .tokens
+            .iter()
+            .flat_map(|t| t.fields.iter().map(move |f| (t, f)));
+        for (i, (token, field)) in token_fields.enumerate() {
+            let len = token.size as usize / 8;
+            fields
+                .entry(field.name.as_str())
+                .or_insert(FieldSource::Token(i, len));
+        }
+        let context_fields = spec.contexts.iter().flat_map(|c| c.fields.iter());
+        for (i, field) in context_fields.enumerate() {
+            fields
+                .entry(field.name.as_str())
+                .or_insert(FieldSource::Context(i));
+        }
+
+        Codegen {
+            spec,
+            out: String::new(),
+            tables,
+            fields,
+            registers,
+            fresh: 0,
+        }
+    }
+
+    fn spec(&mut self) {
+        writeln!(
+            self.out,
+            "// Generated by `Spec::generate_decoder`, do not edit."
+        )
+        .unwrap();
+        self.out += HELPERS;
+        self.context();
+        self.instruction();
+        self.field_readers();
+        for (i, constructor) in self.spec.constructors.iter().enumerate() {
+            self.constructor(i, constructor);
+        }
+        let tables: Vec<_> = self.tables.iter().map(|(t, i)| (*t, *i)).collect();
+        for (table, index) in tables {
+            self.table(table, index);
+        }
+    }
+
+    fn context(&mut self) {
+        let spec = self.spec;
+        writeln!(self.out, "\n#[derive(Clone, Debug)]\npub struct Context {{").unwrap();
+        for (index, size) in self.registers.values() {
+            writeln!(self.out, "    r{}: [u8; {}],", index, size).unwrap();
+        }
+        writeln!(self.out, "}}\n").unwrap();
+
+        writeln!(
+            self.out,
+            "impl Context {{\n    pub fn new() -> Self {{\n        Context {{"
+        )
+        .unwrap();
+        for (index, size) in self.registers.values() {
+            writeln!(self.out, "            r{}: [0; {}],", index, size).unwrap();
+        }
+        writeln!(self.out, "        }}\n    }}\n").unwrap();
+
+        writeln!(
+            self.out,
+            "    /// Sets the context field `name` to `value`."
+        )
+        .unwrap();
+        writeln!(self.out, "    {}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "    pub fn set(&mut self, name: &str, value: i128) {{"
+        )
+        .unwrap();
+        writeln!(self.out, "        match name {{").unwrap();
+        let mut names: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for context in spec.contexts.iter() {
+            let register = match self.registers.get(context.register.as_str()) {
+                Some((index, _)) => *index,
+                None => continue,
+            };
+            for field in context.fields.iter() {
+                names.entry(&field.name).or_default().push(format!(
+                    "set_bits(&mut self.r{}, {}, {}, value, {})",
+                    register,
+                    field.range.start,
+                    field.range.end,
+                    spec.endianness == Endianness::Big
+                ));
+            }
+        }
+        for (name, statements) in names {
+            writeln!(self.out, "            {:?} => {{", name).unwrap();
+            for statement in statements {
+                writeln!(self.out, "                {};", statement).unwrap();
+            }
+            writeln!(self.out, "            }}").unwrap();
+        }
+        writeln!(self.out, "            _ => {{}}\n        }}\n    }}\n}}\n").unwrap();
+
+        writeln!(
+            self.out,
+            "impl Default for Context {{\n    fn default() -> Self {{\n        Context::new()\n    }}\n}}"
+        )
+        .unwrap();
+    }
+
+    fn instruction(&mut self) {
+        let spec = self.spec;
+        writeln!(
+            self.out,
+            "\n#[derive(Clone, Copy, Debug, PartialEq, Eq)]\npub struct Instruction {{"
+        )
+        .unwrap();
+        writeln!(self.out, "    /// Index of the matched constructor.").unwrap();
+        writeln!(self.out, "    pub constructor: usize,").unwrap();
+        writeln!(self.out, "    /// The number of bytes matched, if known.").unwrap();
+        writeln!(self.out, "    pub len: Option<usize>,\n}}\n").unwrap();
+
+        let tables: Vec<_> = spec
+            .constructors
+            .iter()
+            .map(|c| format!("{:?}", c.header.table))
+            .collect();
+        let mnemonics: Vec<_> = spec
+            .constructors
+            .iter()
+            .map(|c| format!("{:?}", c.header.mnemonic.trim()))
+            .collect();
+        writeln!(
+            self.out,
+            "const TABLES: [&str; {}] = [{}];",
+            tables.len(),
+            tables.join(", ")
+        )
+        .unwrap();
+        writeln!(
+            self.out,
+            "const MNEMONICS: [&str; {}] = [{}];\n",
+            mnemonics.len(),
+            mnemonics.join(", ")
+        )
+        .unwrap();
+
+        writeln!(self.out, "impl Instruction {{").unwrap();
+        writeln!(
+            self.out,
+            "    pub fn table(&self) -> &'static str {{\n        TABLES[self.constructor]\n    }}\n"
+        )
+        .unwrap();
+        writeln!(
+            self.out,
+            "    pub fn mnemonic(&self) -> &'static str {{\n        MNEMONICS[self.constructor]\n    }}\n"
+        )
+        .unwrap();
+        writeln!(
+            self.out,
+            "    /// The value of field `name` as this constructor sees it."
+        )
+        .unwrap();
+        writeln!(
+            self.out,
+            "    pub fn operand(&self, name: &str, code: &[u8], context: &Context) -> Option<i128> {{"
+        )
+        .unwrap();
+        writeln!(self.out, "        match self.constructor {{").unwrap();
+        for i in 0..spec.constructors.len() {
+            writeln!(
+                self.out,
+                "            {} => operand_{}(name, code, context),",
+                i, i
+            )
+            .unwrap();
+        }
+        writeln!(self.out, "            _ => None,\n        }}\n    }}\n}}\n").unwrap();
+
+        writeln!(self.out, "{}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "pub fn decode(code: &[u8], context: &Context) -> Option<Instruction> {{"
+        )
+        .unwrap();
+        match self.tables.get("instruction") {
+            Some(index) => {
+                writeln!(
+                    self.out,
+                    "    let constructor = table_{}(code, context)?;",
+                    index
+                )
+                .unwrap();
+                writeln!(
+                    self.out,
+                    "    let len = constructor_len(constructor, code, context);"
+                )
+                .unwrap();
+                writeln!(self.out, "    Some(Instruction {{ constructor, len }})").unwrap();
+            }
+            None => writeln!(self.out, "    None").unwrap(),
+        }
+        writeln!(self.out, "}}\n").unwrap();
+
+        writeln!(self.out, "{}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "fn constructor_len(constructor: usize, code: &[u8], ctx: &Context) -> Option<usize> {{"
+        )
+        .unwrap();
+        writeln!(self.out, "    match constructor {{").unwrap();
+        for i in 0..spec.constructors.len() {
+            writeln!(self.out, "        {} => len_{}(code, ctx),", i, i).unwrap();
+        }
+        writeln!(self.out, "        _ => None,\n    }}\n}}\n").unwrap();
+    }
+
+    fn field_readers(&mut self) {
+        let spec = self.spec;
+        let token_fields = spec
+            .tokens
+            .iter()
+            .flat_map(|t| t.fields.iter().map(move |f| (t, f)));
+        for (i, (token, field)) in token_fields.enumerate() {
+            writeln!(self.out, "{}", ALLOW).unwrap();
+            writeln!(
+                self.out,
+                "fn field_{}(code: &[u8]) -> Option<i128> {{\n    read(code, {}, {}, {}, {}).map(|raw| decode_field(raw, {}, {}, {}))\n}}\n",
+                i,
+                token.size / 8,
+                field.range.start,
+                field.range.end,
+                spec.token_endianness(token) == Endianness::Big,
+                field.range.start,
+                field.range.end,
+                field.signed,
+            )
+            .unwrap();
+        }
+
+        let context_fields = spec
+            .contexts
+            .iter()
+            .flat_map(|c| c.fields.iter().map(move |f| (c, f)));
+        for (i, (context, field)) in context_fields.enumerate() {
+            writeln!(self.out, "{}", ALLOW).unwrap();
+            writeln!(
+                self.out,
+                "fn context_{}(ctx: &Context) -> Option<i128> {{",
+                i
+            )
+            .unwrap();
+            match self.registers.get(context.register.as_str()) {
+                Some((index, size)) => writeln!(
+                    self.out,
+                    "    read(&ctx.r{}, {}, {}, {}, {}).map(|raw| decode_field(raw, {}, {}, {}))",
+                    index,
+                    size,
+                    field.range.start,
+                    field.range.end,
+                    spec.endianness == Endianness::Big,
+                    field.range.start,
+                    field.range.end,
+                    field.signed,
+                )
+                .unwrap(),
+                None => writeln!(self.out, "    None").unwrap(),
+            }
+            writeln!(self.out, "}}\n").unwrap();
+        }
+
+        // reads any field at the start of `code`
+        writeln!(self.out, "{}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "fn field_value(name: &str, code: &[u8], ctx: &Context) -> Option<i128> {{\n    match name {{"
+        )
+        .unwrap();
+        let mut names: Vec<_> = self.fields.keys().copied().collect();
+        names.sort_unstable();
+        for name in names {
+            let value = self.field_value(name, "code");
+            writeln!(self.out, "        {:?} => {},", name, value).unwrap();
+        }
+        writeln!(self.out, "        _ => None,\n    }}\n}}\n").unwrap();
+    }
+
+    /// An `Option<i128>` expression reading field `name` from the token
+    /// starting at `code`.
+    fn field_value(&self, name: &str, code: &str) -> String {
+        match self.fields.get(name) {
+            Some(FieldSource::Token(i, _)) => format!("field_{}({})", i, code),
+            Some(FieldSource::Context(i)) => format!("context_{}(ctx)", i),
+            None => "None::<i128>".to_string(),
+        }
+    }
+
+    fn constructor(&mut self, i: usize, constructor: &Constructor) {
+        if let Some(pattern) = &constructor.pattern {
+            for (a, alternative) in pattern.alternatives.iter().enumerate() {
+                let matches = self.alternative(alternative);
+                writeln!(self.out, "{}", ALLOW).unwrap();
+                writeln!(
+                    self.out,
+                    "fn alt_{}_{}(code: &[u8], ctx: &Context) -> bool {{\n    {}\n}}\n",
+                    i, a, matches
+                )
+                .unwrap();
+            }
+        }
+
+        let matches = match &constructor.pattern {
+            Some(pattern) => {
+                let alternatives: Vec<_> = (0..pattern.alternatives.len())
+                    .map(|a| format!("alt_{}_{}(code, ctx)", i, a))
+                    .collect();
+                if alternatives.is_empty() {
+                    "false".to_string()
+                } else {
+                    alternatives.join(" || ")
+                }
+            }
+            None => self.matches(&constructor.constraint, "0"),
+        };
+        writeln!(self.out, "{}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "fn matches_{}(code: &[u8], ctx: &Context) -> bool {{\n    {}\n}}\n",
+            i, matches
+        )
+        .unwrap();
+
+        let len = self.len(&constructor.constraint, "0");
+        writeln!(self.out, "{}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "fn len_{}(code: &[u8], ctx: &Context) -> Option<usize> {{\n    {}\n}}\n",
+            i, len
+        )
+        .unwrap();
+
+        // fields the constraint mentions may be read from a later token
+        let mut names = Idents::default();
+        names.visit_constraint(&constructor.constraint);
+        let mut names = names.0;
+        names.retain(|name| self.fields.contains_key(name.as_str()));
+        names.sort_unstable();
+        names.dedup();
+
+        writeln!(self.out, "{}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "fn operand_{}(name: &str, code: &[u8], ctx: &Context) -> Option<i128> {{\n    match name {{",
+            i
+        )
+        .unwrap();
+        for name in names.iter() {
+            let value = self.field_value(name, "code");
+            match self.field_offset(&constructor.constraint, name, "0") {
+                Some(offset) => writeln!(
+                    self.out,
+                    "        {:?} => match {} {{\n            Some(offset) => {{\n                let code = code.get(offset..)?;\n                {}\n            }}\n            None => {},\n        }},",
+                    name, offset, value, value
+                )
+                .unwrap(),
+                None => writeln!(self.out, "        {:?} => {},", name, value).unwrap(),
+            }
+        }
+        writeln!(
+            self.out,
+            "        _ => field_value(name, code, ctx),\n    }}\n}}\n"
+        )
+        .unwrap();
+    }
+
+    fn table(&mut self, table: &str, index: usize) {
+        let spec = self.spec;
+        writeln!(self.out, "{}", ALLOW).unwrap();
+        writeln!(
+            self.out,
+            "fn table_{}(code: &[u8], ctx: &Context) -> Option<usize> {{",
+            index
+        )
+        .unwrap();
+        match spec.decision_tree.table(table) {
+            Some(node) => {
+                let body = self.decision_node(node, 1);
+                writeln!(self.out, "    {}", body).unwrap();
+            }
+            None => {
+                // tables with uncompiled constructors are scanned linearly
+                for (i, constructor) in spec.constructors.iter().enumerate() {
+                    if constructor.header.table == table {
+                        writeln!(
+                            self.out,
+                            "    if matches_{}(code, ctx) {{\n        return Some({});\n    }}",
+                            i, i
+                        )
+                        .unwrap();
+                    }
+                }
+                writeln!(self.out, "    None").unwrap();
+            }
+        }
+        writeln!(self.out, "}}\n").unwrap();
+    }
+
+    fn decision_node(&mut self, node: &DecisionNode, depth: usize) -> String {
+        let indent = "    ".repeat(depth);
+        match node {
+            DecisionNode::Leaf(candidates) => self.leaf(candidates, &indent),
+            DecisionNode::Branch {
+                source,
+                byte,
+                mask,
+                children,
+            } => {
+                let data = match source {
+                    BitSource::Instruction => format!("code.get({})", byte),
+                    BitSource::Context(register) => match self.registers.get(register.as_str()) {
+                        Some((index, _)) => format!("ctx.r{}.get({})", index, byte),
+                        None => "None::<&u8>".to_string(),
+                    },
+                };
+                let mut s = format!(
+                    "match extract({}.copied().unwrap_or_default(), {:#04x}) {{\n",
+                    data, mask
+                );
+                for (i, child) in children.iter().enumerate() {
+                    let child = self.decision_node(child, depth + 1);
+                    writeln!(s, "{}    {} => {}", indent, i, child).unwrap();
+                }
+                write!(s, "{}    _ => unreachable!(),\n{}}}", indent, indent).unwrap();
+                s
+            }
+        }
+    }
+
+    /// Picks the candidate `State::resolve_constructor` would: the lowest
+    /// constructor index among the matches no other match is more specific
+    /// than.
+    fn leaf(&mut self, candidates: &[(usize, usize)], indent: &str) -> String {
+        let spec = self.spec;
+        if candidates.is_empty() {
+            return "None,".to_string();
+        }
+        let mut s = String::from("{\n");
+        let matches: Vec<_> = candidates
+            .iter()
+            .map(|(c, a)| format!("alt_{}_{}(code, ctx)", c, a))
+            .collect();
+        writeln!(s, "{}    let m = [{}];", indent, matches.join(", ")).unwrap();
+
+        let mut order: Vec<_> = (0..candidates.len()).collect();
+        order.sort_by_key(|i| candidates[*i].0);
+        for i in order {
+            let pattern = spec.alternative(candidates[i]);
+            let more_specific: Vec<_> = (0..candidates.len())
+                .filter(|j| spec.alternative(candidates[*j]).specializes(pattern))
+                .map(|j| format!("!m[{}]", j))
+                .collect();
+            let mut condition = format!("m[{}]", i);
+            for other in more_specific {
+                condition = format!("{} && {}", condition, other);
+            }
+            writeln!(
+                s,
+                "{}    if {} {{\n{}        return Some({});\n{}    }}",
+                indent, condition, indent, candidates[i].0, indent
+            )
+            .unwrap();
+        }
+        write!(s, "{}    None\n{}}}", indent, indent).unwrap();
+        s
+    }
+
+    /// A `bool` expression for `DisjointPattern::matches`.
+    fn alternative(&mut self, alternative: &DisjointPattern) -> String {
+        let mut checks = Vec::new();
+        if alternative.instruction.mask.iter().any(|m| *m != 0) {
+            checks.push(format!(
+                "bits(code, &{:?}, &{:?})",
+                alternative.instruction.mask, alternative.instruction.value
+            ));
+        }
+        for (register, pattern) in alternative.context.iter() {
+            match self.registers.get(register.as_str()) {
+                Some((index, _)) => checks.push(format!(
+                    "bits(&ctx.r{}, &{:?}, &{:?})",
+                    index, pattern.mask, pattern.value
+                )),
+                None => checks.push("false".to_string()),
+            }
+        }
+        for (offset, constraint) in alternative.residual.iter() {
+            let matches = self.matches(constraint, &offset.to_string());
+            match offset {
+                0 => checks.push(matches),
+                _ => checks.push(format!("({} <= code.len() && {})", offset, matches)),
+            }
+        }
+        if checks.is_empty() {
+            "true".to_string()
+        } else {
+            checks.join("\n        && ")
+        }
+    }
+
+    fn fresh(&mut self) -> String {
+        self.fresh += 1;
+        format!("v{}", self.fresh)
+    }
+
+    fn is_table(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+    }
+
+    /// An `Option<usize>` expression for the constructor of subtable `name`
+    /// matching at `off`.
+    fn subtable(&self, name: &str, off: &str) -> String {
+        format!(
+            "code.get({}..).and_then(|code| table_{}(code, ctx))",
+            off, self.tables[name]
+        )
+    }
+
+    /// A `bool` expression for `Constraint::matches` with the constraint
+    /// starting `off` bytes into `code`.
+    fn matches(&mut self, constraint: &Constraint, off: &str) -> String {
+        match constraint {
+            Constraint::Ellipsis(inner) => self.matches(&inner.op, off),
+            Constraint::And(inner) => {
+                let lhs = self.matches(&inner.lhs, off);
+                let rhs = self.matches(&inner.rhs, off);
+                format!("({} && {})", lhs, rhs)
+            }
+            Constraint::Or(inner) => {
+                let lhs = self.matches(&inner.lhs, off);
+                let rhs = self.matches(&inner.rhs, off);
+                format!("({} || {})", lhs, rhs)
+            }
+            Constraint::Semi(inner) => {
+                let lhs = self.matches(&inner.lhs, off);
+                let len = self.len(&inner.lhs, off);
+                let v = self.fresh();
+                let rhs_off = format!("({} + {})", off, v);
+                let rhs = self.matches(&inner.rhs, &rhs_off);
+                format!(
+                    "({} && {{ let {} = {}.unwrap_or_default(); {} <= code.len() && {} }})",
+                    lhs, v, len, rhs_off, rhs
+                )
+            }
+            Constraint::Parenthesized(inner) => self.matches(inner, off),
+            Constraint::Comparison(inner) => {
+                let lhs = self.value(&inner.lhs, off);
+                let rhs = self.value(&inner.rhs, off);
+                let operator = match inner.comparison {
+                    ComparisonOperator::Equal => "==",
+                    ComparisonOperator::NotEqual => "!=",
+                    ComparisonOperator::Less => "<",
+                    ComparisonOperator::LessEqual => "<=",
+                    ComparisonOperator::Greater => ">",
+                    ComparisonOperator::GreaterEqual => ">=",
+                };
+                format!(
+                    "matches!(({}).zip({}), Some((l, r)) if l {} r)",
+                    lhs, rhs, operator
+                )
+            }
+            Constraint::Exists(inner) => {
+                if self.is_table(&inner.name) && inner.name != "instruction" {
+                    format!("{}.is_some()", self.subtable(&inner.name, off))
+                } else {
+                    "true".to_string()
+                }
+            }
+            Constraint::Constructor(inner) => {
+                if inner.name != "instruction" && self.is_table(&inner.name) {
+                    format!("{}.is_some()", self.subtable(&inner.name, off))
+                } else {
+                    "false".to_string()
+                }
+            }
+        }
+    }
+
+    /// An `Option<usize>` expression for the length of `constraint`.
+    fn len(&mut self, constraint: &Constraint, off: &str) -> String {
+        match constraint {
+            Constraint::Ellipsis(inner) => self.len(&inner.op, off),
+            Constraint::And(inner) => {
+                let lhs = self.len(&inner.lhs, off);
+                let rhs = self.len(&inner.rhs, off);
+                format!("({}).or({})", lhs, rhs)
+            }
+            Constraint::Or(inner) => {
+                let matches = self.matches(&inner.lhs, off);
+                let lhs = self.len(&inner.lhs, off);
+                let rhs = self.len(&inner.rhs, off);
+                format!("(if {} {{ {} }} else {{ {} }})", matches, lhs, rhs)
+            }
+            Constraint::Semi(inner) => {
+                let lhs = self.len(&inner.lhs, off);
+                let v = self.fresh();
+                let rhs_off = format!("({} + {})", off, v);
+                let rhs = self.len(&inner.rhs, &rhs_off);
+                format!(
+                    "{{ let {} = ({}).unwrap_or_default(); if {} <= code.len() {{ Some({} + ({}).unwrap_or_default()) }} else {{ None }} }}",
+                    v, lhs, rhs_off, v, rhs
+                )
+            }
+            Constraint::Parenthesized(inner) => self.len(inner, off),
+            Constraint::Comparison(inner) => {
+                match self
+                    .value_len(&inner.lhs)
+                    .or_else(|| self.value_len(&inner.rhs))
+                {
+                    Some(len) => format!("Some::<usize>({})", len),
+                    None => "None::<usize>".to_string(),
+                }
+            }
+            Constraint::Exists(inner) => {
+                if self.is_table(&inner.name) {
+                    self.subtable_len(&inner.name, off)
+                } else {
+                    match self.token_len(&inner.name) {
+                        Some(len) => format!("Some::<usize>({})", len),
+                        None => "None::<usize>".to_string(),
+                    }
+                }
+            }
+            Constraint::Constructor(inner) => {
+                if self.is_table(&inner.name) {
+                    self.subtable_len(&inner.name, off)
+                } else {
+                    "None::<usize>".to_string()
+                }
+            }
+        }
+    }
+
+    fn subtable_len(&self, name: &str, off: &str) -> String {
+        format!(
+            "code.get({}..).and_then(|code| constructor_len(table_{}(code, ctx)?, code, ctx))",
+            off, self.tables[name]
+        )
+    }
+
+    fn token_len(&self, name: &str) -> Option<usize> {
+        match self.fields.get(name) {
+            Some(FieldSource::Token(_, len)) => Some(*len),
+            _ => self
+                .spec
+                .tokens
+                .iter()
+                .find(|t| t.fields.iter().any(|f| f.name == name))
+                .map(|t| t.size as usize / 8),
+        }
+    }
+
+    /// The static length `ConstraintRValue::len` computes.
+    fn value_len(&self, rvalue: &ConstraintRValue) -> Option<usize> {
+        match rvalue {
+            ConstraintRValue::Field(name) => self.token_len(name),
+            _ => rvalue
+                .operands()
+                .into_iter()
+                .find_map(|op| self.value_len(op)),
+        }
+    }
+
+    /// An `Option<i128>` expression for `ConstraintRValue::evaluate`.
+    fn value(&mut self, rvalue: &ConstraintRValue, off: &str) -> String {
+        let binary = |codegen: &mut Self, lhs, rhs, op: &str| {
+            let lhs = codegen.value(lhs, off);
+            let rhs = codegen.value(rhs, off);
+            format!("({}).zip({}).and_then(|(l, r)| {})", lhs, rhs, op)
+        };
+        let shift = "<u32 as std::convert::TryFrom<i128>>::try_from(r).ok()?";
+        match rvalue {
+            ConstraintRValue::Add(inner) => {
+                binary(self, &inner.lhs, &inner.rhs, "l.checked_add(r)")
+            }
+            ConstraintRValue::Sub(inner) => {
+                binary(self, &inner.lhs, &inner.rhs, "l.checked_sub(r)")
+            }
+            ConstraintRValue::Mult(inner) => {
+                binary(self, &inner.lhs, &inner.rhs, "l.checked_mul(r)")
+            }
+            ConstraintRValue::Div(inner) => {
+                binary(self, &inner.lhs, &inner.rhs, "l.checked_div(r)")
+            }
+            ConstraintRValue::IntOr(inner) => binary(self, &inner.lhs, &inner.rhs, "Some(l | r)"),
+            ConstraintRValue::IntAnd(inner) => binary(self, &inner.lhs, &inner.rhs, "Some(l & r)"),
+            ConstraintRValue::IntXor(inner) => binary(self, &inner.lhs, &inner.rhs, "Some(l ^ r)"),
+            ConstraintRValue::RShift(inner) => binary(
+                self,
+                &inner.lhs,
+                &inner.rhs,
+                &format!("l.checked_shr({})", shift),
+            ),
+            ConstraintRValue::LShift(inner) => binary(
+                self,
+                &inner.lhs,
+                &inner.rhs,
+                &format!("l.checked_shl({})", shift),
+            ),
+            ConstraintRValue::Neg(inner) => {
+                format!(
+                    "({}).and_then(i128::checked_neg)",
+                    self.value(&inner.op, off)
+                )
+            }
+            ConstraintRValue::Not(inner) => format!("({}).map(|v| !v)", self.value(&inner.op, off)),
+            ConstraintRValue::Parenthesized(inner) => self.value(inner, off),
+            ConstraintRValue::Field(name) => match self.fields.get(name.as_str()) {
+                Some(FieldSource::Token(..)) => {
+                    let code = format!("code.get({}..)", off);
+                    let read = self.field_value(name, "code");
+                    format!("{}.and_then(|code| {})", code, read)
+                }
+                _ => self.field_value(name, "code"),
+            },
+            ConstraintRValue::Integer(value) => format!("Some::<i128>({})", value),
+        }
+    }
+
+    /// An `Option<usize>` expression for `Constraint::field_offset`, `None`
+    /// if the field can't be found in `constraint` at all.
+    fn field_offset(&mut self, constraint: &Constraint, name: &str, off: &str) -> Option<String> {
+        match constraint {
+            Constraint::Ellipsis(inner) => self.field_offset(&inner.op, name, off),
+            Constraint::And(inner) => {
+                let lhs = self.field_offset(&inner.lhs, name, off);
+                let rhs = self.field_offset(&inner.rhs, name, off);
+                match (lhs, rhs) {
+                    (Some(lhs), Some(rhs)) => Some(format!("({}).or({})", lhs, rhs)),
+                    (lhs, rhs) => lhs.or(rhs),
+                }
+            }
+            Constraint::Or(inner) => {
+                let lhs = self.field_offset(&inner.lhs, name, off);
+                let rhs = self.field_offset(&inner.rhs, name, off);
+                if lhs.is_none() && rhs.is_none() {
+                    return None;
+                }
+                let matches = self.matches(&inner.lhs, off);
+                let none = "None::<usize>".to_string();
+                Some(format!(
+                    "(if {} {{ {} }} else {{ {} }})",
+                    matches,
+                    lhs.unwrap_or_else(|| none.clone()),
+                    rhs.unwrap_or(none)
+                ))
+            }
+            Constraint::Semi(inner) => {
+                let lhs = self.field_offset(&inner.lhs, name, off);
+                let v = self.fresh();
+                let rhs_off = format!("({} + {})", off, v);
+                let rhs = self.field_offset(&inner.rhs, name, &rhs_off);
+                let rhs = rhs.map(|rhs| {
+                    let len = self.len(&inner.lhs, off);
+                    format!(
+                        "{{ let {} = ({}).unwrap_or_default(); if {} <= code.len() {{ {} }} else {{ None }} }}",
+                        v, len, rhs_off, rhs
+                    )
+                });
+                match (lhs, rhs) {
+                    (Some(lhs), Some(rhs)) => Some(format!("({}).or_else(|| {})", lhs, rhs)),
+                    (lhs, rhs) => lhs.or(rhs),
+                }
+            }
+            Constraint::Parenthesized(inner) => self.field_offset(inner, name, off),
+            Constraint::Comparison(_) => {
+                let mut idents = Idents::default();
+                idents.visit_constraint(constraint);
+                idents
+                    .0
+                    .iter()
+                    .any(|ident| ident == name)
+                    .then(|| format!("Some::<usize>({})", off))
+            }
+            Constraint::Exists(inner) => {
+                (inner.name == name).then(|| format!("Some::<usize>({})", off))
+            }
+            Constraint::Constructor(inner) => {
+                (inner.name == name).then(|| format!("Some::<usize>({})", off))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Idents(Vec<String>);
+
+impl Visit for Idents {
+    fn visit_ident(&mut self, ident: &str) {
+        self.0.push(ident.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+    use std::{env, fs, process::Command};
+
+    #[test]
+    fn test_generate_decoder() {
+        let spec = Spec::parse(
+            r#"
+define endian=big;
+define space ram type=ram_space size=2 default;
+define register offset=0 size=1 [ r0 r1 r2 r3 ];
+define register offset=8 size=1 [ ctx ];
+define token instr(8)
+    op = (4,7)
+    sub = (2,3)
+    reg = (0,1)
+;
+define token imm8(8)
+    simm = (0,7) signed
+;
+define context ctx
+    mode = (0,0)
+;
+attach variables reg [ r0 r1 r2 r3 ];
+
+src: reg is sub=0 & reg { export reg; }
+src: simm is sub=1; simm { export *[const]:1 simm; }
+src: reg is sub=2 & reg=0 | sub=3 & reg=3 { export reg; }
+
+:add src is op=1 & src { }
+:neg reg is op=2 & reg { }
+:not reg is mode=1 & op=2 & reg { }
+:mov reg, simm is op=3 & reg; simm>0 { }
+:mov reg, simm is op=3 & reg; simm<1 { }
+:big is op=4; simm=-1 { }
+:nop is op=4 { }
+"#,
+        );
+        let names = ["op", "sub", "reg", "simm", "mode"];
+
+        let mut main = String::from("fn main() {\n");
+        main += "    for mode in 0..2 {\n        let mut ctx = Context::new();\n";
+        main += "        ctx.set(\"mode\", mode);\n";
+        main += "        for i in 0..0x10000u32 {\n";
+        main += "            let code = [(i >> 8) as u8, i as u8];\n";
+        main += "            let insn = decode(&code, &ctx);\n";
+        main +=
+            "            print!(\"{:?}\", insn.map(|i| (i.constructor, i.mnemonic(), i.len)));\n";
+        for name in names.iter() {
+            writeln!(
+                main,
+                "            print!(\" {{:?}}\", insn.and_then(|i| i.operand({:?}, &code, &ctx)));",
+                name
+            )
+            .unwrap();
+        }
+        main += "            println!();\n        }\n    }\n}\n";
+
+        let dir = env::temp_dir().join(format!("sleigh-codegen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("decoder.rs");
+        fs::write(&source, spec.generate_decoder() + &main).unwrap();
+        let status = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+            .args(["--edition", "2018", "-O", "-D", "warnings", "-o"])
+            .arg(dir.join("decoder"))
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(dir.join("decoder")).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+
+        let mut expected = String::new();
+        for mode in 0..2 {
+            for i in 0..0x10000u32 {
+                let code = [(i >> 8) as u8, i as u8];
+                let mut state = State::new(&spec, &code);
+                state.set_context("mode", mode);
+                let constructor = state.match_constructor(None);
+                let index = constructor.map(|c| {
+                    spec.constructors
+                        .iter()
+                        .position(|o| std::ptr::eq(o, c))
+                        .unwrap()
+                });
+                write!(
+                    expected,
+                    "{:?}",
+                    constructor.map(|c| (
+                        index.unwrap(),
+                        c.header.mnemonic.trim(),
+                        c.constraint.len(state.clone())
+                    ))
+                )
+                .unwrap();
+                for name in names.iter() {
+                    let value = constructor.and_then(|c| state.operand_value(c, name));
+                    write!(expected, " {:?}", value).unwrap();
+                }
+                expected += "\n";
+            }
+        }
+        assert!(expected.contains("Some((3, \"add src\", Some(1))) Some(1) Some(1)"));
+        assert_eq!(output, expected);
+    }
+}
@@ -16,3 +16,26 @@ impl_constraint_from!(Or, ConstraintOr);
 impl_constraint_from!(Semi, ConstraintSemi);
 impl_constraint_from!(Parenthesized, Box<Constraint>);
 impl_constraint_from!(Comparison, ConstraintComparison);
+
+macro_rules! impl_constraint_rvalue_from {
+    ($i:ident, $o:ty) => {
+        impl From<$o> for ConstraintRValue {
+            fn from(val: $o) -> Self {
+                ConstraintRValue::$i(val.into())
+            }
+        }
+    };
+}
+
+impl_constraint_rvalue_from!(Add, ConstraintRValueAdd);
+impl_constraint_rvalue_from!(Sub, ConstraintRValueSub);
+impl_constraint_rvalue_from!(Mult, ConstraintRValueMult);
+impl_constraint_rvalue_from!(Div, ConstraintRValueDiv);
+impl_constraint_rvalue_from!(IntOr, ConstraintRValueIntOr);
+impl_constraint_rvalue_from!(IntAnd, ConstraintRValueIntAnd);
+impl_constraint_rvalue_from!(IntXor, ConstraintRValueIntXor);
+impl_constraint_rvalue_from!(RShift, ConstraintRValueRShift);
+impl_constraint_rvalue_from!(LShift, ConstraintRValueLShift);
+impl_constraint_rvalue_from!(Neg, ConstraintRValueNeg);
+impl_constraint_rvalue_from!(Not, ConstraintRValueNot);
+impl_constraint_rvalue_from!(Parenthesized, Box<ConstraintRValue>);
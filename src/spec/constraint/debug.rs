@@ -49,7 +49,18 @@ impl Display for ConstraintComparison {
 impl Display for ConstraintRValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ConstraintRValue::Add(inner) => write!(f, "{} + {}", inner.lhs, inner.rhs),
+            ConstraintRValue::Add(inner) => write!(f, "({} + {})", inner.lhs, inner.rhs),
+            ConstraintRValue::Sub(inner) => write!(f, "({} - {})", inner.lhs, inner.rhs),
+            ConstraintRValue::Mult(inner) => write!(f, "({} * {})", inner.lhs, inner.rhs),
+            ConstraintRValue::Div(inner) => write!(f, "({} / {})", inner.lhs, inner.rhs),
+            ConstraintRValue::IntOr(inner) => write!(f, "({} $or {})", inner.lhs, inner.rhs),
+            ConstraintRValue::IntAnd(inner) => write!(f, "({} $and {})", inner.lhs, inner.rhs),
+            ConstraintRValue::IntXor(inner) => write!(f, "({} $xor {})", inner.lhs, inner.rhs),
+            ConstraintRValue::RShift(inner) => write!(f, "({} >> {})", inner.lhs, inner.rhs),
+            ConstraintRValue::LShift(inner) => write!(f, "({} << {})", inner.lhs, inner.rhs),
+            ConstraintRValue::Neg(inner) => write!(f, "-{}", inner.op),
+            ConstraintRValue::Not(inner) => write!(f, "~{}", inner.op),
+            ConstraintRValue::Parenthesized(inner) => write!(f, "({})", inner),
             ConstraintRValue::Field(inner) => write!(f, "{}", inner),
             ConstraintRValue::Integer(inner) => write!(f, "{}", inner),
         }
@@ -1,30 +1,49 @@
 use super::*;
-use crate::State;
+use crate::{Spec, State, Token};
 
 impl Constraint {
     pub fn matches(&self, state: State) -> bool {
+        self.matches_scoped(self.scoped(state))
+    }
+
+    fn matches_scoped(&self, state: State) -> bool {
         match self {
             Constraint::Ellipsis(inner) => inner.matches(state),
             Constraint::And(inner) => inner.matches(state),
             Constraint::Or(inner) => inner.matches(state),
             Constraint::Semi(inner) => inner.matches(state),
-            Constraint::Parenthesized(inner) => inner.matches(state),
+            Constraint::Parenthesized(inner) => inner.matches_scoped(state),
             Constraint::Comparison(inner) => inner.matches(state),
             Constraint::Exists(inner) => inner.matches(state),
             Constraint::Constructor(inner) => inner.matches(state),
         }
     }
 
-    fn len(&self, state: State) -> Option<usize> {
+    pub(crate) fn len(&self, state: State) -> Option<usize> {
+        self.len_scoped(self.scoped(state))
+    }
+
+    fn len_scoped(&self, state: State) -> Option<usize> {
         match self {
-            Constraint::Ellipsis(inner) => todo!(),
+            Constraint::Ellipsis(inner) => inner.op.len_scoped(state),
             Constraint::And(inner) => inner
                 .lhs
-                .len(state.clone())
-                .or_else(|| inner.rhs.len(state)),
-            Constraint::Or(inner) => todo!(),
-            Constraint::Semi(inner) => todo!(),
-            Constraint::Parenthesized(inner) => todo!(),
+                .len_scoped(state.clone())
+                .or_else(|| inner.rhs.len_scoped(state)),
+            Constraint::Or(inner) => {
+                if inner.lhs.matches_scoped(state.clone()) {
+                    inner.lhs.len_scoped(state)
+                } else {
+                    inner.rhs.len_scoped(state)
+                }
+            }
+            Constraint::Semi(inner) => {
+                let lhs = inner.lhs.len(state.clone()).unwrap_or_default();
+                let mut rhs_state = state;
+                rhs_state.code = rhs_state.code.get(lhs..)?;
+                Some(lhs + inner.rhs.len(rhs_state).unwrap_or_default())
+            }
+            Constraint::Parenthesized(inner) => inner.len_scoped(state),
             Constraint::Comparison(inner) => inner
                 .lhs
                 .len(state.clone())
@@ -33,23 +52,117 @@ impl Constraint {
             Constraint::Constructor(inner) => inner.len(state),
         }
     }
+
+    /// Returns the byte offset of the token that field `name` is read from,
+    /// relative to the start of this constraint.
+    pub(crate) fn field_offset(&self, name: &str, state: State) -> Option<usize> {
+        let len = state.code.len();
+        self.field_state(name, state)
+            .map(|state| len - state.code.len())
+    }
+
+    /// Returns `state` moved to the token that field `name` is read from and
+    /// scoped to it.
+    pub(crate) fn field_state<'s>(&self, name: &str, state: State<'s>) -> Option<State<'s>> {
+        self.field_state_scoped(name, self.scoped(state))
+    }
+
+    fn field_state_scoped<'s>(&self, name: &str, state: State<'s>) -> Option<State<'s>> {
+        match self {
+            Constraint::Ellipsis(inner) => inner.op.field_state_scoped(name, state),
+            Constraint::And(inner) => inner
+                .lhs
+                .field_state_scoped(name, state.clone())
+                .or_else(|| inner.rhs.field_state_scoped(name, state)),
+            Constraint::Or(inner) => {
+                if inner.lhs.matches_scoped(state.clone()) {
+                    inner.lhs.field_state_scoped(name, state)
+                } else {
+                    inner.rhs.field_state_scoped(name, state)
+                }
+            }
+            Constraint::Semi(inner) => {
+                if let Some(state) = inner.lhs.field_state(name, state.clone()) {
+                    return Some(state);
+                }
+                let lhs = inner.lhs.len(state.clone()).unwrap_or_default();
+                let mut rhs_state = state;
+                rhs_state.code = rhs_state.code.get(lhs..)?;
+                inner.rhs.field_state(name, rhs_state)
+            }
+            Constraint::Parenthesized(inner) => inner.field_state_scoped(name, state),
+            Constraint::Comparison(inner) => {
+                let fields = [&inner.lhs, &inner.rhs];
+                fields.iter().any(|f| f.references(name)).then_some(state)
+            }
+            Constraint::Exists(inner) => (inner.name == name).then_some(state),
+            Constraint::Constructor(inner) => (inner.name == name).then_some(state),
+        }
+    }
+
+    /// Returns the token this part of a pattern is made of, the one defining
+    /// most of the fields it refers to. The parts joined by `;` each have
+    /// their own token.
+    pub(crate) fn token<'s>(&self, spec: &'s Spec) -> Option<&'s Token> {
+        let mut names = Vec::new();
+        self.segment_fields(&mut names);
+        let mut token = None;
+        let mut most = 0;
+        for candidate in spec.tokens.iter() {
+            let count = names
+                .iter()
+                .filter(|name| candidate.fields.iter().any(|f| f.name == **name))
+                .count();
+            if count > most {
+                token = Some(candidate);
+                most = count;
+            }
+        }
+        token
+    }
+
+    fn scoped<'s>(&self, mut state: State<'s>) -> State<'s> {
+        state.token = self.token(state.spec);
+        state
+    }
+
+    fn segment_fields<'c>(&'c self, names: &mut Vec<&'c str>) {
+        match self {
+            Constraint::Ellipsis(inner) => inner.op.segment_fields(names),
+            Constraint::And(inner) => {
+                inner.lhs.segment_fields(names);
+                inner.rhs.segment_fields(names);
+            }
+            Constraint::Or(inner) => {
+                inner.lhs.segment_fields(names);
+                inner.rhs.segment_fields(names);
+            }
+            Constraint::Semi(_) | Constraint::Constructor(_) => {}
+            Constraint::Parenthesized(inner) => inner.segment_fields(names),
+            Constraint::Comparison(inner) => {
+                inner.lhs.fields(names);
+                inner.rhs.fields(names);
+            }
+            Constraint::Exists(inner) => names.push(&inner.name),
+        }
+    }
 }
 
 impl ConstraintEllipsis {
     pub fn matches(&self, state: State) -> bool {
-        todo!()
+        self.op.matches_scoped(state)
     }
 }
 
 impl ConstraintAnd {
     pub fn matches(&self, state: State) -> bool {
-        self.lhs.matches(state.clone()) && self.rhs.matches(state)
+        self.lhs.matches_scoped(state.clone()) && self.rhs.matches_scoped(state)
     }
 }
 
 impl ConstraintOr {
     pub fn matches(&self, state: State) -> bool {
-        self.lhs.matches(state.clone()) || self.rhs.matches(state)
+        self.lhs.matches_scoped(state.clone()) || self.rhs.matches_scoped(state)
     }
 }
 
@@ -71,7 +184,7 @@ impl ConstraintComparison {
     pub fn matches(&self, state: State) -> bool {
         if let Some(lhs) = state.eval(&self.lhs) {
             if let Some(rhs) = state.eval(&self.rhs) {
-                lhs == rhs
+                self.comparison.compare(lhs, rhs)
             } else {
                 false
             }
@@ -84,12 +197,25 @@ impl ConstraintComparison {
 impl ConstraintRValue {
     fn len(&self, state: State) -> Option<usize> {
         match self {
-            ConstraintRValue::Add(inner) => inner
-                .lhs
-                .len(state.clone())
-                .or_else(|| inner.rhs.len(state)),
             ConstraintRValue::Field(inner) => state.token_len(inner),
-            ConstraintRValue::Integer(_) => None,
+            _ => self
+                .operands()
+                .into_iter()
+                .find_map(|op| op.len(state.clone())),
+        }
+    }
+
+    fn fields<'c>(&'c self, names: &mut Vec<&'c str>) {
+        match self {
+            ConstraintRValue::Field(field) => names.push(field),
+            _ => self.operands().into_iter().for_each(|op| op.fields(names)),
+        }
+    }
+
+    fn references(&self, name: &str) -> bool {
+        match self {
+            ConstraintRValue::Field(field) => field == name,
+            _ => self.operands().iter().any(|op| op.references(name)),
         }
     }
 }
@@ -110,13 +236,14 @@ impl ConstraintExists {
     }
 
     fn len(&self, state: State) -> Option<usize> {
-        if let Some(c) = state
+        if state
             .spec
             .constructors
             .iter()
-            .find(|c| c.header.table == self.name)
+            .any(|c| c.header.table == self.name)
         {
-            c.constraint.len(state)
+            let constructor = state.match_constructor(Some(&self.name))?;
+            constructor.constraint.len(state)
         } else {
             state.token_len(&self.name)
         }
@@ -133,12 +260,8 @@ impl ConstraintConstructor {
     }
 
     fn len(&self, state: State) -> Option<usize> {
-        let constructor = state
-            .spec
-            .constructors
-            .iter()
-            .find(|c| c.header.table == self.name)
-            .unwrap();
+        // context fields and `epsilon` don't consume any bytes
+        let constructor = state.match_constructor(Some(&self.name))?;
         constructor.constraint.len(state)
     }
 }
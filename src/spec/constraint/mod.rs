@@ -2,7 +2,8 @@ mod convert;
 mod debug;
 mod matches;
 
-use crate::{ComparisonOperator, NumTypePrefix};
+use crate::{ComparisonOperator, NumTypePrefix, Span};
+use std::convert::TryFrom;
 
 #[derive(Clone, PartialEq)]
 pub enum Constraint {
@@ -16,27 +17,48 @@ pub enum Constraint {
     Constructor(ConstraintConstructor),
 }
 
+impl Constraint {
+    /// The source span of this constraint. Parenthesized constraints report
+    /// the span of their contents.
+    pub fn span(&self) -> Span {
+        match self {
+            Constraint::Ellipsis(inner) => inner.span,
+            Constraint::And(inner) => inner.span,
+            Constraint::Or(inner) => inner.span,
+            Constraint::Semi(inner) => inner.span,
+            Constraint::Parenthesized(inner) => inner.span(),
+            Constraint::Comparison(inner) => inner.span,
+            Constraint::Exists(inner) => inner.span,
+            Constraint::Constructor(inner) => inner.span,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct ConstraintEllipsis {
     pub op: Constraint,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct ConstraintAnd {
     pub lhs: Constraint,
     pub rhs: Constraint,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct ConstraintOr {
     pub lhs: Constraint,
     pub rhs: Constraint,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct ConstraintSemi {
     pub lhs: Constraint,
     pub rhs: Constraint,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -45,21 +67,35 @@ pub struct ConstraintComparison {
     pub num_type: NumTypePrefix,
     pub comparison: ComparisonOperator,
     pub rhs: ConstraintRValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct ConstraintExists {
     pub name: String,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct ConstraintConstructor {
     pub name: String,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub enum ConstraintRValue {
     Add(Box<ConstraintRValueAdd>),
+    Sub(Box<ConstraintRValueSub>),
+    Mult(Box<ConstraintRValueMult>),
+    Div(Box<ConstraintRValueDiv>),
+    IntOr(Box<ConstraintRValueIntOr>),
+    IntAnd(Box<ConstraintRValueIntAnd>),
+    IntXor(Box<ConstraintRValueIntXor>),
+    RShift(Box<ConstraintRValueRShift>),
+    LShift(Box<ConstraintRValueLShift>),
+    Neg(Box<ConstraintRValueNeg>),
+    Not(Box<ConstraintRValueNot>),
+    Parenthesized(Box<ConstraintRValue>),
     Field(String),
     Integer(i128),
 }
@@ -69,3 +105,132 @@ pub struct ConstraintRValueAdd {
     pub lhs: ConstraintRValue,
     pub rhs: ConstraintRValue,
 }
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueSub {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueMult {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueDiv {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueIntOr {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueIntAnd {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueIntXor {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueRShift {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueLShift {
+    pub lhs: ConstraintRValue,
+    pub rhs: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueNeg {
+    pub op: ConstraintRValue,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ConstraintRValueNot {
+    pub op: ConstraintRValue,
+}
+
+impl ConstraintRValue {
+    /// Evaluates the expression, reading fields with `field`. Fails if a
+    /// field is unknown or the arithmetic overflows.
+    pub fn evaluate(&self, field: &mut dyn FnMut(&str) -> Option<i128>) -> Option<i128> {
+        macro_rules! binary {
+            ($inner:expr, $f:expr) => {{
+                let lhs = $inner.lhs.evaluate(field)?;
+                let rhs = $inner.rhs.evaluate(field)?;
+                $f(lhs, rhs)
+            }};
+        }
+        match self {
+            ConstraintRValue::Add(inner) => binary!(inner, i128::checked_add),
+            ConstraintRValue::Sub(inner) => binary!(inner, i128::checked_sub),
+            ConstraintRValue::Mult(inner) => binary!(inner, i128::checked_mul),
+            ConstraintRValue::Div(inner) => binary!(inner, i128::checked_div),
+            ConstraintRValue::IntOr(inner) => binary!(inner, |l: i128, r| Some(l | r)),
+            ConstraintRValue::IntAnd(inner) => binary!(inner, |l: i128, r| Some(l & r)),
+            ConstraintRValue::IntXor(inner) => binary!(inner, |l: i128, r| Some(l ^ r)),
+            ConstraintRValue::RShift(inner) => {
+                binary!(inner, |l: i128, r| l.checked_shr(u32::try_from(r).ok()?))
+            }
+            ConstraintRValue::LShift(inner) => {
+                binary!(inner, |l: i128, r| l.checked_shl(u32::try_from(r).ok()?))
+            }
+            ConstraintRValue::Neg(inner) => inner.op.evaluate(field)?.checked_neg(),
+            ConstraintRValue::Not(inner) => Some(!inner.op.evaluate(field)?),
+            ConstraintRValue::Parenthesized(inner) => inner.evaluate(field),
+            ConstraintRValue::Field(name) => field(name),
+            ConstraintRValue::Integer(value) => Some(*value),
+        }
+    }
+
+    /// The operands of an operator, empty for fields and integers.
+    pub fn operands(&self) -> Vec<&ConstraintRValue> {
+        match self {
+            ConstraintRValue::Add(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::Sub(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::Mult(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::Div(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::IntOr(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::IntAnd(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::IntXor(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::RShift(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::LShift(inner) => vec![&inner.lhs, &inner.rhs],
+            ConstraintRValue::Neg(inner) => vec![&inner.op],
+            ConstraintRValue::Not(inner) => vec![&inner.op],
+            ConstraintRValue::Parenthesized(inner) => vec![inner],
+            ConstraintRValue::Field(_) | ConstraintRValue::Integer(_) => Vec::new(),
+        }
+    }
+
+    pub(crate) fn operands_mut(&mut self) -> Vec<&mut ConstraintRValue> {
+        match self {
+            ConstraintRValue::Add(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::Sub(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::Mult(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::Div(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::IntOr(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::IntAnd(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::IntXor(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::RShift(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::LShift(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ConstraintRValue::Neg(inner) => vec![&mut inner.op],
+            ConstraintRValue::Not(inner) => vec![&mut inner.op],
+            ConstraintRValue::Parenthesized(inner) => vec![inner],
+            ConstraintRValue::Field(_) | ConstraintRValue::Integer(_) => Vec::new(),
+        }
+    }
+}
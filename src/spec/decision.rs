@@ -0,0 +1,372 @@
+use super::*;
+use crate::State;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+};
+
+/// Selects constructor candidates by looking at a few instruction and
+/// context bits at a time, one tree per table.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct DecisionTree {
+    pub tables: HashMap<String, DecisionNode>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DecisionNode {
+    /// Candidates as indices into `Spec::constructors` and their pattern
+    /// alternatives, more specific patterns first and in source order
+    /// otherwise.
+    Leaf(Vec<(usize, usize)>),
+    Branch {
+        source: BitSource,
+        byte: usize,
+        /// The bits of `byte` that select the child, the lowest set bit is
+        /// the lowest bit of the child index.
+        mask: u8,
+        children: Vec<DecisionNode>,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BitSource {
+    Instruction,
+    Context(String),
+}
+
+impl DecisionTree {
+    pub fn table(&self, table: &str) -> Option<&DecisionNode> {
+        self.tables.get(table)
+    }
+}
+
+impl DecisionNode {
+    /// Returns the candidates that can match `state`. They still have to
+    /// be checked against their full pattern.
+    pub fn candidates(&self, state: &State) -> &[(usize, usize)] {
+        match self {
+            DecisionNode::Leaf(candidates) => candidates,
+            DecisionNode::Branch {
+                source,
+                byte,
+                mask,
+                children,
+            } => {
+                let data = match source {
+                    BitSource::Instruction => Some(state.code),
+                    BitSource::Context(register) => state.register_data(register),
+                };
+                let value = data.and_then(|d| d.get(*byte)).copied().unwrap_or_default();
+                children[extract(value, *mask)].candidates(state)
+            }
+        }
+    }
+}
+
+impl Spec {
+    /// Builds `Spec::decision_tree` from the compiled constructor patterns.
+    pub fn build_decision_tree(&mut self) {
+        let mut candidates: HashMap<&str, Vec<(usize, usize)>> = HashMap::new();
+        let mut complete: HashMap<&str, bool> = HashMap::new();
+        for (i, constructor) in self.constructors.iter().enumerate() {
+            let table = constructor.header.table.as_str();
+            let entry = candidates.entry(table).or_default();
+            match &constructor.pattern {
+                Some(pattern) => entry.extend((0..pattern.alternatives.len()).map(|a| (i, a))),
+                None => {
+                    complete.insert(table, false);
+                }
+            }
+        }
+
+        let mut tables = HashMap::new();
+        for (table, candidates) in candidates {
+            // tables with uncompiled constructors fall back to a linear scan
+            if complete.get(table) == Some(&false) {
+                continue;
+            }
+            let candidates = self.order_by_specificity(candidates);
+            let node = self.decision_node(candidates, &mut HashSet::new());
+            tables.insert(table.to_string(), node);
+        }
+        self.decision_tree = DecisionTree { tables };
+    }
+
+    pub(crate) fn alternative(
+        &self,
+        (constructor, alternative): (usize, usize),
+    ) -> &DisjointPattern {
+        &self.constructors[constructor]
+            .pattern
+            .as_ref()
+            .unwrap()
+            .alternatives[alternative]
+    }
+
+    /// Sorts candidates so that special cases come before the patterns they
+    /// specialize, keeping source order otherwise. The first match is then
+    /// always one that no other match is more specific than.
+    fn order_by_specificity(&self, candidates: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        let mut more_specific = vec![0; candidates.len()];
+        let mut less_specific = vec![Vec::new(); candidates.len()];
+        for (i, a) in candidates.iter().enumerate() {
+            for (j, b) in candidates.iter().enumerate() {
+                if self.alternative(*a).specializes(self.alternative(*b)) {
+                    more_specific[j] += 1;
+                    less_specific[i].push(j);
+                }
+            }
+        }
+
+        let mut ready: BinaryHeap<_> = (0..candidates.len())
+            .filter(|i| more_specific[*i] == 0)
+            .map(Reverse)
+            .collect();
+        let mut ordered = Vec::with_capacity(candidates.len());
+        while let Some(Reverse(i)) = ready.pop() {
+            ordered.push(candidates[i]);
+            for j in less_specific[i].iter() {
+                more_specific[*j] -= 1;
+                if more_specific[*j] == 0 {
+                    ready.push(Reverse(*j));
+                }
+            }
+        }
+        ordered
+    }
+
+    fn decision_node(
+        &self,
+        candidates: Vec<(usize, usize)>,
+        used: &mut HashSet<(BitSource, usize, u8)>,
+    ) -> DecisionNode {
+        if candidates.len() <= 1 {
+            return DecisionNode::Leaf(candidates);
+        }
+
+        let patterns: Vec<Vec<(BitSource, &BitPattern)>> = candidates
+            .iter()
+            .map(|candidate| {
+                let alternative = self.alternative(*candidate);
+                Some((BitSource::Instruction, &alternative.instruction))
+                    .into_iter()
+                    .chain(
+                        alternative
+                            .context
+                            .iter()
+                            .map(|(r, p)| (BitSource::Context(r.clone()), p)),
+                    )
+                    .collect()
+            })
+            .collect();
+
+        // count how many candidates fix each bit, skipping bits that all
+        // candidates fix to the same value as they don't tell them apart
+        let mut fixed: HashMap<(BitSource, usize, u8), (usize, usize)> = HashMap::new();
+        for pattern in patterns.iter() {
+            for (source, bits) in pattern.iter() {
+                for (byte, (mask, value)) in bits.mask.iter().zip(bits.value.iter()).enumerate() {
+                    for bit in 0..8 {
+                        if mask >> bit & 1 == 0 || used.contains(&(source.clone(), byte, bit)) {
+                            continue;
+                        }
+                        let count = fixed.entry((source.clone(), byte, bit)).or_default();
+                        count.0 += 1;
+                        count.1 += (value >> bit & 1) as usize;
+                    }
+                }
+            }
+        }
+        fixed.retain(|_, (count, ones)| {
+            !(*count == candidates.len() && (*ones == 0 || ones == count))
+        });
+
+        let mut scores: HashMap<(BitSource, usize), usize> = HashMap::new();
+        for ((source, byte, _), (count, _)) in fixed.iter() {
+            *scores.entry((source.clone(), *byte)).or_default() += count;
+        }
+        // break ties deterministically, preferring the instruction bytes
+        let best = scores.into_iter().max_by(|(a, sa), (b, sb)| {
+            sa.cmp(sb)
+                .then_with(|| (b.0 != BitSource::Instruction).cmp(&(a.0 != BitSource::Instruction)))
+                .then_with(|| b.1.cmp(&a.1))
+        });
+        let (source, byte) = match best {
+            Some((best, _)) => best,
+            None => return DecisionNode::Leaf(candidates),
+        };
+        let selected = (0..8)
+            .filter(|bit| fixed.contains_key(&(source.clone(), byte, *bit)))
+            .fold(0u8, |mask, bit| mask | 1 << bit);
+
+        let mut children = vec![Vec::new(); 1 << selected.count_ones()];
+        for (candidate, pattern) in candidates.iter().zip(patterns.iter()) {
+            let (fixed_mask, value) = pattern
+                .iter()
+                .find(|(s, _)| *s == source)
+                .map(|(_, bits)| {
+                    let mask = bits.mask.get(byte).copied().unwrap_or_default();
+                    let value = bits.value.get(byte).copied().unwrap_or_default();
+                    (mask & selected, value)
+                })
+                .unwrap_or_default();
+            for (index, child) in children.iter_mut().enumerate() {
+                let bits = deposit(index, selected);
+                if bits & fixed_mask == value & fixed_mask {
+                    child.push(*candidate);
+                }
+            }
+        }
+        if children.iter().all(|c| c.len() == candidates.len()) {
+            return DecisionNode::Leaf(candidates);
+        }
+
+        let bits: Vec<_> = (0..8)
+            .filter(|bit| selected >> bit & 1 != 0)
+            .map(|bit| (source.clone(), byte, bit))
+            .collect();
+        used.extend(bits.iter().cloned());
+        let children = children
+            .into_iter()
+            .map(|candidates| self.decision_node(candidates, used))
+            .collect();
+        for bit in bits {
+            used.remove(&bit);
+        }
+
+        DecisionNode::Branch {
+            source,
+            byte,
+            mask: selected,
+            children,
+        }
+    }
+}
+
+/// Packs the bits of `value` selected by `mask` into the low bits.
+fn extract(value: u8, mask: u8) -> usize {
+    let mut index = 0;
+    let mut n = 0;
+    for bit in 0..8 {
+        if mask >> bit & 1 != 0 {
+            index |= ((value >> bit & 1) as usize) << n;
+            n += 1;
+        }
+    }
+    index
+}
+
+/// The inverse of `extract`, spreads the low bits of `index` over `mask`.
+fn deposit(index: usize, mask: u8) -> u8 {
+    let mut value = 0;
+    let mut n = 0;
+    for bit in 0..8 {
+        if mask >> bit & 1 != 0 {
+            value |= ((index >> n & 1) as u8) << bit;
+            n += 1;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BitSource, ConstructorMatch, DecisionNode, Spec, State};
+
+    #[test]
+    fn test_decision_tree() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 r3 ctx ];
+define token instr(8)
+    op = (4,7)
+    sub = (2,3)
+    reg = (0,1)
+;
+define context ctx
+    mode = (0,0)
+;
+attach variables reg [ r0 r1 r2 r3 ];
+
+:add reg is op=1 & reg { reg = reg + 1; }
+:sub reg is op=2 & sub=0 & reg { reg = reg - 1; }
+:neg reg is op=2 & sub=1 & reg { reg = -reg; }
+:not reg is mode=1 & op=2 & reg { reg = ~reg; }
+:mov reg is op=3 & reg!=0 { reg = 0; }
+:nop is op=3 { }
+:ret is mode=0 & op { }
+"#,
+        );
+
+        let root = spec.decision_tree.table("instruction").unwrap();
+        assert!(matches!(
+            root,
+            DecisionNode::Branch {
+                source: BitSource::Instruction,
+                mask: 0xff,
+                ..
+            }
+        ));
+
+        for mode in 0..2 {
+            for byte in 0..=255u8 {
+                let code = [byte];
+                let mut state = State::new(&spec, &code);
+                state.set_context("mode", mode);
+                let expected = spec
+                    .constructors
+                    .iter()
+                    .find(|c| c.matches(state.clone()))
+                    .map(|c| &c.header.mnemonic);
+                let found = state.match_constructor(None).map(|c| &c.header.mnemonic);
+                assert_eq!(expected, found, "{:#x} in mode {}", byte, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_specificity() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define token instr(8)
+    op = (4,7)
+    sub = (2,3)
+    reg = (0,1)
+;
+attach variables reg [ r0 r1 r2 r3 ];
+
+:nop is op=3 { }
+:mov reg is op=3 & reg!=0 { reg = 0; }
+:clr is op=3 & sub=1 & reg=2 { r0 = 0; }
+:a is op=4 & sub=1 { }
+:b is op=4 & reg=1 { }
+"#,
+        );
+
+        let resolve = |byte: u8| {
+            let code = [byte];
+            let state = State::new(&spec, &code);
+            match state.resolve_constructor(None) {
+                ConstructorMatch::NoMatch => Vec::new(),
+                ConstructorMatch::Unique(c) => vec![c.header.mnemonic.trim().to_string()],
+                ConstructorMatch::Ambiguous(cs) => cs
+                    .iter()
+                    .map(|c| c.header.mnemonic.trim().to_string())
+                    .collect(),
+            }
+        };
+
+        assert_eq!(resolve(0x30), ["nop"]);
+        assert_eq!(resolve(0x31), ["mov reg"]);
+        assert_eq!(resolve(0x36), ["clr"]);
+        assert_eq!(resolve(0x44), ["a"]);
+        assert_eq!(resolve(0x41), ["b"]);
+        assert_eq!(resolve(0x45), ["a", "b"]);
+        assert_eq!(resolve(0x50), Vec::<&str>::new());
+    }
+}
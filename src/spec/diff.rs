@@ -0,0 +1,433 @@
+use super::*;
+use std::fmt::{self, Display};
+
+/// A difference between two versions of a spec. Constructors are referred to
+/// by their index into `Spec::constructors` of the old and new spec.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SpecChange {
+    AddedConstructor {
+        new: usize,
+    },
+    RemovedConstructor {
+        old: usize,
+    },
+    /// A constructor with the same table and display changed its pattern,
+    /// its semantics (calculations and actions) or both.
+    ChangedConstructor {
+        old: usize,
+        new: usize,
+        pattern: bool,
+        semantics: bool,
+    },
+    AddedTokenField {
+        token: String,
+        field: String,
+    },
+    RemovedTokenField {
+        token: String,
+        field: String,
+    },
+    ChangedTokenField {
+        token: String,
+        field: String,
+    },
+    AddedContextField {
+        register: String,
+        field: String,
+    },
+    RemovedContextField {
+        register: String,
+        field: String,
+    },
+    /// The bits, display or attached meaning of a context field changed.
+    ChangedContextField {
+        register: String,
+        field: String,
+    },
+    AddedSpace(String),
+    RemovedSpace(String),
+    ChangedSpace(String),
+    AddedRegister(String),
+    RemovedRegister(String),
+    ChangedRegister(String),
+    AddedMacro(String),
+    RemovedMacro(String),
+    ChangedMacro(String),
+}
+
+impl Spec {
+    /// Compares `self` to a `new` version of the spec. Constructors are
+    /// matched by table, display and pattern, so changes to the layout of the
+    /// source or to how a constraint is written aren't reported.
+    pub fn diff(&self, new: &Spec) -> Vec<SpecChange> {
+        let mut changes = Vec::new();
+        diff_constructors(self, new, &mut changes);
+        diff_token_fields(self, new, &mut changes);
+        diff_context_fields(self, new, &mut changes);
+
+        for space in new.spaces.iter() {
+            match self.spaces.iter().find(|s| s.name == space.name) {
+                Some(old) if old != space => {
+                    changes.push(SpecChange::ChangedSpace(space.name.clone()))
+                }
+                Some(_) => {}
+                None => changes.push(SpecChange::AddedSpace(space.name.clone())),
+            }
+        }
+        for space in self.spaces.iter() {
+            if !new.spaces.iter().any(|s| s.name == space.name) {
+                changes.push(SpecChange::RemovedSpace(space.name.clone()));
+            }
+        }
+
+        for register in new.registers.iter() {
+            match self.registers.iter().find(|r| r.name == register.name) {
+                Some(old) if old.offset != register.offset || old.size != register.size => {
+                    changes.push(SpecChange::ChangedRegister(register.name.clone()))
+                }
+                Some(_) => {}
+                None => changes.push(SpecChange::AddedRegister(register.name.clone())),
+            }
+        }
+        for register in self.registers.iter() {
+            if !new.registers.iter().any(|r| r.name == register.name) {
+                changes.push(SpecChange::RemovedRegister(register.name.clone()));
+            }
+        }
+
+        for r#macro in new.macros.iter() {
+            match self.macros.iter().find(|m| m.name == r#macro.name) {
+                Some(old) if old.args != r#macro.args || old.actions != r#macro.actions => {
+                    changes.push(SpecChange::ChangedMacro(r#macro.name.clone()))
+                }
+                Some(_) => {}
+                None => changes.push(SpecChange::AddedMacro(r#macro.name.clone())),
+            }
+        }
+        for r#macro in self.macros.iter() {
+            if !new.macros.iter().any(|m| m.name == r#macro.name) {
+                changes.push(SpecChange::RemovedMacro(r#macro.name.clone()));
+            }
+        }
+        changes
+    }
+}
+
+fn diff_constructors(old: &Spec, new: &Spec, changes: &mut Vec<SpecChange>) {
+    let key = |c: &Constructor| {
+        let display = c.header.mnemonic.split_whitespace().collect::<Vec<_>>();
+        (c.header.table.clone(), display.join(" "))
+    };
+    let semantics = |a: &Constructor, b: &Constructor| {
+        a.calculations != b.calculations || a.actions != b.actions
+    };
+    let old_keys: Vec<_> = old.constructors.iter().map(key).collect();
+    let mut matched = vec![false; old.constructors.len()];
+    let mut unmatched = Vec::new();
+
+    // pair up constructors with the same pattern first, so that of two
+    // constructors with the same display the right ones are compared
+    for (j, constructor) in new.constructors.iter().enumerate() {
+        let key = key(constructor);
+        let same = (0..old.constructors.len()).find(|i| {
+            !matched[*i] && old_keys[*i] == key && same_pattern(&old.constructors[*i], constructor)
+        });
+        match same {
+            Some(i) => {
+                matched[i] = true;
+                if semantics(&old.constructors[i], constructor) {
+                    changes.push(SpecChange::ChangedConstructor {
+                        old: i,
+                        new: j,
+                        pattern: false,
+                        semantics: true,
+                    });
+                }
+            }
+            None => unmatched.push((j, key)),
+        }
+    }
+
+    for (j, key) in unmatched {
+        let constructor = &new.constructors[j];
+        match (0..old.constructors.len()).find(|i| !matched[*i] && old_keys[*i] == key) {
+            Some(i) => {
+                matched[i] = true;
+                changes.push(SpecChange::ChangedConstructor {
+                    old: i,
+                    new: j,
+                    pattern: true,
+                    semantics: semantics(&old.constructors[i], constructor),
+                });
+            }
+            None => changes.push(SpecChange::AddedConstructor { new: j }),
+        }
+    }
+    for (i, matched) in matched.into_iter().enumerate() {
+        if !matched {
+            changes.push(SpecChange::RemovedConstructor { old: i });
+        }
+    }
+}
+
+/// Compares the bits two constructors match, the order their operands and
+/// alternatives are written in doesn't matter. Without compiled patterns the
+/// constraints are compared as written.
+fn same_pattern(a: &Constructor, b: &Constructor) -> bool {
+    let (a, b) = match (&a.pattern, &b.pattern) {
+        (Some(a_pattern), Some(b_pattern)) => (&a_pattern.alternatives, &b_pattern.alternatives),
+        _ => return a.constraint == b.constraint,
+    };
+    let same = |a: &DisjointPattern, b: &DisjointPattern| {
+        a.instruction == b.instruction
+            && a.context == b.context
+            && a.residual == b.residual
+            && a.len == b.len
+    };
+    a.iter().all(|a| b.iter().any(|b| same(a, b))) && b.iter().all(|b| a.iter().any(|a| same(a, b)))
+}
+
+fn diff_token_fields(old: &Spec, new: &Spec, changes: &mut Vec<SpecChange>) {
+    fn fields(spec: &Spec) -> Vec<(&Token, &TokenField)> {
+        spec.tokens
+            .iter()
+            .flat_map(|t| t.fields.iter().map(move |f| (t, f)))
+            .collect()
+    }
+    let old_fields = fields(old);
+    let new_fields = fields(new);
+
+    for (token, field) in new_fields.iter() {
+        let change = match old_fields.iter().find(|(_, f)| f.name == field.name) {
+            // a field is decoded differently if its token changes size or
+            // byte order
+            Some((old_token, old_field)) => {
+                if old_field != field
+                    || old_token.size != token.size
+                    || old.token_endianness(old_token) != new.token_endianness(token)
+                {
+                    SpecChange::ChangedTokenField {
+                        token: token.name.clone(),
+                        field: field.name.clone(),
+                    }
+                } else {
+                    continue;
+                }
+            }
+            None => SpecChange::AddedTokenField {
+                token: token.name.clone(),
+                field: field.name.clone(),
+            },
+        };
+        changes.push(change);
+    }
+    for (token, field) in old_fields.iter() {
+        if !new_fields.iter().any(|(_, f)| f.name == field.name) {
+            changes.push(SpecChange::RemovedTokenField {
+                token: token.name.clone(),
+                field: field.name.clone(),
+            });
+        }
+    }
+}
+
+fn diff_context_fields(old: &Spec, new: &Spec, changes: &mut Vec<SpecChange>) {
+    fn fields(spec: &Spec) -> Vec<(&str, &ContextField)> {
+        spec.contexts
+            .iter()
+            .flat_map(|c| c.fields.iter().map(move |f| (c.register.as_str(), f)))
+            .collect()
+    }
+    let old_fields = fields(old);
+    let new_fields = fields(new);
+
+    for (register, field) in new_fields.iter() {
+        let change = match old_fields.iter().find(|(_, f)| f.name == field.name) {
+            Some((old_register, old_field)) => {
+                if old_field != field || old_register != register {
+                    SpecChange::ChangedContextField {
+                        register: register.to_string(),
+                        field: field.name.clone(),
+                    }
+                } else {
+                    continue;
+                }
+            }
+            None => SpecChange::AddedContextField {
+                register: register.to_string(),
+                field: field.name.clone(),
+            },
+        };
+        changes.push(change);
+    }
+    for (register, field) in old_fields.iter() {
+        if !new_fields.iter().any(|(_, f)| f.name == field.name) {
+            changes.push(SpecChange::RemovedContextField {
+                register: register.to_string(),
+                field: field.name.clone(),
+            });
+        }
+    }
+}
+
+impl Display for SpecChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecChange::AddedConstructor { new } => write!(f, "added constructor #{}", new),
+            SpecChange::RemovedConstructor { old } => write!(f, "removed constructor #{}", old),
+            SpecChange::ChangedConstructor {
+                old,
+                new,
+                pattern,
+                semantics,
+            } => {
+                let what = match (pattern, semantics) {
+                    (true, true) => "pattern and semantics",
+                    (true, false) => "pattern",
+                    _ => "semantics",
+                };
+                write!(f, "changed {} of constructor #{} (was #{})", what, new, old)
+            }
+            SpecChange::AddedTokenField { token, field } => {
+                write!(f, "added field `{}` of token `{}`", field, token)
+            }
+            SpecChange::RemovedTokenField { token, field } => {
+                write!(f, "removed field `{}` of token `{}`", field, token)
+            }
+            SpecChange::ChangedTokenField { token, field } => {
+                write!(f, "changed field `{}` of token `{}`", field, token)
+            }
+            SpecChange::AddedContextField { register, field } => {
+                write!(f, "added context field `{}` of `{}`", field, register)
+            }
+            SpecChange::RemovedContextField { register, field } => {
+                write!(f, "removed context field `{}` of `{}`", field, register)
+            }
+            SpecChange::ChangedContextField { register, field } => {
+                write!(f, "changed context field `{}` of `{}`", field, register)
+            }
+            SpecChange::AddedSpace(name) => write!(f, "added space `{}`", name),
+            SpecChange::RemovedSpace(name) => write!(f, "removed space `{}`", name),
+            SpecChange::ChangedSpace(name) => write!(f, "changed space `{}`", name),
+            SpecChange::AddedRegister(name) => write!(f, "added register `{}`", name),
+            SpecChange::RemovedRegister(name) => write!(f, "removed register `{}`", name),
+            SpecChange::ChangedRegister(name) => write!(f, "changed register `{}`", name),
+            SpecChange::AddedMacro(name) => write!(f, "added macro `{}`", name),
+            SpecChange::RemovedMacro(name) => write!(f, "removed macro `{}`", name),
+            SpecChange::ChangedMacro(name) => write!(f, "changed macro `{}`", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Spec, SpecChange};
+
+    #[test]
+    fn test_diff() {
+        let old = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define space io type=ram_space size=2;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define register offset=0x10 size=1 [ Z ];
+define register offset=0x20 size=4 [ ctx ];
+define token instr(16)
+    op = (12,15)
+    dst = (8,9)
+    src = (6,7)
+    imm = (0,5)
+;
+define context ctx
+    mode = (0,0)
+    isa = (1,2)
+;
+attach variables [ dst src ] [ r0 r1 r2 r3 ];
+
+macro setflags(res) { Z = res == 0; }
+
+:mov dst, src is op=1 & dst & src { dst = src; }
+:add dst, src is op=2 & dst & src { dst = dst + src; setflags(dst); }
+:li dst, imm is op=3 & dst & imm { dst = imm; }
+:nop is op=0 { }
+:alt is op=5 | op=6 { }
+"#,
+        );
+        // reformatted, with a reordered constraint and some real changes
+        let new = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define space io type=ram_space size=4;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define register offset=0x10 size=1 [ Z C ];
+define register offset=0x20 size=4 [ ctx ];
+define token instr(16)
+    op = (12,15)
+    dst = (8,9)
+    src = (6,7)
+    imm = (0,5) signed
+;
+define context ctx
+    mode = (0,0)
+    lane = (3,3)
+;
+attach variables [ dst src ] [ r0 r1 r2 r3 ];
+attach names mode [ a b ];
+
+macro setflags(res) { Z = res == 0; C = 0; }
+
+:mov   dst,  src is src & dst & op=1 {
+    dst = src;
+}
+:add dst, src is op=2 & dst & src { dst = dst + src; setflags(dst); }
+:li dst, imm is op=4 & dst & imm { dst = imm; }
+:halt is op=15 { }
+:alt is op=6 | op=5 { }
+"#,
+        );
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                SpecChange::ChangedConstructor {
+                    old: 1,
+                    new: 1,
+                    pattern: false,
+                    semantics: true
+                },
+                SpecChange::ChangedConstructor {
+                    old: 2,
+                    new: 2,
+                    pattern: true,
+                    semantics: false
+                },
+                SpecChange::AddedConstructor { new: 3 },
+                SpecChange::RemovedConstructor { old: 3 },
+                SpecChange::ChangedTokenField {
+                    token: "instr".to_string(),
+                    field: "imm".to_string()
+                },
+                SpecChange::ChangedContextField {
+                    register: "ctx".to_string(),
+                    field: "mode".to_string()
+                },
+                SpecChange::AddedContextField {
+                    register: "ctx".to_string(),
+                    field: "lane".to_string()
+                },
+                SpecChange::RemovedContextField {
+                    register: "ctx".to_string(),
+                    field: "isa".to_string()
+                },
+                SpecChange::ChangedSpace("io".to_string()),
+                SpecChange::AddedSpace("register".to_string()),
+                SpecChange::AddedRegister("C".to_string()),
+                SpecChange::ChangedMacro("setflags".to_string()),
+            ]
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use super::*;
+
+/// A pattern one constructor of a table is encoded with.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TableEncoding {
+    /// Index into `Spec::constructors`.
+    pub constructor: usize,
+    pub pattern: DisjointPattern,
+    /// The operands that aren't fixed by the pattern.
+    pub operands: Vec<FreeOperand>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum FreeOperand {
+    /// A token field, `bits` has the mask of the bits left open.
+    Field {
+        name: String,
+        offset: usize,
+        bits: BitPattern,
+    },
+    Table {
+        name: String,
+        offset: usize,
+    },
+}
+
+impl Spec {
+    /// Lists the distinct encodings of the constructors of `table`, in the
+    /// order of the constructors. Constructors have no encodings until
+    /// `Spec::compile_patterns` has run.
+    pub fn table_encodings(&self, table: &str) -> Vec<TableEncoding> {
+        let mut encodings: Vec<TableEncoding> = Vec::new();
+        for (i, constructor) in self.constructors.iter().enumerate() {
+            if constructor.header.table != table {
+                continue;
+            }
+            let alternatives = constructor
+                .pattern
+                .iter()
+                .flat_map(|p| p.alternatives.iter());
+            for alternative in alternatives {
+                let duplicate = encodings.iter().any(|e| {
+                    e.constructor == i
+                        && e.pattern.instruction == alternative.instruction
+                        && e.pattern.context == alternative.context
+                        && e.pattern.residual == alternative.residual
+                });
+                if duplicate {
+                    continue;
+                }
+                encodings.push(TableEncoding {
+                    constructor: i,
+                    pattern: alternative.clone(),
+                    operands: self.free_operands(alternative),
+                });
+            }
+        }
+        encodings
+    }
+
+    fn free_operands(&self, pattern: &DisjointPattern) -> Vec<FreeOperand> {
+        pattern
+            .operands
+            .iter()
+            .filter_map(|(offset, name)| {
+                let token_field = self.tokens.iter().find_map(|token| {
+                    let field = token.fields.iter().find(|f| &f.name == name)?;
+                    Some((token, field))
+                });
+                let (token, field) = match token_field {
+                    Some(token_field) => token_field,
+                    None => {
+                        return Some(FreeOperand::Table {
+                            name: name.clone(),
+                            offset: *offset,
+                        })
+                    }
+                };
+
+                let len = token.size as usize / 8;
+                let mut bits = BitPattern::default();
+                bits.extend(offset + len);
+                bits.set_field(*offset, len, &field.range, -1, self.token_endianness(token))
+                    .unwrap();
+                bits.value = vec![0; bits.len()];
+                for (mask, fixed) in bits.mask.iter_mut().zip(pattern.instruction.mask.iter()) {
+                    *mask &= !fixed;
+                }
+                bits.mask
+                    .iter()
+                    .any(|m| *m != 0)
+                    .then(|| FreeOperand::Field {
+                        name: name.clone(),
+                        offset: *offset,
+                        bits,
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FreeOperand, Spec};
+
+    #[test]
+    fn test_table_encodings() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 r3 ctx ];
+define token instr(16)
+    op = (12,15)
+    dst = (8,9)
+    src = (6,7)
+    sub = (0,1)
+;
+define token imm16(16)
+    imm = (0,15)
+;
+define context ctx
+    mode = (0,0)
+;
+attach variables [ dst src ] [ r0 r1 r2 r3 ];
+
+Src: src is src & sub=0 { export src; }
+Src: imm is sub=1; imm { export *[const]:2 imm; }
+
+:mov dst, src is mode=1 & op=1 & dst & src & src!=0 { dst = src; }
+:ld dst, Src is (op=4 | op=5) & dst & Src { dst = Src; }
+"#,
+        );
+
+        // the small `src` field is split into one encoding per value
+        let encodings = spec.table_encodings("instruction");
+        assert_eq!(encodings.len(), 5);
+        assert_eq!(encodings[0].constructor, 2);
+        assert_eq!(
+            encodings[0].pattern.to_string(),
+            "01...... 0001.... ctx=.......1 ........ ........ ........"
+        );
+        match &encodings[0].operands[..] {
+            [FreeOperand::Field { name, offset, bits }] => {
+                assert_eq!((name.as_str(), *offset), ("dst", 0));
+                assert_eq!(bits.mask, [0x00, 0x03]);
+            }
+            operands => panic!("{:?}", operands),
+        }
+        assert_eq!(encodings[4].constructor, 3);
+        assert_eq!(encodings[4].pattern.to_string(), "........ 0101....");
+        assert_eq!(encodings[4].pattern.len, None);
+        assert_eq!(
+            encodings[4].operands[1],
+            FreeOperand::Table {
+                name: "Src".to_string(),
+                offset: 0
+            }
+        );
+
+        let src = spec.table_encodings("Src");
+        assert_eq!(src.len(), 2);
+        assert_eq!(src[1].pattern.len, Some(4));
+        assert_eq!(
+            src[1].operands[0],
+            FreeOperand::Field {
+                name: "imm".to_string(),
+                offset: 2,
+                bits: crate::BitPattern {
+                    mask: vec![0x00, 0x00, 0xff, 0xff],
+                    value: vec![0x00; 4],
+                }
+            }
+        );
+    }
+}
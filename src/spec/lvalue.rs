@@ -1,4 +1,4 @@
-use crate::RValue;
+use crate::{RValue, Span};
 
 #[derive(Clone, PartialEq)]
 pub enum LValue {
@@ -7,10 +7,21 @@ pub enum LValue {
     Ref(LValueRef),
 }
 
+impl LValue {
+    pub fn span(&self) -> Span {
+        match self {
+            LValue::Ident(inner) => inner.span,
+            LValue::Slice(inner) => inner.span,
+            LValue::Ref(inner) => inner.span,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct LValueIdent {
     pub field: String,
     pub size: Option<u8>,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -18,6 +29,7 @@ pub struct LValueSlice {
     pub field: String,
     pub offset: u8,
     pub size: u8,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -25,4 +37,5 @@ pub struct LValueRef {
     pub space: Option<String>,
     pub size: Option<u8>,
     pub op: Box<RValue>,
+    pub span: Span,
 }
@@ -1,26 +1,49 @@
 mod action;
+mod analysis;
+mod builder;
+mod codegen;
 mod constraint;
+mod decision;
+mod diff;
+mod encoding;
 mod lvalue;
 mod parser;
+mod pattern;
 #[macro_use]
 mod precedence;
+mod print;
 mod rvalue;
+mod size;
+mod span;
+mod validate;
+mod visit;
 
 pub use action::*;
+pub use builder::*;
 pub use constraint::*;
+pub use decision::*;
+pub use diff::*;
+pub use encoding::*;
 pub use lvalue::*;
-pub use precedence::{fix_precedence_constraint, fix_precedence_rvalue};
+pub use parser::{Rule, SleighParser};
+pub use pattern::*;
+pub use precedence::{
+    fix_precedence_constraint, fix_precedence_constraint_rvalue, fix_precedence_rvalue,
+};
 pub use rvalue::*;
+pub use span::*;
+pub use validate::*;
+pub use visit::*;
 
-use crate::State;
-use parser::SleighParser;
+use crate::{CompilerSpec, State};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     mem::replace,
     ops::Range,
-    sync::atomic::{AtomicU64, Ordering},
 };
+use validate::BUILTIN_SYMBOLS;
 
+#[derive(PartialEq)]
 pub struct Spec {
     pub endianness: Endianness,
     pub alignment: u8,
@@ -31,49 +54,120 @@ pub struct Spec {
     pub pcodeops: Vec<PCodeOp>,
     pub constructors: Vec<Constructor>,
     pub macros: Vec<Macro>,
+    pub compiler_spec: Option<CompilerSpec>,
+    pub decision_tree: DecisionTree,
 }
 
 impl Spec {
     pub fn parse(s: &str) -> Self {
         let mut spec = SleighParser::parse_file(s);
         spec.expand_macros();
+        // mismatches are reported by `Spec::validate`
+        spec.infer_sizes();
+        spec.compile_patterns();
+        spec.build_decision_tree();
         spec
     }
 
+    /// The byte order fields of `token` are read in.
+    pub fn token_endianness(&self, token: &Token) -> Endianness {
+        token.endianness.unwrap_or(self.endianness)
+    }
+
+    /// The macros that invoke themselves, directly or through other macros.
+    pub(crate) fn recursive_macros(&self) -> HashSet<&str> {
+        fn reaches<'s>(
+            spec: &'s Spec,
+            from: &'s Macro,
+            target: &str,
+            seen: &mut HashSet<&'s str>,
+        ) -> bool {
+            from.actions.iter().any(|action| {
+                let name = match action {
+                    Action::Macro(invocation) => invocation.r#macro.as_str(),
+                    _ => return false,
+                };
+                name == target
+                    || seen.insert(name)
+                        && spec
+                            .macros
+                            .iter()
+                            .find(|m| m.name == name)
+                            .is_some_and(|m| reaches(spec, m, target, seen))
+            })
+        }
+        self.macros
+            .iter()
+            .filter(|m| reaches(self, m, &m.name, &mut HashSet::new()))
+            .map(|m| m.name.as_str())
+            .collect()
+    }
+
+    /// Inlines macro invocations. Macro locals get names that are unique
+    /// within their constructor, so the result is the same on every run.
+    /// Invocations of unknown or recursive macros and invocations with the
+    /// wrong number of arguments are left in place and reported by
+    /// `Spec::validate`.
     fn expand_macros(&mut self) {
+        let recursive: HashSet<String> = self
+            .recursive_macros()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let globals: HashSet<_> = self
+            .registers
+            .iter()
+            .map(|r| r.name.clone())
+            .chain(self.spaces.iter().map(|s| s.name.clone()))
+            .chain(
+                self.tokens
+                    .iter()
+                    .flat_map(|t| t.fields.iter())
+                    .map(|f| f.name.clone()),
+            )
+            .chain(
+                self.contexts
+                    .iter()
+                    .flat_map(|c| c.fields.iter())
+                    .map(|f| f.name.clone()),
+            )
+            .chain(self.constructors.iter().map(|c| c.header.table.clone()))
+            .chain(BUILTIN_SYMBOLS.iter().map(|s| s.to_string()))
+            .collect();
+
         for constructor in self.constructors.iter_mut() {
-            while let Some(pos) = constructor
-                .actions
-                .iter()
-                .position(|a| matches!(a, Action::Macro(_)))
-            {
-                let action = constructor.actions.remove(pos);
-                let macro_invocation = if let Action::Macro(m) = action {
-                    m
-                } else {
-                    unreachable!()
+            let mut taken = globals.clone();
+            CollectIdents(&mut taken).visit_constructor(constructor);
+
+            let mut pos = 0;
+            while pos < constructor.actions.len() {
+                let actions = match &constructor.actions[pos] {
+                    Action::Macro(invocation) => self
+                        .macros
+                        .iter()
+                        .find(|m| m.name == invocation.r#macro && !recursive.contains(&m.name))
+                        .and_then(|m| m.expand(&invocation.args, &globals, &mut taken)),
+                    _ => None,
                 };
-                for (action, i) in self
-                    .macros
-                    .iter()
-                    .find(|m| m.name == macro_invocation.r#macro)
-                    .unwrap()
-                    .expand(&macro_invocation.args)
-                    .zip(pos..)
-                {
-                    constructor.actions.insert(i, action);
+                match actions {
+                    // not advancing `pos` expands nested invocations as well
+                    Some(actions) => {
+                        constructor.actions.splice(pos..=pos, actions);
+                    }
+                    None => pos += 1,
                 }
             }
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum Endianness {
     Big,
     Little,
 }
 
+#[derive(PartialEq)]
 pub struct Space {
     pub name: String,
     pub ty: SpaceType,
@@ -82,23 +176,27 @@ pub struct Space {
     pub wordsize: u8,
 }
 
+#[derive(PartialEq)]
 pub enum SpaceType {
     Ram,
     Rom,
     Register,
 }
 
+#[derive(PartialEq)]
 pub struct Register {
     pub name: String,
     pub offset: u32,
     pub size: u16,
 }
 
+#[derive(PartialEq)]
 pub struct Context {
     pub register: String,
     pub fields: Vec<ContextField>,
 }
 
+#[derive(PartialEq)]
 pub struct ContextField {
     pub name: String,
     pub range: Range<u16>,
@@ -108,18 +206,31 @@ pub struct ContextField {
     pub meaning: FieldMeaning,
 }
 
+impl ContextField {
+    /// Interprets the raw bits of the field, sign extending them if the
+    /// field is signed.
+    pub fn decode(&self, raw: i128) -> i128 {
+        decode_field(&self.range, self.signed, raw)
+    }
+}
+
+#[derive(PartialEq)]
 pub enum FieldDisplay {
     Default,
     Hex,
     Decimal,
 }
 
+#[derive(PartialEq)]
 pub struct Token {
     pub name: String,
     pub size: u16,
+    /// Overrides `Spec::endianness` for this token.
+    pub endianness: Option<Endianness>,
     pub fields: Vec<TokenField>,
 }
 
+#[derive(PartialEq)]
 pub struct TokenField {
     pub name: String,
     pub range: Range<u16>,
@@ -128,30 +239,58 @@ pub struct TokenField {
     pub meaning: FieldMeaning,
 }
 
+impl TokenField {
+    /// Interprets the raw bits of the field, sign extending them if the
+    /// field is signed.
+    pub fn decode(&self, raw: i128) -> i128 {
+        decode_field(&self.range, self.signed, raw)
+    }
+}
+
+fn decode_field(range: &Range<u16>, signed: bool, raw: i128) -> i128 {
+    let bits = (range.end - range.start + 1) as u32;
+    let raw = raw & ((1 << bits) - 1);
+    if signed && raw >> (bits - 1) != 0 {
+        raw - (1 << bits)
+    } else {
+        raw
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub enum FieldMeaning {
     Default,
     Variables(Vec<String>),
-    Values(Vec<u128>),
+    Values(Vec<Option<u128>>),
     Names(Vec<String>),
 }
 
+#[derive(PartialEq)]
 pub struct PCodeOp {
     pub name: String,
 }
 
+#[derive(PartialEq)]
 pub struct Constructor {
     pub header: TableHeader,
     pub constraint: Constraint,
     pub calculations: Vec<Calculation>,
     pub actions: Vec<Action>,
+    /// The constraint lowered by `Spec::compile_patterns`.
+    pub pattern: Option<Pattern>,
+    pub span: Span,
 }
 
 impl Constructor {
     pub fn matches(&self, state: State) -> bool {
-        self.constraint.matches(state)
+        match &self.pattern {
+            Some(pattern) => pattern.matches(state),
+            None => self.constraint.matches(state),
+        }
     }
 }
 
+#[derive(PartialEq)]
 pub struct TableHeader {
     pub table: String,
     pub mnemonic: String,
@@ -167,6 +306,19 @@ pub enum ComparisonOperator {
     GreaterEqual,
 }
 
+impl ComparisonOperator {
+    pub fn compare(self, lhs: i128, rhs: i128) -> bool {
+        match self {
+            ComparisonOperator::Equal => lhs == rhs,
+            ComparisonOperator::NotEqual => lhs != rhs,
+            ComparisonOperator::Less => lhs < rhs,
+            ComparisonOperator::LessEqual => lhs <= rhs,
+            ComparisonOperator::Greater => lhs > rhs,
+            ComparisonOperator::GreaterEqual => lhs >= rhs,
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum NumTypePrefix {
     Default,
@@ -174,24 +326,25 @@ pub enum NumTypePrefix {
     Float,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Calculation {
     Assignment(CalculationAssignment),
     GlobalSet(CalculationGlobalSet),
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct CalculationAssignment {
     pub lhs: String,
     pub rhs: RValue,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct CalculationGlobalSet {
     pub lhs: RValue,
     pub rhs: RValue,
 }
 
+#[derive(PartialEq)]
 pub struct Macro {
     pub name: String,
     pub args: Vec<String>,
@@ -199,39 +352,96 @@ pub struct Macro {
 }
 
 impl Macro {
-    pub fn expand<'s>(&'s self, args: &'s [RValue]) -> impl Iterator<Item = Action> + 's {
-        static EXPAND_COUNTER: AtomicU64 = AtomicU64::new(0);
-        let cnt = EXPAND_COUNTER.fetch_add(1, Ordering::Relaxed);
+    /// Returns the macro's actions for an invocation with `args`, `None` if
+    /// the number of arguments is wrong.
+    ///
+    /// Arguments that are plain identifiers are substituted, so assigning to
+    /// a parameter writes the argument; any other argument is evaluated once
+    /// into a local. Parameters, labels and locals of the macro are renamed
+    /// to `{name}.macro{n}` with the first `n` not in `taken`, identifiers in
+    /// `globals` are left alone.
+    pub fn expand(
+        &self,
+        args: &[RValue],
+        globals: &HashSet<String>,
+        taken: &mut HashSet<String>,
+    ) -> Option<Vec<Action>> {
+        if self.args.len() != args.len() {
+            return None;
+        }
 
-        let names = self
-            .args
-            .iter()
-            .map(|s| s.as_str())
-            .chain(self.actions.iter().filter_map(|a| match a {
-                Action::Label(label) => Some(label.as_str()),
-                Action::LocalDecl(local_decl) => Some(local_decl.name.field.as_str()),
-                _ => None,
-            }));
+        let mut fresh = |name: &str| {
+            (0..)
+                .map(|i| format!("{}.macro{}", name, i))
+                .find(|new| taken.insert(new.clone()))
+                .unwrap()
+        };
 
         let mut renames = HashMap::new();
-        for name in names {
-            renames.insert(name.to_string(), format!("macro expand {} {}", name, cnt));
+        let mut actions = Vec::new();
+        for (name, arg) in self.args.iter().zip(args) {
+            match arg {
+                RValue::LValue(LValue::Ident(LValueIdent {
+                    field, size: None, ..
+                })) => {
+                    renames.insert(name.as_str(), field.clone());
+                }
+                _ => {
+                    let local = fresh(name);
+                    actions.push(Action::LocalDecl(ActionLocalDecl {
+                        name: LValueIdent {
+                            field: local.clone(),
+                            size: None,
+                            span: arg.span(),
+                        },
+                        val: arg.clone(),
+                        span: arg.span(),
+                    }));
+                    renames.insert(name.as_str(), local);
+                }
+            }
         }
 
-        self.args
-            .iter()
-            .cloned()
-            .map(|name| LValueIdent {
-                field: name,
-                size: None,
-            })
-            .zip(args.iter().cloned())
-            .map(|(name, val)| Action::LocalDecl(ActionLocalDecl { name, val }))
-            .chain(self.actions.iter().cloned())
-            .map(move |mut a| {
-                a.rename(&renames);
-                a
-            })
+        // assigning to an unknown identifier declares a temporary
+        for action in self.actions.iter() {
+            let name = match action {
+                Action::Label(label) => &label.label,
+                Action::LocalDecl(inner) => &inner.name.field,
+                Action::Assignment(ActionAssignment {
+                    name: LValue::Ident(ident),
+                    ..
+                }) if !globals.contains(&ident.field) => &ident.field,
+                _ => continue,
+            };
+            if !renames.contains_key(name.as_str()) {
+                renames.insert(name, fresh(name));
+            }
+        }
+
+        let mut rename = Rename(&renames);
+        actions.extend(
+            self.actions
+                .iter()
+                .cloned()
+                .map(|action| rename.fold_action(action)),
+        );
+        Some(actions)
+    }
+}
+
+struct CollectIdents<'a>(&'a mut HashSet<String>);
+
+impl Visit for CollectIdents<'_> {
+    fn visit_ident(&mut self, ident: &str) {
+        self.0.insert(ident.to_string());
+    }
+}
+
+struct Rename<'a>(&'a HashMap<&'a str, String>);
+
+impl Fold for Rename<'_> {
+    fn fold_ident(&mut self, ident: String) -> String {
+        self.0.get(ident.as_str()).cloned().unwrap_or(ident)
     }
 }
 
@@ -250,13 +460,77 @@ impl WithBlockContext<'_> {
             &mut self.constraint,
             Constraint::Exists(ConstraintExists {
                 name: String::new(),
+                span: Span::default(),
             }),
         );
-        self.constraint = Constraint::And(Box::new(ConstraintAnd {
-            lhs: constraint,
-            rhs: other.constraint.clone(),
-        }));
+        self.constraint = and_constraints(constraint, other.constraint.clone());
         self.calculation_block
             .extend_from_slice(&other.calculation_block);
     }
 }
+
+/// Joins the constraint of a constructor and the constraint of a surrounding
+/// with block. Operands are parenthesized where needed so that the result
+/// reads the same as if it had been written out in the source. The result
+/// keeps the span of `lhs`, since `rhs` was written elsewhere.
+pub(crate) fn and_constraints(lhs: Constraint, rhs: Constraint) -> Constraint {
+    let span = lhs.span();
+    let lhs = match lhs {
+        Constraint::Ellipsis(_) | Constraint::Or(_) | Constraint::Semi(_) => {
+            Constraint::Parenthesized(Box::new(lhs))
+        }
+        lhs => lhs,
+    };
+    let rhs = match rhs {
+        Constraint::Ellipsis(_) | Constraint::And(_) | Constraint::Or(_) | Constraint::Semi(_) => {
+            Constraint::Parenthesized(Box::new(rhs))
+        }
+        rhs => rhs,
+    };
+    Constraint::And(Box::new(ConstraintAnd { lhs, rhs, span }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Spec;
+
+    #[test]
+    fn test_expand_macros() {
+        let source = r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 x.macro0 ];
+define token instr(8)
+    op = (4,7)
+    tmp = (0,3)
+;
+
+macro inc(x) { x = x + 1; }
+macro swap(a, b) { t = a; a = b; b = t; }
+macro twice(x) { local t = x; inc(t); swap(r0, t); }
+
+:swap tmp is op=0 & tmp { swap(r0, r1); twice(r1 + tmp); }
+"#;
+        let spec = Spec::parse(source);
+        assert!(Spec::parse(source) == spec);
+
+        let printed = spec.print();
+        let actions = printed.lines().skip_while(|l| !l.starts_with(":swap"));
+        assert_eq!(
+            actions.collect::<Vec<_>>(),
+            [
+                ":swap tmp is op=0x0 & tmp {",
+                "    t.macro0:4 = r0;",
+                "    r0 = r1;",
+                "    r1 = t.macro0;",
+                "    local x.macro1:4 = r1 + tmp:4;",
+                "    local t.macro1:4 = x.macro1;",
+                "    t.macro1 = t.macro1 + 1:4;",
+                "    t.macro2:4 = r0;",
+                "    r0 = t.macro1;",
+                "    t.macro1 = t.macro2;",
+                "}",
+            ]
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use super::WithBlockContext;
+use super::{
+    and_constraints,
+    precedence::{climb, constraint_precedence, constraint_rvalue_precedence, rvalue_precedence},
+    WithBlockContext,
+};
 use crate::*;
 use pest::{
     iterators::{Pair, Pairs},
@@ -11,6 +15,8 @@ use std::{
     ops::Range,
 };
 
+/// Also exposes the grammar, for tools that need the concrete syntax tree
+/// parse `Rule::file` with `pest::Parser::parse`.
 #[derive(Parser, Default)]
 #[grammar = "../pest/sleigh.pest"] // relative to src
 pub struct SleighParser {
@@ -28,9 +34,11 @@ pub struct SleighParser {
 impl SleighParser {
     pub fn parse_file(s: &str) -> Spec {
         let stmts: Pairs<Rule> =
-            SleighParser::parse(Rule::file, &s).unwrap_or_else(|e| panic!("{}", e));
-        let mut parser: SleighParser = Default::default();
-        parser.alignment = 1;
+            SleighParser::parse(Rule::file, s).unwrap_or_else(|e| panic!("{}", e));
+        let mut parser = SleighParser {
+            alignment: 1,
+            ..Default::default()
+        };
         parser.parse_stmts(stmts, None);
         parser.finish()
     }
@@ -61,7 +69,7 @@ impl SleighParser {
                 Rule::attach_names => self.attach_names(tokens),
                 Rule::stmt_macro => self.stmt_macro(tokens),
                 Rule::with_block => self.with_block(tokens, with_context),
-                Rule::constructor => self.constructor(tokens, with_context),
+                Rule::constructor => self.constructor(tokens, with_context, span.into()),
                 Rule::EOI => {}
                 rule => {
                     let content = &content[..content.len().min(1000)];
@@ -77,11 +85,15 @@ impl SleighParser {
     }
 
     fn define_endianness(&mut self, mut tokens: Pairs<Rule>) {
-        self.endianness = Some(match tokens.next().unwrap().as_rule() {
+        self.endianness = Some(Self::endianness(tokens.next().unwrap()));
+    }
+
+    fn endianness(token: Pair<Rule>) -> Endianness {
+        match token.as_rule() {
             Rule::little_endian => Endianness::Little,
             Rule::big_endian => Endianness::Big,
             _ => unreachable!(),
-        });
+        }
     }
 
     fn define_alignment(&mut self, mut tokens: Pairs<Rule>) {
@@ -130,11 +142,13 @@ impl SleighParser {
         let size = Self::parse_integer(tokens.next().unwrap());
 
         for name in Self::parse_string_list(tokens.next().unwrap()) {
-            self.registers.push(Register {
-                name: name.to_string(),
-                offset,
-                size,
-            });
+            if name != "_" {
+                self.registers.push(Register {
+                    name: name.to_string(),
+                    offset,
+                    size,
+                });
+            }
             offset += size as u32;
         }
     }
@@ -142,6 +156,14 @@ impl SleighParser {
     fn define_token(&mut self, mut tokens: Pairs<Rule>) {
         let name = tokens.next().unwrap().as_str().to_string();
         let size = Self::parse_integer(tokens.next().unwrap());
+        let mut tokens = tokens.peekable();
+        let endianness = match tokens.peek().map(|t| t.as_rule()) {
+            Some(Rule::token_endianness) => {
+                let token = tokens.next().unwrap();
+                Some(Self::endianness(token.into_inner().next().unwrap()))
+            }
+            _ => None,
+        };
         let fields = tokens
             .map(|token| {
                 let mut tokens = token.into_inner();
@@ -169,7 +191,12 @@ impl SleighParser {
                 field
             })
             .collect();
-        self.tokens.push(Token { name, size, fields })
+        self.tokens.push(Token {
+            name,
+            size,
+            endianness,
+            fields,
+        })
     }
 
     fn define_context(&mut self, mut tokens: Pairs<Rule>) {
@@ -295,7 +322,12 @@ impl SleighParser {
         }
     }
 
-    fn constructor(&mut self, mut tokens: Pairs<Rule>, with_context: Option<&WithBlockContext>) {
+    fn constructor(
+        &mut self,
+        mut tokens: Pairs<Rule>,
+        with_context: Option<&WithBlockContext>,
+        span: Span,
+    ) {
         let mut header = Self::parse_table_header(tokens.next().unwrap());
         let mut constraint = self.parse_constraint(tokens.next().unwrap());
 
@@ -316,10 +348,7 @@ impl SleighParser {
                 debug_assert_eq!(header.table, "");
                 header.table = table.to_string();
             }
-            constraint = Constraint::And(Box::new(ConstraintAnd {
-                lhs: constraint,
-                rhs: ctx.constraint.clone(),
-            }));
+            constraint = and_constraints(constraint, ctx.constraint.clone());
         }
 
         self.constructors.push(Constructor {
@@ -327,6 +356,8 @@ impl SleighParser {
             constraint,
             calculations,
             actions,
+            pattern: None,
+            span,
         });
     }
 
@@ -366,45 +397,34 @@ impl SleighParser {
     }
 
     fn parse_constraint(&self, token: Pair<Rule>) -> Constraint {
-        let constraint = self.parse_raw_constraint(token);
-        fix_precedence_constraint(constraint)
-    }
-
-    fn parse_raw_constraint(&self, token: Pair<Rule>) -> Constraint {
         let rule = token.as_rule();
+        let span = token.as_span().into();
         let mut tokens = token.into_inner();
 
         match rule {
-            Rule::constraint => self.parse_constraint(tokens.next().unwrap()),
-            Rule::constraint_and => {
-                let mut constraint = self.parse_constraint(tokens.next().unwrap());
-                for rhs in tokens.map(|t| self.parse_constraint(t)) {
-                    constraint = Constraint::And(Box::new(ConstraintAnd {
-                        lhs: constraint,
-                        rhs,
-                    }));
-                }
-                constraint
-            }
-            Rule::constraint_or => {
-                let mut constraint = self.parse_constraint(tokens.next().unwrap());
-                for rhs in tokens.map(|t| self.parse_constraint(t)) {
-                    constraint = Constraint::Or(Box::new(ConstraintOr {
-                        lhs: constraint,
-                        rhs,
-                    }));
-                }
-                constraint
-            }
-            Rule::constraint_semi => {
-                let mut constraint = self.parse_constraint(tokens.next().unwrap());
-                for rhs in tokens.map(|t| self.parse_constraint(t)) {
-                    constraint = Constraint::Semi(Box::new(ConstraintSemi {
-                        lhs: constraint,
-                        rhs,
-                    }));
+            Rule::constraint => {
+                let first = self.parse_constraint(tokens.next().unwrap());
+                let mut rest = Vec::new();
+                while let Some(operator) = tokens.next() {
+                    rest.push((
+                        operator.as_str(),
+                        self.parse_constraint(tokens.next().unwrap()),
+                    ));
                 }
-                constraint
+                climb(
+                    first,
+                    rest,
+                    |op| constraint_precedence(op),
+                    |lhs, op, rhs| {
+                        let span = lhs.span().to(rhs.span());
+                        match op {
+                            "&" => ConstraintAnd { lhs, rhs, span }.into(),
+                            "|" => ConstraintOr { lhs, rhs, span }.into(),
+                            ";" => ConstraintSemi { lhs, rhs, span }.into(),
+                            r => unreachable!("{}", r),
+                        }
+                    },
+                )
             }
             Rule::basic_constraint_comparison => {
                 let lhs = tokens.next().unwrap().as_str().to_string();
@@ -423,6 +443,7 @@ impl SleighParser {
                     num_type,
                     comparison,
                     rhs,
+                    span,
                 })
             }
             Rule::basic_constraint_exists => {
@@ -433,9 +454,9 @@ impl SleighParser {
                     .flat_map(|t| t.fields.iter())
                     .any(|f| f.name == name)
                 {
-                    Constraint::Exists(ConstraintExists { name })
+                    Constraint::Exists(ConstraintExists { name, span })
                 } else {
-                    Constraint::Constructor(ConstraintConstructor { name })
+                    Constraint::Constructor(ConstraintConstructor { name, span })
                 }
             }
             Rule::basic_constraint_parenthesized => {
@@ -454,16 +475,45 @@ impl SleighParser {
         }
 
         let mut tokens = token.into_inner();
+
         match rule {
-            Rule::constraint_rvalue => Self::parse_constraint_rvalue(tokens.next().unwrap()),
-            Rule::constraint_rvalue_int_add => {
-                let mut rvalue = Self::parse_constraint_rvalue(tokens.next().unwrap());
-                for rhs in tokens.map(Self::parse_constraint_rvalue) {
-                    rvalue =
-                        ConstraintRValue::Add(Box::new(ConstraintRValueAdd { lhs: rvalue, rhs }));
+            Rule::constraint_rvalue | Rule::constraint_rvalue_parenthesized => {
+                let first = Self::parse_constraint_rvalue(tokens.next().unwrap());
+                let mut rest = Vec::new();
+                while let Some(operator) = tokens.next() {
+                    let operand = Self::parse_constraint_rvalue(tokens.next().unwrap());
+                    rest.push((operator.as_str(), operand));
+                }
+                let value = climb(
+                    first,
+                    rest,
+                    |op| constraint_rvalue_precedence(op),
+                    |lhs, op, rhs| match op {
+                        "+" => ConstraintRValueAdd { lhs, rhs }.into(),
+                        "-" => ConstraintRValueSub { lhs, rhs }.into(),
+                        "*" => ConstraintRValueMult { lhs, rhs }.into(),
+                        "/" => ConstraintRValueDiv { lhs, rhs }.into(),
+                        "$or" | "|" => ConstraintRValueIntOr { lhs, rhs }.into(),
+                        "$and" | "&" => ConstraintRValueIntAnd { lhs, rhs }.into(),
+                        "$xor" | "^" => ConstraintRValueIntXor { lhs, rhs }.into(),
+                        ">>" => ConstraintRValueRShift { lhs, rhs }.into(),
+                        "<<" => ConstraintRValueLShift { lhs, rhs }.into(),
+                        r => unreachable!("{}", r),
+                    },
+                );
+                match rule {
+                    Rule::constraint_rvalue_parenthesized => {
+                        ConstraintRValue::Parenthesized(Box::new(value))
+                    }
+                    _ => value,
                 }
-                rvalue
             }
+            Rule::constraint_rvalue_neg => ConstraintRValue::Neg(Box::new(ConstraintRValueNeg {
+                op: Self::parse_constraint_rvalue(tokens.next().unwrap()),
+            })),
+            Rule::constraint_rvalue_not => ConstraintRValue::Not(Box::new(ConstraintRValueNot {
+                op: Self::parse_constraint_rvalue(tokens.next().unwrap()),
+            })),
             Rule::signed_integer => {
                 ConstraintRValue::Integer(Self::parse_signed_integer(tokens.next().unwrap()))
             }
@@ -518,6 +568,7 @@ impl SleighParser {
 
     fn parse_action(&self, token: Pair<Rule>) -> Action {
         let rule = token.as_rule();
+        let span = token.as_span().into();
 
         if let Rule::label = rule {
             let label = Self::parse_label(token);
@@ -529,25 +580,26 @@ impl SleighParser {
         match rule {
             Rule::action_export => Action::Export(ActionExport {
                 op: Self::parse_rvalue(tokens.next().unwrap()),
+                span,
             }),
             Rule::action_local_decl => {
-                let name = Self::parse_lvalue_ident(tokens.next().unwrap().into_inner());
+                let name = Self::parse_lvalue_ident(tokens.next().unwrap());
                 let val = Self::parse_rvalue(tokens.next().unwrap());
-                Action::LocalDecl(ActionLocalDecl { name, val })
+                Action::LocalDecl(ActionLocalDecl { name, val, span })
             }
             Rule::action_assignment => {
                 let name = Self::parse_lvalue(tokens.next().unwrap());
                 let val = Self::parse_rvalue(tokens.next().unwrap());
-                Action::Assignment(ActionAssignment { name, val })
+                Action::Assignment(ActionAssignment { name, val, span })
             }
             Rule::action_build => {
                 let field = tokens.next().unwrap().as_str().to_string();
-                Action::Build(ActionBuild { field })
+                Action::Build(ActionBuild { field, span })
             }
             Rule::action_if => {
                 let cond = Self::parse_rvalue(tokens.next().unwrap());
                 let action = self.parse_action(tokens.next().unwrap());
-                Action::If(Box::new(ActionIf { cond, action }))
+                Action::If(Box::new(ActionIf { cond, action, span }))
             }
             Rule::action_goto => {
                 let token = tokens.next().unwrap();
@@ -569,34 +621,32 @@ impl SleighParser {
                     Action::PCodeOp(ActionPCodeOp {
                         pcopdeop: name,
                         args,
+                        span,
                     })
                 } else {
                     let args = tokens.map(Self::parse_rvalue).collect();
                     Action::Macro(ActionMacro {
                         r#macro: name,
                         args,
+                        span,
                     })
                 }
             }
             Rule::action_call => {
                 let address = Self::parse_rvalue(tokens.next().unwrap());
-                Action::Call(ActionCall { address })
+                Action::Call(ActionCall { address, span })
             }
             Rule::action_return => {
                 let val = Self::parse_rvalue(tokens.next().unwrap());
-                Action::Return(ActionReturn { val })
+                Action::Return(ActionReturn { val, span })
             }
             r => unreachable!("{:?}", r),
         }
     }
 
     fn parse_rvalue(token: Pair<Rule>) -> RValue {
-        let raw = Self::parse_raw_rvalue(token);
-        fix_precedence_rvalue(raw)
-    }
-
-    fn parse_raw_rvalue(token: Pair<Rule>) -> RValue {
         let rule = token.as_rule();
+        let span = token.as_span().into();
 
         if let Rule::lvalue = rule {
             return RValue::LValue(Self::parse_lvalue(token));
@@ -604,83 +654,99 @@ impl SleighParser {
 
         let mut tokens = token.into_inner();
 
-        if let Rule::rvalue = rule {
-            return Self::parse_rvalue(tokens.next().unwrap());
-        }
-
-        macro_rules! binary_operator_with_prefix {
-            ($rule:ident, $en:ident, $ty:ident) => {
-                if let Rule::$rule = rule {
-                    let mut value = Self::parse_rvalue(tokens.next().unwrap());
-                    while let Some(mut token) = tokens.next() {
-                        let mut num_type_prefix = NumTypePrefix::Default;
-                        if let Rule::num_type_prefix = token.as_rule() {
-                            num_type_prefix = Self::parse_num_type(token);
-                            token = tokens.next().unwrap();
-                        }
-                        let rhs = Self::parse_rvalue(token);
-                        value = RValue::$en(Box::new($ty {
-                            lhs: value,
-                            num_type_prefix,
-                            rhs,
-                        }));
-                    }
-                    return value;
-                }
-            };
-        }
-        macro_rules! binary_operator {
-            ($rule:ident, $en:ident, $ty:ident) => {
-                if let Rule::$rule = rule {
-                    let mut value = Self::parse_rvalue(tokens.next().unwrap());
-                    for token in tokens {
-                        let rhs = Self::parse_rvalue(token);
-                        value = RValue::$en(Box::new($ty { lhs: value, rhs }));
-                    }
-                    return value;
-                }
-            };
-        }
-
-        binary_operator_with_prefix!(rvalue_add, Add, RValueAdd);
-        binary_operator_with_prefix!(rvalue_sub, Sub, RValueSub);
-        binary_operator_with_prefix!(rvalue_mult, Mult, RValueMult);
-        binary_operator_with_prefix!(rvalue_div, Div, RValueDiv);
-        binary_operator_with_prefix!(rvalue_rem, Rem, RValueRem);
-        binary_operator!(rvalue_int_or, IntOr, RValueIntOr);
-        binary_operator!(rvalue_int_and, IntAnd, RValueIntAnd);
-        binary_operator!(rvalue_int_xor, IntXor, RValueIntXor);
-        binary_operator!(rvalue_bool_or, BoolOr, RValueBoolOr);
-        binary_operator!(rvalue_bool_and, BoolAnd, RValueBoolAnd);
-        binary_operator!(rvalue_bool_xor, BoolXor, RValueBoolXor);
-        binary_operator_with_prefix!(rvalue_rshift, RShift, RValueRShift);
-        binary_operator!(rvalue_lshift, LShift, RValueLShift);
-
         match rule {
-            Rule::rvalue_bool_comparison => {
-                let mut value = Self::parse_rvalue(tokens.next().unwrap());
-                if let Some(mut token) = tokens.next() {
+            Rule::rvalue => {
+                let first = Self::parse_rvalue(tokens.next().unwrap());
+                let mut rest = Vec::new();
+                while let Some(operator) = tokens.next() {
                     let mut num_type_prefix = NumTypePrefix::Default;
+                    let mut operator = operator.into_inner();
+                    let mut token = operator.next().unwrap();
                     if let Rule::num_type_prefix = token.as_rule() {
                         num_type_prefix = Self::parse_num_type(token);
-                        token = tokens.next().unwrap();
+                        token = operator.next().unwrap();
                     }
-                    let operator = Self::parse_comparison_operator(token);
                     let rhs = Self::parse_rvalue(tokens.next().unwrap());
-                    value = RValue::Comparison(Box::new(RValueComparison {
-                        lhs: value,
-                        num_type_prefix,
-                        operator,
-                        rhs,
-                    }))
+                    rest.push(((token, num_type_prefix), rhs));
                 }
-                value
+                climb(
+                    first,
+                    rest,
+                    |(op, _)| rvalue_precedence(op.as_str()),
+                    |lhs, (op, num_type_prefix), rhs| {
+                        let span = lhs.span().to(rhs.span());
+                        if let Rule::comparison_operator = op.as_rule() {
+                            let operator = Self::parse_comparison_operator(op);
+                            return RValueComparison {
+                                lhs,
+                                num_type_prefix,
+                                operator,
+                                rhs,
+                                span,
+                            }
+                            .into();
+                        }
+                        match op.as_str() {
+                            "+" => RValueAdd {
+                                lhs,
+                                num_type_prefix,
+                                rhs,
+                                span,
+                            }
+                            .into(),
+                            "-" => RValueSub {
+                                lhs,
+                                num_type_prefix,
+                                rhs,
+                                span,
+                            }
+                            .into(),
+                            "*" => RValueMult {
+                                lhs,
+                                num_type_prefix,
+                                rhs,
+                                span,
+                            }
+                            .into(),
+                            "/" => RValueDiv {
+                                lhs,
+                                num_type_prefix,
+                                rhs,
+                                span,
+                            }
+                            .into(),
+                            "%" => RValueRem {
+                                lhs,
+                                num_type_prefix,
+                                rhs,
+                                span,
+                            }
+                            .into(),
+                            ">>" => RValueRShift {
+                                lhs,
+                                num_type_prefix,
+                                rhs,
+                                span,
+                            }
+                            .into(),
+                            "<<" => RValueLShift { lhs, rhs, span }.into(),
+                            "|" => RValueIntOr { lhs, rhs, span }.into(),
+                            "&" => RValueIntAnd { lhs, rhs, span }.into(),
+                            "^" => RValueIntXor { lhs, rhs, span }.into(),
+                            "||" | "$or" => RValueBoolOr { lhs, rhs, span }.into(),
+                            "&&" | "$and" => RValueBoolAnd { lhs, rhs, span }.into(),
+                            "^^" | "$xor" => RValueBoolXor { lhs, rhs, span }.into(),
+                            r => unreachable!("{}", r),
+                        }
+                    },
+                )
             }
             Rule::rvalue_not => {
                 let token = tokens.next().unwrap();
                 if let Rule::not_operator = token.as_rule() {
                     RValue::Not(Box::new(RValueNot {
                         op: Self::parse_rvalue(tokens.next().unwrap()),
+                        span,
                     }))
                 } else {
                     Self::parse_rvalue(token)
@@ -691,6 +757,7 @@ impl SleighParser {
                 if let Rule::neg_operator = token.as_rule() {
                     RValue::Neg(Box::new(RValueNeg {
                         op: Self::parse_rvalue(tokens.next().unwrap()),
+                        span,
                     }))
                 } else {
                     Self::parse_rvalue(token)
@@ -699,16 +766,16 @@ impl SleighParser {
             Rule::rvalue_basic_int => {
                 let value = Self::parse_signed_integer(tokens.next().unwrap());
                 let size = tokens.next().map(Self::parse_integer);
-                RValue::Constant(RValueConstant { value, size })
+                RValue::Constant(RValueConstant { value, size, span })
             }
             Rule::rvalue_basic_call => {
                 let call = tokens.next().unwrap().as_str().to_string();
                 let args = tokens.map(Self::parse_rvalue).collect();
-                RValue::Call(RValueCall { call, args })
+                RValue::Call(RValueCall { call, args, span })
             }
             Rule::rvalue_basic_parenthesized => {
                 let op = Self::parse_rvalue(tokens.next().unwrap());
-                RValue::Parenthesized(Box::new(RValueParenthesized { op }))
+                RValue::Parenthesized(Box::new(RValueParenthesized { op, span }))
             }
             Rule::rvalue_basic_ref => {
                 let mut token = tokens.next().unwrap();
@@ -718,11 +785,11 @@ impl SleighParser {
                     token = tokens.next().unwrap();
                 }
                 let field = token.as_str().to_string();
-                RValue::Ref(RValueRef { field, size })
+                RValue::Ref(RValueRef { field, size, span })
             }
             Rule::rvalue_basic_deref => {
                 let op = Self::parse_rvalue(tokens.next().unwrap());
-                RValue::Deref(Box::new(RValueDeref { op }))
+                RValue::Deref(Box::new(RValueDeref { op, span }))
             }
             r => unreachable!("{:?}", r),
         }
@@ -730,11 +797,16 @@ impl SleighParser {
 
     fn parse_lvalue(token: Pair<Rule>) -> LValue {
         let rule = token.as_rule();
+        let span = token.as_span().into();
+
+        if let Rule::lvalue_ident = rule {
+            return LValue::Ident(Self::parse_lvalue_ident(token));
+        }
+
         let mut tokens = token.into_inner();
 
         match rule {
             Rule::lvalue => Self::parse_lvalue(tokens.next().unwrap()),
-            Rule::lvalue_ident => LValue::Ident(Self::parse_lvalue_ident(tokens)),
             Rule::lvalue_ref => {
                 let mut token = tokens.next().unwrap();
                 let mut space = None;
@@ -752,6 +824,7 @@ impl SleighParser {
                     space,
                     size,
                     op: Box::new(op),
+                    span,
                 })
             }
             Rule::lvalue_slice => {
@@ -762,21 +835,27 @@ impl SleighParser {
                     field,
                     offset,
                     size,
+                    span,
                 })
             }
             r => unreachable!("{:?}", r),
         }
     }
 
-    fn parse_lvalue_ident(mut tokens: Pairs<Rule>) -> LValueIdent {
+    fn parse_lvalue_ident(token: Pair<Rule>) -> LValueIdent {
+        debug_assert_eq!(token.as_rule(), Rule::lvalue_ident);
+        let span = token.as_span().into();
+        let mut tokens = token.into_inner();
         let field = tokens.next().unwrap().as_str().to_string();
         let size = tokens.next().map(Self::parse_integer);
-        LValueIdent { field, size }
+        LValueIdent { field, size, span }
     }
 
-    fn parse_label(token: Pair<Rule>) -> String {
+    fn parse_label(token: Pair<Rule>) -> ActionLabel {
         debug_assert_eq!(token.as_rule(), Rule::label);
-        token.into_inner().as_str().to_string()
+        let span = token.as_span().into();
+        let label = token.into_inner().as_str().to_string();
+        ActionLabel { label, span }
     }
 
     fn parse_num_type(token: Pair<Rule>) -> NumTypePrefix {
@@ -789,13 +868,16 @@ impl SleighParser {
     }
 
     fn parse_integer_list<I: TryFrom<u128> + 'static>(
-        token: Pair<Rule>,
-    ) -> impl Iterator<Item = I> + Clone + '_
+        token: Pair<'_, Rule>,
+    ) -> impl Iterator<Item = Option<I>> + Clone + '_
     where
         I::Error: Debug,
     {
         debug_assert_eq!(token.as_rule(), Rule::integer_list);
-        token.into_inner().map(Self::parse_integer)
+        token.into_inner().map(|token| match token.as_rule() {
+            Rule::placeholder => None,
+            _ => Some(Self::parse_integer(token)),
+        })
     }
 
     fn parse_integer<I: TryFrom<u128>>(token: Pair<Rule>) -> I
@@ -838,12 +920,15 @@ impl SleighParser {
         I::try_from(value * factor).unwrap()
     }
 
-    fn parse_string_list(token: Pair<Rule>) -> impl Iterator<Item = &str> + Clone + '_ {
+    fn parse_string_list(token: Pair<'_, Rule>) -> impl Iterator<Item = &str> + Clone + '_ {
         debug_assert_eq!(token.as_rule(), Rule::string_list);
-        token.into_inner().map(Self::parse_ident_or_string)
+        token.into_inner().map(|token| match token.as_rule() {
+            Rule::placeholder => "_",
+            _ => Self::parse_ident_or_string(token),
+        })
     }
 
-    fn parse_ident_or_string(token: Pair<Rule>) -> &str {
+    fn parse_ident_or_string(token: Pair<'_, Rule>) -> &str {
         let token = token.into_inner().next().unwrap();
         match token.as_rule() {
             Rule::ident => token.as_str(),
@@ -852,7 +937,7 @@ impl SleighParser {
         }
     }
 
-    fn parse_string(token: Pair<Rule>) -> &str {
+    fn parse_string(token: Pair<'_, Rule>) -> &str {
         debug_assert_eq!(token.as_rule(), Rule::string);
         token.into_inner().next().unwrap().as_str()
     }
@@ -868,6 +953,8 @@ impl SleighParser {
             pcodeops: self.pcodeops,
             constructors: self.constructors,
             macros: self.macros,
+            compiler_spec: None,
+            decision_tree: DecisionTree::default(),
         }
     }
 }
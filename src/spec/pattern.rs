@@ -0,0 +1,612 @@
+use super::*;
+use crate::State;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+/// The bits of a byte string that have a fixed value.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct BitPattern {
+    pub mask: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl BitPattern {
+    pub fn len(&self) -> usize {
+        self.value.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.mask
+            .iter()
+            .zip(self.value.iter())
+            .enumerate()
+            .all(|(i, (mask, value))| match data.get(i) {
+                Some(b) => b & mask == *value,
+                None => *mask == 0,
+            })
+    }
+
+    /// Overlays `other` at byte `offset`, fails if a fixed bit differs.
+    pub fn merge(&self, other: &BitPattern, offset: usize) -> Option<BitPattern> {
+        let mut merged = self.clone();
+        merged.extend(offset + other.len());
+        for (i, (mask, value)) in other.mask.iter().zip(other.value.iter()).enumerate() {
+            let common = merged.mask[offset + i] & mask;
+            if (merged.value[offset + i] ^ value) & common != 0 {
+                return None;
+            }
+            merged.mask[offset + i] |= mask;
+            merged.value[offset + i] |= value & mask;
+        }
+        Some(merged)
+    }
+
+    /// Whether every bit fixed by `other` is fixed to the same value here.
+    pub fn contains(&self, other: &BitPattern) -> bool {
+        other
+            .mask
+            .iter()
+            .zip(other.value.iter())
+            .enumerate()
+            .all(|(i, (mask, value))| {
+                let own_mask = self.mask.get(i).copied().unwrap_or_default();
+                let own_value = self.value.get(i).copied().unwrap_or_default();
+                own_mask & mask == *mask && own_value & mask == *value
+            })
+    }
+
+    pub(crate) fn extend(&mut self, len: usize) {
+        if len > self.len() {
+            self.mask.resize(len, 0);
+            self.value.resize(len, 0);
+        }
+    }
+
+    /// Fixes the bits of a field in the `len` byte token at `offset`, fails
+    /// if they are already fixed to something else.
+    pub(crate) fn set_field(
+        &mut self,
+        offset: usize,
+        len: usize,
+        range: &Range<u16>,
+        value: i128,
+        endianness: Endianness,
+    ) -> Option<()> {
+        let mut field = BitPattern::default();
+        field.extend(offset + len);
+        for (n, bit) in (range.start..=range.end).enumerate() {
+            let byte = match endianness {
+                Endianness::Little => bit as usize / 8,
+                Endianness::Big => len - bit as usize / 8 - 1,
+            };
+            let bit = 1 << (bit % 8);
+            field.mask[offset + byte] |= bit;
+            if value >> n & 1 != 0 {
+                field.value[offset + byte] |= bit;
+            }
+        }
+        *self = self.merge(&field, 0)?;
+        Some(())
+    }
+}
+
+/// A constraint lowered to bit patterns, one alternative per branch of its
+/// `|` operators.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Pattern {
+    pub alternatives: Vec<DisjointPattern>,
+}
+
+impl Pattern {
+    pub fn matches(&self, state: State) -> bool {
+        self.alternatives.iter().any(|a| a.matches(state.clone()))
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct DisjointPattern {
+    pub instruction: BitPattern,
+    /// Patterns over the context registers, by register name.
+    pub context: BTreeMap<String, BitPattern>,
+    /// Constraints that can't be expressed as bits, with the byte offset
+    /// they apply at. These are subtables and comparisons on wide fields.
+    pub residual: Vec<(usize, Constraint)>,
+    /// The number of bytes matched, `None` if it depends on a subtable.
+    pub len: Option<usize>,
+    /// Token fields and subtables the constraint refers to, with the byte
+    /// offset of their token. Those after a subtable of varying length are
+    /// only part of the residual.
+    pub operands: Vec<(usize, String)>,
+}
+
+impl DisjointPattern {
+    pub fn matches(&self, state: State) -> bool {
+        self.instruction.matches(state.code)
+            && self.context.iter().all(|(register, pattern)| {
+                state
+                    .register_data(register)
+                    .is_some_and(|data| pattern.matches(data))
+            })
+            && self.residual.iter().all(|(offset, constraint)| {
+                let mut state = state.clone();
+                if state.code.len() < *offset {
+                    return false;
+                }
+                state.code = &state.code[*offset..];
+                constraint.matches(state)
+            })
+    }
+
+    /// Whether this pattern is a special case of `other`: it fixes at least
+    /// the same bits to the same values and has at least the same residual
+    /// checks, but isn't identical.
+    pub fn specializes(&self, other: &DisjointPattern) -> bool {
+        let contains = self.instruction.contains(&other.instruction)
+            && other.context.iter().all(|(register, pattern)| {
+                self.context
+                    .get(register)
+                    .is_some_and(|own| own.contains(pattern))
+            })
+            && other.residual.iter().all(|r| self.residual.contains(r));
+        let reverse = other.instruction.contains(&self.instruction)
+            && self.context.iter().all(|(register, pattern)| {
+                other
+                    .context
+                    .get(register)
+                    .is_some_and(|own| own.contains(pattern))
+            })
+            && self.residual.iter().all(|r| other.residual.contains(r));
+        contains && !reverse
+    }
+
+    pub(crate) fn and(&self, other: &DisjointPattern) -> Option<DisjointPattern> {
+        let mut context = self.context.clone();
+        for (register, pattern) in other.context.iter() {
+            let merged = match context.get(register) {
+                Some(existing) => existing.merge(pattern, 0)?,
+                None => pattern.clone(),
+            };
+            context.insert(register.clone(), merged);
+        }
+        Some(DisjointPattern {
+            instruction: self.instruction.merge(&other.instruction, 0)?,
+            context,
+            residual: self
+                .residual
+                .iter()
+                .chain(other.residual.iter())
+                .cloned()
+                .collect(),
+            len: self.len.zip(other.len).map(|(l, r)| l.max(r)),
+            operands: self
+                .operands
+                .iter()
+                .chain(other.operands.iter().filter(|o| !self.operands.contains(o)))
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+/// Formats the bits of a pattern most significant first, `.` for bits that
+/// aren't fixed.
+impl Display for BitPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (mask, value)) in self.mask.iter().zip(self.value.iter()).enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            for bit in (0..8).rev() {
+                let c = match (mask >> bit & 1, value >> bit & 1) {
+                    (0, _) => '.',
+                    (_, 0) => '0',
+                    _ => '1',
+                };
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for DisjointPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.instruction)?;
+        for (register, bits) in self.context.iter() {
+            write!(f, " {}={}", register, bits)?;
+        }
+        Ok(())
+    }
+}
+
+impl Spec {
+    /// Lowers the constraint of every constructor into `Constructor::pattern`.
+    pub fn compile_patterns(&mut self) {
+        // the length of a subtable is only known once its constructors are
+        // compiled, so run until no more lengths are found
+        let mut table_lens = HashMap::new();
+        let mut patterns = Vec::new();
+        for _ in 0..=self.constructors.len() {
+            let compiler = PatternCompiler {
+                spec: self,
+                table_lens: &table_lens,
+                token: None,
+            };
+            patterns = self
+                .constructors
+                .iter()
+                .map(|c| compiler.scoped(&c.constraint).constraint(&c.constraint, 0))
+                .collect();
+
+            let mut lens: HashMap<String, Option<usize>> = HashMap::new();
+            for (constructor, pattern) in self.constructors.iter().zip(patterns.iter()) {
+                for alternative in pattern.alternatives.iter() {
+                    let len = lens
+                        .entry(constructor.header.table.clone())
+                        .or_insert(alternative.len);
+                    if *len != alternative.len {
+                        *len = None;
+                    }
+                }
+            }
+            let lens: HashMap<_, _> = lens
+                .into_iter()
+                .filter_map(|(table, len)| Some((table, len?)))
+                .collect();
+
+            if lens == table_lens {
+                break;
+            }
+            table_lens = lens;
+        }
+
+        for (constructor, pattern) in self.constructors.iter_mut().zip(patterns) {
+            constructor.pattern = Some(pattern);
+        }
+    }
+}
+
+/// Comparisons other than `=` on fields up to this wide are enumerated.
+const MAX_ENUMERATED_BITS: u16 = 4;
+
+#[derive(Clone, Copy)]
+struct PatternCompiler<'s> {
+    spec: &'s Spec,
+    table_lens: &'s HashMap<String, usize>,
+    /// See `State::token`.
+    token: Option<&'s Token>,
+}
+
+impl<'s> PatternCompiler<'s> {
+    fn scoped(&self, constraint: &Constraint) -> PatternCompiler<'s> {
+        PatternCompiler {
+            token: constraint.token(self.spec),
+            ..*self
+        }
+    }
+
+    fn constraint(&self, constraint: &Constraint, offset: usize) -> Pattern {
+        let alternatives = match constraint {
+            Constraint::Ellipsis(inner) => return self.constraint(&inner.op, offset),
+            Constraint::Parenthesized(inner) => return self.constraint(inner, offset),
+            Constraint::And(inner) => {
+                let lhs = self.constraint(&inner.lhs, offset);
+                let rhs = self.constraint(&inner.rhs, offset);
+                lhs.alternatives
+                    .iter()
+                    .flat_map(|l| rhs.alternatives.iter().filter_map(move |r| l.and(r)))
+                    .collect()
+            }
+            Constraint::Or(inner) => {
+                let mut lhs = self.constraint(&inner.lhs, offset);
+                lhs.alternatives
+                    .extend(self.constraint(&inner.rhs, offset).alternatives);
+                lhs.alternatives
+            }
+            Constraint::Semi(inner) => {
+                let lhs = self.scoped(&inner.lhs).constraint(&inner.lhs, offset);
+                let mut alternatives = Vec::new();
+                for l in lhs.alternatives {
+                    match l.len {
+                        Some(len) => {
+                            let rhs = self.scoped(&inner.rhs).constraint(&inner.rhs, len);
+                            alternatives.extend(rhs.alternatives.iter().filter_map(|r| l.and(r)))
+                        }
+                        // the rest can only be matched once the length of the
+                        // subtable is known
+                        None => {
+                            let mut l = l;
+                            l.residual.push((offset, constraint.clone()));
+                            alternatives.push(l);
+                        }
+                    }
+                }
+                alternatives
+            }
+            Constraint::Comparison(inner) => self.comparison(inner, constraint, offset),
+            Constraint::Exists(inner) => match self.token_field(&inner.name) {
+                Some((token, _)) => {
+                    let len = token.size as usize / 8;
+                    let mut alternative = DisjointPattern {
+                        len: Some(offset + len),
+                        operands: vec![(offset, inner.name.clone())],
+                        ..Default::default()
+                    };
+                    alternative.instruction.extend(offset + len);
+                    vec![alternative]
+                }
+                None => unresolved(constraint, offset),
+            },
+            Constraint::Constructor(inner) => {
+                let is_table = inner.name == "instruction"
+                    || self
+                        .spec
+                        .constructors
+                        .iter()
+                        .any(|c| c.header.table == inner.name);
+                if is_table {
+                    vec![DisjointPattern {
+                        residual: vec![(offset, constraint.clone())],
+                        len: self.table_lens.get(&inner.name).map(|len| offset + len),
+                        operands: vec![(offset, inner.name.clone())],
+                        ..Default::default()
+                    }]
+                } else {
+                    // context fields and `epsilon` don't constrain anything
+                    vec![DisjointPattern {
+                        len: Some(offset),
+                        ..Default::default()
+                    }]
+                }
+            }
+        };
+        Pattern { alternatives }
+    }
+
+    fn comparison(
+        &self,
+        comparison: &ConstraintComparison,
+        constraint: &Constraint,
+        offset: usize,
+    ) -> Vec<DisjointPattern> {
+        let field = match &comparison.lhs {
+            ConstraintRValue::Field(field) => field.as_str(),
+            _ => return unresolved(constraint, offset),
+        };
+
+        let mut alternative = DisjointPattern::default();
+        let (range, signed) = if let Some((token, token_field)) = self.token_field(field) {
+            let len = token.size as usize / 8;
+            if !fits(&token_field.range, len) {
+                return unresolved(constraint, offset);
+            }
+            alternative.len = Some(offset + len);
+            alternative.instruction.extend(offset + len);
+            alternative.operands.push((offset, field.to_string()));
+            (&token_field.range, token_field.signed)
+        } else if let Some((register, context_field)) = self.context_field(field) {
+            if !fits(&context_field.range, register.size as usize) {
+                return unresolved(constraint, offset);
+            }
+            alternative.len = Some(offset);
+            (&context_field.range, context_field.signed)
+        } else {
+            return unresolved(constraint, offset);
+        };
+
+        let values = match (comparison.comparison, constant(&comparison.rhs)) {
+            (ComparisonOperator::Equal, Some(value)) => vec![value],
+            // like Ghidra, small fields are split into one alternative per
+            // value so that specificity can be decided on the bits alone
+            (op, Some(rhs)) if range.end - range.start < MAX_ENUMERATED_BITS => {
+                let bits = (range.end - range.start + 1) as u32;
+                (0..1 << bits)
+                    .map(|raw| decode_field(range, signed, raw))
+                    .filter(|value| op.compare(*value, rhs))
+                    .collect()
+            }
+            _ => {
+                alternative.residual.push((offset, constraint.clone()));
+                return vec![alternative];
+            }
+        };
+
+        values
+            .into_iter()
+            .filter_map(|value| {
+                let value = fit(range, signed, value)?;
+                let mut alternative = alternative.clone();
+                if let Some((token, _)) = self.token_field(field) {
+                    let len = token.size as usize / 8;
+                    alternative
+                        .instruction
+                        .set_field(offset, len, range, value, self.spec.token_endianness(token))
+                        .unwrap();
+                } else {
+                    let (register, _) = self.context_field(field).unwrap();
+                    let mut pattern = BitPattern::default();
+                    pattern
+                        .set_field(
+                            0,
+                            register.size as usize,
+                            range,
+                            value,
+                            self.spec.endianness,
+                        )
+                        .unwrap();
+                    alternative.context.insert(register.name.clone(), pattern);
+                }
+                Some(alternative)
+            })
+            .collect()
+    }
+
+    fn token_field(&self, name: &str) -> Option<(&'s Token, &'s TokenField)> {
+        self.token
+            .into_iter()
+            .chain(self.spec.tokens.iter())
+            .find_map(|token| {
+                let field = token.fields.iter().find(|f| f.name == name)?;
+                Some((token, field))
+            })
+    }
+
+    fn context_field(&self, name: &str) -> Option<(&Register, &ContextField)> {
+        self.spec.contexts.iter().find_map(|context| {
+            let field = context.fields.iter().find(|f| f.name == name)?;
+            let register = self
+                .spec
+                .registers
+                .iter()
+                .find(|r| r.name == context.register)?;
+            Some((register, field))
+        })
+    }
+}
+
+/// Constraints on undefined or malformed fields are left to be checked when
+/// matching, `Spec::validate` reports them.
+fn unresolved(constraint: &Constraint, offset: usize) -> Vec<DisjointPattern> {
+    vec![DisjointPattern {
+        residual: vec![(offset, constraint.clone())],
+        len: Some(offset),
+        ..Default::default()
+    }]
+}
+
+/// Whether the bits of a field lie within `len` bytes.
+fn fits(range: &Range<u16>, len: usize) -> bool {
+    range.start <= range.end && (range.end as usize) < len * 8
+}
+
+fn constant(rvalue: &ConstraintRValue) -> Option<i128> {
+    rvalue.evaluate(&mut |_| None)
+}
+
+/// Returns the raw bits of `value`, `None` if it doesn't fit in `range`.
+fn fit(range: &Range<u16>, signed: bool, value: i128) -> Option<i128> {
+    let bits = (range.end - range.start + 1) as u32;
+    let (min, max) = if signed {
+        (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+    } else {
+        (0, (1 << bits) - 1)
+    };
+    if value < min || value > max {
+        None
+    } else {
+        Some(value & ((1 << bits) - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BitPattern, Spec};
+
+    #[test]
+    fn test_compile_patterns() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 ctx ];
+define token instr(16)
+    op = (12,15)
+    dst = (8,9)
+    src = (6,7)
+;
+define token imm16(16)
+    imm = (0,15)
+;
+define context ctx
+    mode = (0,0)
+;
+
+Src: src is src & src!=0 { export src; }
+
+:mov dst, src is mode=1 & op=1 & dst & src { dst = src; }
+:li dst, imm is (op=2 | op=3) & dst; imm & imm!=0 { dst = imm; }
+:ld dst, Src is op=4 & dst & Src { dst = Src; }
+"#,
+        );
+
+        let mov = spec.constructors[1].pattern.as_ref().unwrap();
+        assert_eq!(mov.alternatives.len(), 1);
+        assert_eq!(
+            mov.alternatives[0].instruction,
+            BitPattern {
+                mask: vec![0x00, 0xf0],
+                value: vec![0x00, 0x10],
+            }
+        );
+        assert_eq!(
+            mov.alternatives[0].context["ctx"],
+            BitPattern {
+                mask: vec![0x01, 0x00, 0x00, 0x00],
+                value: vec![0x01, 0x00, 0x00, 0x00],
+            }
+        );
+
+        let li = spec.constructors[2].pattern.as_ref().unwrap();
+        assert_eq!(li.alternatives.len(), 2);
+        assert_eq!(
+            li.alternatives[1].instruction.value,
+            [0x00, 0x30, 0x00, 0x00]
+        );
+        assert_eq!(li.alternatives[1].len, Some(4));
+        assert_eq!(li.alternatives[1].residual.len(), 1);
+
+        let ld = spec.constructors[3].pattern.as_ref().unwrap();
+        assert_eq!(ld.alternatives[0].residual.len(), 1);
+        assert_eq!(ld.alternatives[0].len, Some(2));
+        let src = spec.constructors[0].pattern.as_ref().unwrap();
+        assert_eq!(src.alternatives.len(), 3);
+        assert!(src.alternatives.iter().all(|a| a.residual.is_empty()));
+
+        let code = [0x40, 0x40];
+        assert!(spec.constructors[3].matches(crate::State::new(&spec, &code)));
+        let code = [0x00, 0x40];
+        assert!(!spec.constructors[3].matches(crate::State::new(&spec, &code)));
+    }
+
+    #[test]
+    fn test_compile_undefined_fields() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define token instr(8)
+    op = (0,7)
+    wide = (4,11)
+;
+define context missing
+    mode = (0,0)
+;
+
+:x is nope=1 { }
+:y is nope { }
+:z is mode=1 & op=2 { }
+:w is wide=1 { }
+"#,
+        );
+
+        for i in [0, 2, 3] {
+            let pattern = spec.constructors[i].pattern.as_ref().unwrap();
+            assert!(!pattern.alternatives[0].residual.is_empty());
+        }
+        let undefined: Vec<_> = spec
+            .validate()
+            .into_iter()
+            .filter_map(|d| match d.kind {
+                crate::DiagnosticKind::UndefinedSymbol(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        assert!(undefined.contains(&"nope".to_string()));
+    }
+}
@@ -55,28 +55,153 @@ impl Fix<Constraint> for Constraint {
     }
 }
 
+/// The precedence of a constraint operator as written in the source, the
+/// same as the `Fix` implementations below use.
+pub(crate) fn constraint_precedence(operator: &str) -> u8 {
+    match operator {
+        "&" => 14,
+        "|" => 16,
+        ";" => 17,
+        r => unreachable!("{}", r),
+    }
+}
+
+/// Like `constraint_precedence` for operators in pattern expressions.
+pub(crate) fn constraint_rvalue_precedence(operator: &str) -> u8 {
+    match operator {
+        "*" | "/" => 5,
+        "+" | "-" => 6,
+        ">>" | "<<" => 7,
+        "&" | "$and" => 11,
+        "^" | "$xor" => 12,
+        "|" | "$or" => 13,
+        r => unreachable!("{}", r),
+    }
+}
+
 impl_for_binary_operation!(Constraint, ConstraintAnd, lhs, rhs, 14);
 impl_for_binary_operation!(Constraint, ConstraintOr, lhs, rhs, 16);
 impl_for_binary_operation!(Constraint, ConstraintSemi, lhs, rhs, 17);
 
 impl_for_unary_operation!(Constraint, ConstraintEllipsis, op, 18, false);
 
+impl Fix<ConstraintRValue> for ConstraintRValue {
+    fn fix(&self) -> ConstraintRValue {
+        match self {
+            ConstraintRValue::Add(inner) => inner.fix(),
+            ConstraintRValue::Sub(inner) => inner.fix(),
+            ConstraintRValue::Mult(inner) => inner.fix(),
+            ConstraintRValue::Div(inner) => inner.fix(),
+            ConstraintRValue::IntOr(inner) => inner.fix(),
+            ConstraintRValue::IntAnd(inner) => inner.fix(),
+            ConstraintRValue::IntXor(inner) => inner.fix(),
+            ConstraintRValue::RShift(inner) => inner.fix(),
+            ConstraintRValue::LShift(inner) => inner.fix(),
+            ConstraintRValue::Neg(inner) => inner.fix(),
+            ConstraintRValue::Not(inner) => inner.fix(),
+            ConstraintRValue::Parenthesized(_)
+            | ConstraintRValue::Field(_)
+            | ConstraintRValue::Integer(_) => self.clone(),
+        }
+    }
+
+    fn set_left(
+        &self,
+        left: impl FnOnce(ConstraintRValue) -> ConstraintRValue,
+    ) -> ConstraintRValue {
+        match self {
+            ConstraintRValue::Add(inner) => inner.set_left(left),
+            ConstraintRValue::Sub(inner) => inner.set_left(left),
+            ConstraintRValue::Mult(inner) => inner.set_left(left),
+            ConstraintRValue::Div(inner) => inner.set_left(left),
+            ConstraintRValue::IntOr(inner) => inner.set_left(left),
+            ConstraintRValue::IntAnd(inner) => inner.set_left(left),
+            ConstraintRValue::IntXor(inner) => inner.set_left(left),
+            ConstraintRValue::RShift(inner) => inner.set_left(left),
+            ConstraintRValue::LShift(inner) => inner.set_left(left),
+            ConstraintRValue::Neg(inner) => inner.set_left(left),
+            ConstraintRValue::Not(inner) => inner.set_left(left),
+            ConstraintRValue::Parenthesized(_)
+            | ConstraintRValue::Field(_)
+            | ConstraintRValue::Integer(_) => unreachable!(),
+        }
+    }
+
+    fn set_right(
+        &self,
+        right: impl FnOnce(ConstraintRValue) -> ConstraintRValue,
+    ) -> ConstraintRValue {
+        match self {
+            ConstraintRValue::Add(inner) => inner.set_right(right),
+            ConstraintRValue::Sub(inner) => inner.set_right(right),
+            ConstraintRValue::Mult(inner) => inner.set_right(right),
+            ConstraintRValue::Div(inner) => inner.set_right(right),
+            ConstraintRValue::IntOr(inner) => inner.set_right(right),
+            ConstraintRValue::IntAnd(inner) => inner.set_right(right),
+            ConstraintRValue::IntXor(inner) => inner.set_right(right),
+            ConstraintRValue::RShift(inner) => inner.set_right(right),
+            ConstraintRValue::LShift(inner) => inner.set_right(right),
+            ConstraintRValue::Neg(inner) => inner.set_right(right),
+            ConstraintRValue::Not(inner) => inner.set_right(right),
+            ConstraintRValue::Parenthesized(_)
+            | ConstraintRValue::Field(_)
+            | ConstraintRValue::Integer(_) => unreachable!(),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            ConstraintRValue::Add(inner) => inner.precedence(),
+            ConstraintRValue::Sub(inner) => inner.precedence(),
+            ConstraintRValue::Mult(inner) => inner.precedence(),
+            ConstraintRValue::Div(inner) => inner.precedence(),
+            ConstraintRValue::IntOr(inner) => inner.precedence(),
+            ConstraintRValue::IntAnd(inner) => inner.precedence(),
+            ConstraintRValue::IntXor(inner) => inner.precedence(),
+            ConstraintRValue::RShift(inner) => inner.precedence(),
+            ConstraintRValue::LShift(inner) => inner.precedence(),
+            ConstraintRValue::Neg(inner) => inner.precedence(),
+            ConstraintRValue::Not(inner) => inner.precedence(),
+            ConstraintRValue::Parenthesized(_)
+            | ConstraintRValue::Field(_)
+            | ConstraintRValue::Integer(_) => 0,
+        }
+    }
+}
+
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueMult, lhs, rhs, 5);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueDiv, lhs, rhs, 5);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueAdd, lhs, rhs, 6);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueSub, lhs, rhs, 6);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueRShift, lhs, rhs, 7);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueLShift, lhs, rhs, 7);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueIntAnd, lhs, rhs, 11);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueIntXor, lhs, rhs, 12);
+impl_for_binary_operation!(ConstraintRValue, ConstraintRValueIntOr, lhs, rhs, 13);
+
+impl_for_unary_operation!(ConstraintRValue, ConstraintRValueNeg, op, 3, false);
+impl_for_unary_operation!(ConstraintRValue, ConstraintRValueNot, op, 3, false);
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        spec::precedence::fix_precedence, Constraint, ConstraintAnd, ConstraintExists, ConstraintOr,
+        spec::precedence::fix_precedence, Constraint, ConstraintAnd, ConstraintExists,
+        ConstraintOr, Span,
     };
 
     #[test]
     fn test_precedence() {
         let a: Constraint = Constraint::Exists(ConstraintExists {
             name: "A".to_string(),
+            span: Span::default(),
         });
         let b: Constraint = Constraint::Exists(ConstraintExists {
             name: "B".to_string(),
+            span: Span::default(),
         });
         let c: Constraint = Constraint::Exists(ConstraintExists {
             name: "C".to_string(),
+            span: Span::default(),
         });
 
         assert_eq!(
@@ -85,11 +210,18 @@ mod tests {
                 rhs: Constraint::Or(Box::new(ConstraintOr {
                     lhs: b.clone(),
                     rhs: c.clone(),
-                }))
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             }))),
             Constraint::Or(Box::new(ConstraintOr {
-                lhs: Constraint::And(Box::new(ConstraintAnd { lhs: a, rhs: b })),
+                lhs: Constraint::And(Box::new(ConstraintAnd {
+                    lhs: a,
+                    rhs: b,
+                    span: Span::default(),
+                })),
                 rhs: c,
+                span: Span::default(),
             }))
         );
     }
@@ -1,7 +1,11 @@
 mod constraint;
 mod rvalue;
 
+pub(crate) use constraint::{constraint_precedence, constraint_rvalue_precedence};
+pub(crate) use rvalue::rvalue_precedence;
+
 use super::*;
+use std::iter::Peekable;
 
 pub fn fix_precedence_rvalue(value: RValue) -> RValue {
     fix_precedence(value)
@@ -11,6 +15,46 @@ pub fn fix_precedence_constraint(value: Constraint) -> Constraint {
     fix_precedence(value)
 }
 
+pub fn fix_precedence_constraint_rvalue(value: ConstraintRValue) -> ConstraintRValue {
+    fix_precedence(value)
+}
+
+/// Combines `first` and the operators and operands following it into one
+/// tree in a single pass. Operators with a lower precedence bind tighter,
+/// operators with the same precedence associate to the left.
+pub(crate) fn climb<T, O>(
+    first: T,
+    rest: impl IntoIterator<Item = (O, T)>,
+    precedence: impl Fn(&O) -> u8,
+    build: impl Fn(T, O, T) -> T,
+) -> T {
+    let mut rest = rest.into_iter().peekable();
+    climb_until(first, &mut rest, u8::MAX, &precedence, &build)
+}
+
+fn climb_until<T, O>(
+    mut lhs: T,
+    rest: &mut Peekable<impl Iterator<Item = (O, T)>>,
+    max: u8,
+    precedence: &impl Fn(&O) -> u8,
+    build: &impl Fn(T, O, T) -> T,
+) -> T {
+    while let Some(p) = rest.peek().map(|(op, _)| precedence(op)) {
+        if p > max {
+            break;
+        }
+        let (op, mut rhs) = rest.next().unwrap();
+        while let Some(next) = rest.peek().map(|(op, _)| precedence(op)) {
+            if next >= p {
+                break;
+            }
+            rhs = climb_until(rhs, rest, next, precedence, build);
+        }
+        lhs = build(lhs, op, rhs);
+    }
+    lhs
+}
+
 fn fix_precedence<T: Fix<T>>(mut value: T) -> T {
     loop {
         let next = value.fix();
@@ -143,3 +187,48 @@ macro_rules! impl_for_terminal {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{fix_precedence_constraint, fix_precedence_rvalue, Action, Spec};
+
+    #[test]
+    fn test_climb() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 ];
+define token instr(8)
+    op = (4,7)
+    a = (2,3)
+    b = (0,1)
+;
+
+:x is op=1 & a=2 | b=3; op & b=a + 1 * 2 - 3 {
+    r0 = 8 - 4 - 2 + r1 * 3 / 2 == 1 && r2 s< 3 | 4;
+}
+"#,
+        );
+
+        let constructor = &spec.constructors[0];
+        assert_eq!(
+            constructor.constraint.to_string(),
+            "(((op == 1 && a == 2) || b == 3) ; (op && b == ((a + (1 * 2)) - 3)))"
+        );
+        let val = match &constructor.actions[0] {
+            Action::Assignment(assignment) => &assignment.val,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            val.to_string(),
+            "(((((8:4 - 4:4) - 2:4) + ((r1 * 3:4) / 2:4)) == 1:4) && ((r2 s< 3:4) | 4:1))"
+        );
+
+        assert_eq!(
+            fix_precedence_constraint(constructor.constraint.clone()),
+            constructor.constraint
+        );
+        assert_eq!(fix_precedence_rvalue(val.clone()), *val);
+    }
+}
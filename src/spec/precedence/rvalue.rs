@@ -112,6 +112,23 @@ impl Fix<RValue> for RValue {
     }
 }
 
+/// The precedence of a binary operator as written in the source, the same
+/// as the `Fix` implementations below use.
+pub(crate) fn rvalue_precedence(operator: &str) -> u8 {
+    match operator {
+        "*" | "/" | "%" => 5,
+        "+" | "-" | ">>" | "<<" => 6,
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => 9,
+        "&" => 11,
+        "^" => 12,
+        "|" => 13,
+        "&&" | "$and" => 14,
+        "^^" | "$xor" => 15,
+        "||" | "$or" => 16,
+        r => unreachable!("{}", r),
+    }
+}
+
 impl_for_binary_operation!(RValue, RValueMult, lhs, rhs, 5);
 impl_for_binary_operation!(RValue, RValueDiv, lhs, rhs, 5);
 impl_for_binary_operation!(RValue, RValueRem, lhs, rhs, 5);
@@ -140,7 +157,7 @@ impl_for_terminal!(RValue, LValue);
 #[cfg(test)]
 mod tests {
     use crate::{
-        spec::precedence::fix_precedence, RValue, RValueConstant, RValueIntAnd, RValueIntOr,
+        spec::precedence::fix_precedence, RValue, RValueConstant, RValueIntAnd, RValueIntOr, Span,
     };
 
     #[test]
@@ -148,14 +165,17 @@ mod tests {
         const A: RValueConstant = RValueConstant {
             value: 1,
             size: None,
+            span: Span::new(0, 0),
         };
         const B: RValueConstant = RValueConstant {
             value: 2,
             size: None,
+            span: Span::new(0, 0),
         };
         const C: RValueConstant = RValueConstant {
             value: 3,
             size: None,
+            span: Span::new(0, 0),
         };
 
         assert_eq!(
@@ -163,15 +183,19 @@ mod tests {
                 lhs: RValue::Constant(A),
                 rhs: RValue::IntOr(Box::new(RValueIntOr {
                     lhs: RValue::Constant(B),
-                    rhs: RValue::Constant(C)
-                }))
+                    rhs: RValue::Constant(C),
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             }))),
             RValue::IntOr(Box::new(RValueIntOr {
                 lhs: RValue::IntAnd(Box::new(RValueIntAnd {
                     lhs: RValue::Constant(A),
-                    rhs: RValue::Constant(B)
+                    rhs: RValue::Constant(B),
+                    span: Span::default(),
                 })),
                 rhs: RValue::Constant(C),
+                span: Span::default(),
             }))
         );
     }
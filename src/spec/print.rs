@@ -0,0 +1,536 @@
+use super::*;
+use std::fmt::Write;
+
+impl Spec {
+    /// Prints the spec as SLEIGH source. Parsing the output yields a spec
+    /// equal to `self` (apart from the attached compiler spec).
+    pub fn print(&self) -> String {
+        let mut printer = Printer::default();
+        printer.spec(self);
+        printer.out
+    }
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+}
+
+impl Printer {
+    fn spec(&mut self, spec: &Spec) {
+        writeln!(self.out, "define endian={};", endianness(spec.endianness)).unwrap();
+        writeln!(self.out, "define alignment={};", spec.alignment).unwrap();
+        writeln!(self.out).unwrap();
+
+        for space in spec.spaces.iter() {
+            self.space(space);
+        }
+        for register in spec.registers.iter() {
+            writeln!(
+                self.out,
+                "define register offset={:#x} size={} {};",
+                register.offset, register.size, register.name
+            )
+            .unwrap();
+        }
+        writeln!(self.out).unwrap();
+
+        for token in spec.tokens.iter() {
+            self.token(token);
+        }
+        for context in spec.contexts.iter() {
+            self.context(context);
+        }
+        for pcodeop in spec.pcodeops.iter() {
+            writeln!(self.out, "define pcodeop {};", pcodeop.name).unwrap();
+        }
+        writeln!(self.out).unwrap();
+
+        let fields = spec
+            .tokens
+            .iter()
+            .flat_map(|t| t.fields.iter().map(|f| (&f.name, &f.meaning)))
+            .chain(
+                spec.contexts
+                    .iter()
+                    .flat_map(|c| c.fields.iter().map(|f| (&f.name, &f.meaning))),
+            );
+        for (name, meaning) in fields {
+            self.attach(name, meaning);
+        }
+        writeln!(self.out).unwrap();
+
+        for r#macro in spec.macros.iter() {
+            self.r#macro(r#macro);
+            writeln!(self.out).unwrap();
+        }
+        for constructor in spec.constructors.iter() {
+            self.constructor(constructor);
+        }
+    }
+
+    fn space(&mut self, space: &Space) {
+        let ty = match space.ty {
+            SpaceType::Ram => "ram_space",
+            SpaceType::Rom => "rom_space",
+            SpaceType::Register => "register_space",
+        };
+        write!(
+            self.out,
+            "define space {} type={} size={} wordsize={}",
+            space.name, ty, space.size, space.wordsize
+        )
+        .unwrap();
+        if space.default {
+            write!(self.out, " default").unwrap();
+        }
+        writeln!(self.out, ";").unwrap();
+    }
+
+    fn token(&mut self, token: &Token) {
+        write!(self.out, "define token {}({})", token.name, token.size).unwrap();
+        if let Some(e) = token.endianness {
+            write!(self.out, " endian={}", endianness(e)).unwrap();
+        }
+        writeln!(self.out).unwrap();
+        for field in token.fields.iter() {
+            write!(
+                self.out,
+                "    {} = ({},{})",
+                field.name, field.range.start, field.range.end
+            )
+            .unwrap();
+            if field.signed {
+                write!(self.out, " signed").unwrap();
+            }
+            self.display(&field.display);
+            writeln!(self.out).unwrap();
+        }
+        writeln!(self.out, ";").unwrap();
+    }
+
+    fn context(&mut self, context: &Context) {
+        writeln!(self.out, "define context {}", context.register).unwrap();
+        for field in context.fields.iter() {
+            write!(
+                self.out,
+                "    {} = ({},{})",
+                field.name, field.range.start, field.range.end
+            )
+            .unwrap();
+            if field.signed {
+                write!(self.out, " signed").unwrap();
+            }
+            self.display(&field.display);
+            if !field.flow {
+                write!(self.out, " noflow").unwrap();
+            }
+            writeln!(self.out).unwrap();
+        }
+        writeln!(self.out, ";").unwrap();
+    }
+
+    fn display(&mut self, display: &FieldDisplay) {
+        match display {
+            FieldDisplay::Default => {}
+            FieldDisplay::Hex => write!(self.out, " hex").unwrap(),
+            FieldDisplay::Decimal => write!(self.out, " dec").unwrap(),
+        }
+    }
+
+    fn attach(&mut self, field: &str, meaning: &FieldMeaning) {
+        match meaning {
+            FieldMeaning::Default => return,
+            FieldMeaning::Variables(variables) => {
+                write!(self.out, "attach variables {} [", field).unwrap();
+                for variable in variables.iter() {
+                    write!(self.out, " {}", variable).unwrap();
+                }
+            }
+            FieldMeaning::Values(values) => {
+                write!(self.out, "attach values {} [", field).unwrap();
+                for value in values.iter() {
+                    if let Some(value) = value {
+                        write!(self.out, " {}", value).unwrap();
+                    } else {
+                        write!(self.out, " _").unwrap();
+                    }
+                }
+            }
+            FieldMeaning::Names(names) => {
+                write!(self.out, "attach names {} [", field).unwrap();
+                for name in names.iter() {
+                    if name == "_" {
+                        write!(self.out, " _").unwrap();
+                    } else {
+                        write!(self.out, " \"{}\"", name).unwrap();
+                    }
+                }
+            }
+        }
+        writeln!(self.out, " ];").unwrap();
+    }
+
+    fn r#macro(&mut self, r#macro: &Macro) {
+        write!(self.out, "macro {}(", r#macro.name).unwrap();
+        for (i, arg) in r#macro.args.iter().enumerate() {
+            if i != 0 {
+                write!(self.out, ", ").unwrap();
+            }
+            write!(self.out, "{}", arg).unwrap();
+        }
+        write!(self.out, ")").unwrap();
+        self.actions(&r#macro.actions);
+        writeln!(self.out).unwrap();
+    }
+
+    fn constructor(&mut self, constructor: &Constructor) {
+        if constructor.header.table != "instruction" {
+            write!(self.out, "{}", constructor.header.table).unwrap();
+        }
+        write!(self.out, ":{}is ", constructor.header.mnemonic).unwrap();
+        self.constraint(&constructor.constraint);
+        if !constructor.calculations.is_empty() {
+            write!(self.out, " [").unwrap();
+            for calculation in constructor.calculations.iter() {
+                write!(self.out, " ").unwrap();
+                match calculation {
+                    Calculation::Assignment(assignment) => {
+                        write!(self.out, "{} = ", assignment.lhs).unwrap();
+                        self.rvalue(&assignment.rhs);
+                    }
+                    Calculation::GlobalSet(global_set) => {
+                        write!(self.out, "globalset(").unwrap();
+                        self.rvalue(&global_set.lhs);
+                        write!(self.out, ", ").unwrap();
+                        self.rvalue(&global_set.rhs);
+                        write!(self.out, ")").unwrap();
+                    }
+                }
+                write!(self.out, ";").unwrap();
+            }
+            write!(self.out, " ]").unwrap();
+        }
+        self.actions(&constructor.actions);
+        writeln!(self.out).unwrap();
+    }
+
+    fn constraint(&mut self, constraint: &Constraint) {
+        match constraint {
+            Constraint::Ellipsis(inner) => {
+                self.constraint(&inner.op);
+                write!(self.out, "...").unwrap();
+            }
+            Constraint::And(inner) => {
+                self.constraint(&inner.lhs);
+                write!(self.out, " & ").unwrap();
+                self.constraint(&inner.rhs);
+            }
+            Constraint::Or(inner) => {
+                self.constraint(&inner.lhs);
+                write!(self.out, " | ").unwrap();
+                self.constraint(&inner.rhs);
+            }
+            Constraint::Semi(inner) => {
+                self.constraint(&inner.lhs);
+                write!(self.out, "; ").unwrap();
+                self.constraint(&inner.rhs);
+            }
+            Constraint::Parenthesized(inner) => {
+                write!(self.out, "(").unwrap();
+                self.constraint(inner);
+                write!(self.out, ")").unwrap();
+            }
+            Constraint::Comparison(inner) => {
+                self.constraint_rvalue(&inner.lhs);
+                let operator = match inner.comparison {
+                    ComparisonOperator::Equal => "=",
+                    ComparisonOperator::NotEqual => "!=",
+                    ComparisonOperator::Less => "<",
+                    ComparisonOperator::LessEqual => "<=",
+                    ComparisonOperator::Greater => ">",
+                    ComparisonOperator::GreaterEqual => ">=",
+                };
+                write!(self.out, "{}{}", inner.num_type, operator).unwrap();
+                self.constraint_rvalue(&inner.rhs);
+            }
+            Constraint::Exists(inner) => write!(self.out, "{}", inner.name).unwrap(),
+            Constraint::Constructor(inner) => write!(self.out, "{}", inner.name).unwrap(),
+        }
+    }
+
+    fn constraint_rvalue(&mut self, rvalue: &ConstraintRValue) {
+        let binary = match rvalue {
+            ConstraintRValue::Add(inner) => Some((&inner.lhs, "+", &inner.rhs)),
+            ConstraintRValue::Sub(inner) => Some((&inner.lhs, "-", &inner.rhs)),
+            ConstraintRValue::Mult(inner) => Some((&inner.lhs, "*", &inner.rhs)),
+            ConstraintRValue::Div(inner) => Some((&inner.lhs, "/", &inner.rhs)),
+            ConstraintRValue::IntOr(inner) => Some((&inner.lhs, " $or ", &inner.rhs)),
+            ConstraintRValue::IntAnd(inner) => Some((&inner.lhs, " $and ", &inner.rhs)),
+            ConstraintRValue::IntXor(inner) => Some((&inner.lhs, " $xor ", &inner.rhs)),
+            ConstraintRValue::RShift(inner) => Some((&inner.lhs, ">>", &inner.rhs)),
+            ConstraintRValue::LShift(inner) => Some((&inner.lhs, "<<", &inner.rhs)),
+            _ => None,
+        };
+        if let Some((lhs, op, rhs)) = binary {
+            self.constraint_rvalue(lhs);
+            write!(self.out, "{}", op).unwrap();
+            self.constraint_rvalue(rhs);
+            return;
+        }
+
+        match rvalue {
+            ConstraintRValue::Neg(inner) => {
+                write!(self.out, "-").unwrap();
+                self.constraint_rvalue(&inner.op);
+            }
+            ConstraintRValue::Not(inner) => {
+                write!(self.out, "~").unwrap();
+                self.constraint_rvalue(&inner.op);
+            }
+            ConstraintRValue::Parenthesized(inner) => {
+                write!(self.out, "(").unwrap();
+                self.constraint_rvalue(inner);
+                write!(self.out, ")").unwrap();
+            }
+            ConstraintRValue::Field(name) => write!(self.out, "{}", name).unwrap(),
+            ConstraintRValue::Integer(value) if *value < 0 => {
+                write!(self.out, "-{:#x}", -value).unwrap()
+            }
+            ConstraintRValue::Integer(value) => write!(self.out, "{:#x}", value).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn actions(&mut self, actions: &[Action]) {
+        writeln!(self.out, " {{").unwrap();
+        for action in actions.iter() {
+            write!(self.out, "    ").unwrap();
+            if let Action::Label(label) = action {
+                writeln!(self.out, "<{}>", label.label).unwrap();
+            } else {
+                self.action(action);
+                writeln!(self.out, ";").unwrap();
+            }
+        }
+        write!(self.out, "}}").unwrap();
+    }
+
+    fn action(&mut self, action: &Action) {
+        match action {
+            Action::Label(label) => write!(self.out, "<{}>", label.label).unwrap(),
+            Action::LocalDecl(inner) => {
+                write!(self.out, "local ").unwrap();
+                self.lvalue(&LValue::Ident(inner.name.clone()));
+                write!(self.out, " = ").unwrap();
+                self.rvalue(&inner.val);
+            }
+            Action::Export(inner) => {
+                write!(self.out, "export ").unwrap();
+                self.rvalue(&inner.op);
+            }
+            Action::Assignment(inner) => {
+                self.lvalue(&inner.name);
+                write!(self.out, " = ").unwrap();
+                self.rvalue(&inner.val);
+            }
+            Action::Build(inner) => write!(self.out, "build {}", inner.field).unwrap(),
+            Action::If(inner) => {
+                write!(self.out, "if ").unwrap();
+                self.rvalue(&inner.cond);
+                write!(self.out, " ").unwrap();
+                self.action(&inner.action);
+            }
+            Action::Goto(ActionGoto::Label(label)) => {
+                write!(self.out, "goto <{}>", label.label).unwrap()
+            }
+            Action::Goto(ActionGoto::Address(address)) => {
+                write!(self.out, "goto ").unwrap();
+                self.rvalue(address);
+            }
+            Action::Macro(inner) => self.call(&inner.r#macro, &inner.args),
+            Action::PCodeOp(inner) => self.call(&inner.pcopdeop, &inner.args),
+            Action::Call(inner) => {
+                write!(self.out, "call ").unwrap();
+                self.rvalue(&inner.address);
+            }
+            Action::Return(inner) => {
+                write!(self.out, "return ").unwrap();
+                self.rvalue(&inner.val);
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[RValue]) {
+        write!(self.out, "{}(", name).unwrap();
+        for (i, arg) in args.iter().enumerate() {
+            if i != 0 {
+                write!(self.out, ", ").unwrap();
+            }
+            self.rvalue(arg);
+        }
+        write!(self.out, ")").unwrap();
+    }
+
+    fn binary(&mut self, lhs: &RValue, num_type_prefix: NumTypePrefix, op: &str, rhs: &RValue) {
+        self.rvalue(lhs);
+        write!(self.out, " {}{} ", num_type_prefix, op).unwrap();
+        self.rvalue(rhs);
+    }
+
+    fn rvalue(&mut self, rvalue: &RValue) {
+        use NumTypePrefix::Default;
+
+        match rvalue {
+            RValue::Add(inner) => self.binary(&inner.lhs, inner.num_type_prefix, "+", &inner.rhs),
+            RValue::Sub(inner) => self.binary(&inner.lhs, inner.num_type_prefix, "-", &inner.rhs),
+            RValue::Mult(inner) => self.binary(&inner.lhs, inner.num_type_prefix, "*", &inner.rhs),
+            RValue::Div(inner) => self.binary(&inner.lhs, inner.num_type_prefix, "/", &inner.rhs),
+            RValue::Rem(inner) => self.binary(&inner.lhs, inner.num_type_prefix, "%", &inner.rhs),
+            RValue::IntOr(inner) => self.binary(&inner.lhs, Default, "|", &inner.rhs),
+            RValue::IntAnd(inner) => self.binary(&inner.lhs, Default, "&", &inner.rhs),
+            RValue::IntXor(inner) => self.binary(&inner.lhs, Default, "^", &inner.rhs),
+            RValue::BoolOr(inner) => self.binary(&inner.lhs, Default, "||", &inner.rhs),
+            RValue::BoolAnd(inner) => self.binary(&inner.lhs, Default, "&&", &inner.rhs),
+            RValue::BoolXor(inner) => self.binary(&inner.lhs, Default, "^^", &inner.rhs),
+            RValue::RShift(inner) => {
+                self.binary(&inner.lhs, inner.num_type_prefix, ">>", &inner.rhs)
+            }
+            RValue::LShift(inner) => self.binary(&inner.lhs, Default, "<<", &inner.rhs),
+            RValue::Comparison(inner) => {
+                let operator = inner.operator.to_string();
+                self.binary(&inner.lhs, inner.num_type_prefix, &operator, &inner.rhs)
+            }
+            RValue::Not(inner) => {
+                write!(self.out, "!").unwrap();
+                self.rvalue(&inner.op);
+            }
+            RValue::Neg(inner) => {
+                write!(self.out, "-").unwrap();
+                self.rvalue(&inner.op);
+            }
+            RValue::Parenthesized(inner) => {
+                write!(self.out, "(").unwrap();
+                self.rvalue(&inner.op);
+                write!(self.out, ")").unwrap();
+            }
+            RValue::Constant(inner) => {
+                write!(self.out, "{}", inner.value).unwrap();
+                if let Some(size) = inner.size {
+                    write!(self.out, ":{}", size).unwrap();
+                }
+            }
+            RValue::Call(inner) => self.call(&inner.call, &inner.args),
+            RValue::Ref(inner) => {
+                write!(self.out, "&").unwrap();
+                if let Some(size) = inner.size {
+                    write!(self.out, ":{} ", size).unwrap();
+                }
+                write!(self.out, "{}", inner.field).unwrap();
+            }
+            RValue::Deref(inner) => {
+                write!(self.out, "[").unwrap();
+                self.rvalue(&inner.op);
+                write!(self.out, "]").unwrap();
+            }
+            RValue::LValue(inner) => self.lvalue(inner),
+        }
+    }
+
+    fn lvalue(&mut self, lvalue: &LValue) {
+        match lvalue {
+            LValue::Ident(inner) => {
+                write!(self.out, "{}", inner.field).unwrap();
+                if let Some(size) = inner.size {
+                    write!(self.out, ":{}", size).unwrap();
+                }
+            }
+            LValue::Slice(inner) => {
+                write!(self.out, "{}[{},{}]", inner.field, inner.offset, inner.size).unwrap()
+            }
+            LValue::Ref(inner) => {
+                write!(self.out, "*").unwrap();
+                if let Some(space) = inner.space.as_ref() {
+                    write!(self.out, "[{}]", space).unwrap();
+                }
+                if let Some(size) = inner.size {
+                    write!(self.out, ":{}", size).unwrap();
+                }
+                write!(self.out, " ").unwrap();
+                self.rvalue(&inner.op);
+            }
+        }
+    }
+}
+
+fn endianness(endianness: Endianness) -> &'static str {
+    match endianness {
+        Endianness::Little => "little",
+        Endianness::Big => "big",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Spec;
+
+    #[test]
+    fn test_round_trip() {
+        let spec = Spec::parse(
+            r##"
+define endian=big;
+define alignment=2;
+define space ram type=ram_space size=4 default;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 _ r3 ];
+define register offset=0x100 size=4 contextreg;
+define token instr(16)
+    op = (12,15)
+    rd = (8,11)
+    rs = (4,7)
+    imm = (0,7) signed hex
+;
+define token data(32) endian=little
+    word = (0,31)
+;
+define context contextreg
+    mode = (0,0) noflow
+;
+define pcodeop trap;
+attach variables [ rd rs ] [ r0 r1 _ r3 _ _ _ _ _ _ _ _ _ _ _ _ ];
+attach names mode [ "arm" "thumb" ];
+
+macro setflags(res) {
+    local tmp = res s< 0;
+    r3 = zext(tmp);
+}
+
+src: rs is rs { export rs; }
+src: "#"imm is op=0xf & imm { export *[const]:4 imm; }
+
+with : mode=0 {
+    :add rd, src is (op=1 | op=2) & rd & src [ mode = 1; globalset(inst_next, mode); ] {
+        rd = rd + src * -2;
+        setflags(rd);
+    }
+}
+
+:c is op=4 & rd=(rs & 3 | 4) + 1 & imm=-(1 << 2) * 3 $xor ~rs { }
+
+:b imm is op=3 & imm {
+    local dest:4 = inst_start + imm;
+    if (r0 == 0) goto <skip>;
+    goto [dest];
+    <skip>
+    r0 = r0[0,8] & ~(1 << 2);
+    trap();
+}
+"##,
+        );
+
+        let printed = spec.print();
+        let reparsed = Spec::parse(&printed);
+        assert!(spec == reparsed, "{}", printed);
+        assert_eq!(printed, reparsed.print());
+    }
+}
@@ -1,8 +1,7 @@
 mod convert;
 mod debug;
-mod rename;
 
-use crate::{ComparisonOperator, LValue, NumTypePrefix};
+use crate::{ComparisonOperator, LValue, NumTypePrefix, Span};
 
 #[derive(Clone, PartialEq)]
 pub enum RValue {
@@ -30,11 +29,93 @@ pub enum RValue {
     LValue(LValue),
 }
 
+impl RValue {
+    pub fn span(&self) -> Span {
+        match self {
+            RValue::Add(inner) => inner.span,
+            RValue::Sub(inner) => inner.span,
+            RValue::Mult(inner) => inner.span,
+            RValue::Div(inner) => inner.span,
+            RValue::Rem(inner) => inner.span,
+            RValue::IntOr(inner) => inner.span,
+            RValue::IntAnd(inner) => inner.span,
+            RValue::IntXor(inner) => inner.span,
+            RValue::BoolOr(inner) => inner.span,
+            RValue::BoolAnd(inner) => inner.span,
+            RValue::BoolXor(inner) => inner.span,
+            RValue::RShift(inner) => inner.span,
+            RValue::LShift(inner) => inner.span,
+            RValue::Comparison(inner) => inner.span,
+            RValue::Not(inner) => inner.span,
+            RValue::Neg(inner) => inner.span,
+            RValue::Parenthesized(inner) => inner.span,
+            RValue::Constant(inner) => inner.span,
+            RValue::Call(inner) => inner.span,
+            RValue::Ref(inner) => inner.span,
+            RValue::Deref(inner) => inner.span,
+            RValue::LValue(inner) => inner.span(),
+        }
+    }
+
+    /// The rvalues directly nested in this one. Operands of an `LValue` are
+    /// not included.
+    pub fn operands(&self) -> Vec<&RValue> {
+        match self {
+            RValue::Add(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::Sub(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::Mult(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::Div(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::Rem(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::IntOr(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::IntAnd(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::IntXor(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::BoolOr(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::BoolAnd(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::BoolXor(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::RShift(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::LShift(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::Comparison(inner) => vec![&inner.lhs, &inner.rhs],
+            RValue::Not(inner) => vec![&inner.op],
+            RValue::Neg(inner) => vec![&inner.op],
+            RValue::Parenthesized(inner) => vec![&inner.op],
+            RValue::Deref(inner) => vec![&inner.op],
+            RValue::Call(inner) => inner.args.iter().collect(),
+            RValue::Constant(_) | RValue::Ref(_) | RValue::LValue(_) => Vec::new(),
+        }
+    }
+
+    pub(crate) fn operands_mut(&mut self) -> Vec<&mut RValue> {
+        match self {
+            RValue::Add(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::Sub(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::Mult(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::Div(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::Rem(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::IntOr(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::IntAnd(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::IntXor(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::BoolOr(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::BoolAnd(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::BoolXor(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::RShift(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::LShift(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::Comparison(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            RValue::Not(inner) => vec![&mut inner.op],
+            RValue::Neg(inner) => vec![&mut inner.op],
+            RValue::Parenthesized(inner) => vec![&mut inner.op],
+            RValue::Deref(inner) => vec![&mut inner.op],
+            RValue::Call(inner) => inner.args.iter_mut().collect(),
+            RValue::Constant(_) | RValue::Ref(_) | RValue::LValue(_) => Vec::new(),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct RValueAdd {
     pub lhs: RValue,
     pub num_type_prefix: NumTypePrefix,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -42,6 +123,7 @@ pub struct RValueSub {
     pub lhs: RValue,
     pub num_type_prefix: NumTypePrefix,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -49,6 +131,7 @@ pub struct RValueMult {
     pub lhs: RValue,
     pub num_type_prefix: NumTypePrefix,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -56,6 +139,7 @@ pub struct RValueDiv {
     pub lhs: RValue,
     pub num_type_prefix: NumTypePrefix,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -63,24 +147,28 @@ pub struct RValueRem {
     pub lhs: RValue,
     pub num_type_prefix: NumTypePrefix,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueIntOr {
     pub lhs: RValue,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueIntAnd {
     pub lhs: RValue,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueIntXor {
     pub lhs: RValue,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -88,68 +176,80 @@ pub struct RValueRShift {
     pub lhs: RValue,
     pub num_type_prefix: NumTypePrefix,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueLShift {
     pub lhs: RValue,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueNot {
     pub op: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueNeg {
     pub op: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueParenthesized {
     pub op: RValue,
+    pub span: Span,
 }
 
 #[derive(Copy, Clone, PartialEq)]
 pub struct RValueConstant {
     pub value: i128,
     pub size: Option<u8>,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueCall {
     pub call: String,
     pub args: Vec<RValue>,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueRef {
     pub field: String,
     pub size: Option<u8>,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueDeref {
     pub op: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueBoolOr {
     pub lhs: RValue,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueBoolAnd {
     pub lhs: RValue,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct RValueBoolXor {
     pub lhs: RValue,
     pub rhs: RValue,
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq)]
@@ -158,4 +258,5 @@ pub struct RValueComparison {
     pub num_type_prefix: NumTypePrefix,
     pub operator: ComparisonOperator,
     pub rhs: RValue,
+    pub span: Span,
 }
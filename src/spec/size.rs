@@ -0,0 +1,402 @@
+use super::*;
+use std::collections::HashMap;
+
+impl Spec {
+    /// Infers the varnode size of every expression in the constructors'
+    /// actions and fills in the sizes of constants, locals and references
+    /// that were left implicit. `Spec::parse` and `SpecBuilder::build` run
+    /// this already, `Spec::validate` reports the mismatches.
+    pub fn infer_sizes(&mut self) -> Vec<Diagnostic> {
+        let mut actions: Vec<_> = self
+            .constructors
+            .iter_mut()
+            .map(|c| std::mem::take(&mut c.actions))
+            .collect();
+        let diagnostics = self.infer_action_sizes(&mut actions);
+        for (constructor, actions) in self.constructors.iter_mut().zip(actions) {
+            constructor.actions = actions;
+        }
+        diagnostics
+    }
+
+    /// The mismatches `Spec::infer_sizes` reports, without filling in sizes.
+    pub(crate) fn size_mismatches(&self) -> Vec<Diagnostic> {
+        let mut actions: Vec<_> = self
+            .constructors
+            .iter()
+            .map(|c| c.actions.clone())
+            .collect();
+        self.infer_action_sizes(&mut actions)
+    }
+
+    /// Infers the sizes in `actions`, the actions of each constructor.
+    fn infer_action_sizes(&self, actions: &mut [Vec<Action>]) -> Vec<Diagnostic> {
+        let tables_of = || self.constructors.iter().map(|c| c.header.table.as_str());
+        let mut tables = HashMap::new();
+
+        // Table exports can depend on other tables, so run until their sizes
+        // stop changing. Every run settles at least one more table.
+        for _ in 0..=actions.len() {
+            let mut inference = SizeInference::new(self, &tables);
+            for (table, actions) in tables_of().zip(actions.iter_mut()) {
+                inference.constructor(table, actions);
+            }
+            let exports = inference.exports;
+            if exports == tables {
+                break;
+            }
+            tables = exports;
+        }
+
+        let mut inference = SizeInference::new(self, &tables);
+        for (i, (table, actions)) in tables_of().zip(actions.iter_mut()).enumerate() {
+            inference.constructor_index = Some(i);
+            inference.constructor(table, actions);
+        }
+        inference.diagnostics
+    }
+}
+
+struct SizeInference<'s> {
+    spec: &'s Spec,
+    tables: &'s HashMap<String, u8>,
+    exports: HashMap<String, u8>,
+    locals: HashMap<String, u8>,
+    constructor_index: Option<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'s> SizeInference<'s> {
+    fn new(spec: &'s Spec, tables: &'s HashMap<String, u8>) -> Self {
+        SizeInference {
+            spec,
+            tables,
+            exports: HashMap::new(),
+            locals: HashMap::new(),
+            constructor_index: None,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn address_size(&self, space: Option<&str>) -> Option<u8> {
+        self.spec
+            .spaces
+            .iter()
+            .find(|s| space.map_or(s.default, |space| s.name == space))
+            .map(|s| s.size)
+    }
+
+    /// `span` is the value of size `found`.
+    fn check(&mut self, expected: Option<u8>, found: Option<u8>, span: Span) {
+        if let (Some(expected), Some(found)) = (expected, found) {
+            if expected != found && self.constructor_index.is_some() {
+                self.diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::SizeMismatch { expected, found },
+                    constructor: self.constructor_index,
+                    span,
+                });
+            }
+        }
+    }
+
+    fn constructor(&mut self, table: &str, actions: &mut [Action]) {
+        self.locals.clear();
+        for action in actions.iter_mut() {
+            if let Some(size) = self.action(action) {
+                self.exports.entry(table.to_string()).or_insert(size);
+            }
+        }
+    }
+
+    /// Returns the size of the exported value for `export` actions.
+    fn action(&mut self, action: &mut Action) -> Option<u8> {
+        match action {
+            Action::Label(_) | Action::Build(_) | Action::Goto(ActionGoto::Label(_)) => {}
+            Action::LocalDecl(inner) => {
+                let size = self.rvalue(&mut inner.val, inner.name.size);
+                self.check(inner.name.size, size, inner.val.span());
+                self.declare(&mut inner.name, size);
+            }
+            Action::Export(inner) => return self.rvalue(&mut inner.op, None),
+            Action::Assignment(inner) => {
+                let expected = self.lvalue(&mut inner.name, None);
+                let size = self.rvalue(&mut inner.val, expected);
+                self.check(expected, size, inner.val.span());
+                if let LValue::Ident(ident) = &mut inner.name {
+                    if expected.is_none() {
+                        self.declare(ident, size);
+                    }
+                }
+            }
+            Action::If(inner) => {
+                let size = self.rvalue(&mut inner.cond, Some(1));
+                self.check(Some(1), size, inner.cond.span());
+                self.action(&mut inner.action);
+            }
+            Action::Goto(ActionGoto::Address(address)) => {
+                self.rvalue(address, None);
+            }
+            Action::Macro(inner) => inner.args.iter_mut().for_each(|arg| {
+                self.rvalue(arg, None);
+            }),
+            Action::PCodeOp(inner) => inner.args.iter_mut().for_each(|arg| {
+                self.rvalue(arg, None);
+            }),
+            Action::Call(inner) => {
+                self.rvalue(&mut inner.address, None);
+            }
+            Action::Return(inner) => {
+                self.rvalue(&mut inner.val, None);
+            }
+        }
+        None
+    }
+
+    fn declare(&mut self, ident: &mut LValueIdent, size: Option<u8>) {
+        if ident.size.is_none() {
+            ident.size = size;
+        }
+        if let Some(size) = ident.size {
+            self.locals.insert(ident.field.clone(), size);
+        }
+    }
+
+    fn symbol_size(&self, name: &str) -> Option<u8> {
+        if let Some(size) = self.locals.get(name) {
+            return Some(*size);
+        }
+        if let Some(register) = self.spec.registers.iter().find(|r| r.name == name) {
+            return Some(register.size as u8);
+        }
+        if let Some(size) = self.tables.get(name) {
+            return Some(*size);
+        }
+        if name == "inst_start" || name == "inst_next" || name == "inst_next2" {
+            return self.address_size(None);
+        }
+
+        // fields with attached registers take the registers' size, all
+        // other fields are constants
+        let field = self
+            .spec
+            .tokens
+            .iter()
+            .flat_map(|t| t.fields.iter())
+            .find(|f| f.name == name)
+            .map(|f| &f.meaning)?;
+        if let FieldMeaning::Variables(variables) = field {
+            variables
+                .iter()
+                .find_map(|v| self.spec.registers.iter().find(|r| r.name == *v))
+                .map(|r| r.size as u8)
+        } else {
+            None
+        }
+    }
+
+    fn lvalue(&mut self, lvalue: &mut LValue, expected: Option<u8>) -> Option<u8> {
+        match lvalue {
+            LValue::Ident(ident) => {
+                if let Some(size) = ident.size {
+                    return Some(size);
+                }
+                let size = self.symbol_size(&ident.field);
+                if size.is_none() && expected.is_some() && self.is_constant(&ident.field) {
+                    ident.size = expected;
+                    return expected;
+                }
+                size
+            }
+            LValue::Slice(slice) => Some(slice.size.div_ceil(8)),
+            LValue::Ref(r) => {
+                let address_size = self.address_size(r.space.as_deref());
+                let op_size = self.rvalue(&mut r.op, address_size);
+                self.check(address_size, op_size, r.op.span());
+                if r.size.is_none() {
+                    r.size = expected;
+                }
+                r.size
+            }
+        }
+    }
+
+    /// Fields without attached registers are constants, they take the size
+    /// of the expression they are used in.
+    fn is_constant(&self, name: &str) -> bool {
+        self.spec
+            .tokens
+            .iter()
+            .flat_map(|t| t.fields.iter())
+            .any(|f| f.name == name && !matches!(f.meaning, FieldMeaning::Variables(_)))
+            || self
+                .spec
+                .contexts
+                .iter()
+                .flat_map(|c| c.fields.iter())
+                .any(|f| f.name == name)
+    }
+
+    fn binary(&mut self, lhs: &mut RValue, rhs: &mut RValue, expected: Option<u8>) -> Option<u8> {
+        let mut l = self.rvalue(lhs, expected);
+        let r = self.rvalue(rhs, l.or(expected));
+        if l.is_none() && r.is_some() {
+            l = self.rvalue(lhs, r);
+        }
+        self.check(l, r, rhs.span());
+        l.or(r)
+    }
+
+    fn rvalue(&mut self, rvalue: &mut RValue, expected: Option<u8>) -> Option<u8> {
+        match rvalue {
+            RValue::Add(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::Sub(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::Mult(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::Div(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::Rem(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::IntOr(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::IntAnd(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::IntXor(inner) => self.binary(&mut inner.lhs, &mut inner.rhs, expected),
+            RValue::BoolOr(inner) => {
+                self.binary(&mut inner.lhs, &mut inner.rhs, Some(1));
+                Some(1)
+            }
+            RValue::BoolAnd(inner) => {
+                self.binary(&mut inner.lhs, &mut inner.rhs, Some(1));
+                Some(1)
+            }
+            RValue::BoolXor(inner) => {
+                self.binary(&mut inner.lhs, &mut inner.rhs, Some(1));
+                Some(1)
+            }
+            RValue::Comparison(inner) => {
+                self.binary(&mut inner.lhs, &mut inner.rhs, None);
+                Some(1)
+            }
+            RValue::RShift(inner) => {
+                self.rvalue(&mut inner.rhs, None);
+                self.rvalue(&mut inner.lhs, expected)
+            }
+            RValue::LShift(inner) => {
+                self.rvalue(&mut inner.rhs, None);
+                self.rvalue(&mut inner.lhs, expected)
+            }
+            RValue::Not(inner) => self.rvalue(&mut inner.op, expected),
+            RValue::Neg(inner) => self.rvalue(&mut inner.op, expected),
+            RValue::Parenthesized(inner) => self.rvalue(&mut inner.op, expected),
+            // `[op]` is the target of an indirect branch, `op` is an address
+            RValue::Deref(inner) => {
+                let address_size = self.address_size(None);
+                let size = self.rvalue(&mut inner.op, address_size);
+                self.check(address_size, size, inner.op.span());
+                address_size
+            }
+            RValue::Constant(inner) => {
+                if inner.size.is_none() {
+                    inner.size = expected;
+                }
+                inner.size
+            }
+            RValue::Call(inner) => self.call(inner, expected),
+            RValue::Ref(inner) => {
+                if inner.size.is_none() {
+                    inner.size = self.address_size(None);
+                }
+                inner.size
+            }
+            RValue::LValue(inner) => self.lvalue(inner, expected),
+        }
+    }
+
+    fn call(&mut self, call: &mut RValueCall, expected: Option<u8>) -> Option<u8> {
+        match (call.call.as_str(), call.args.as_mut_slice()) {
+            ("zext", [arg]) | ("sext", [arg]) => {
+                let size = self.rvalue(arg, None);
+                if let (Some(expected), Some(size)) = (expected, size) {
+                    if expected < size {
+                        self.check(Some(expected), Some(size), arg.span());
+                    }
+                }
+                expected
+            }
+            ("carry", [lhs, rhs]) | ("scarry", [lhs, rhs]) | ("sborrow", [lhs, rhs]) => {
+                self.binary(lhs, rhs, None);
+                Some(1)
+            }
+            ("nan", [arg]) => {
+                self.rvalue(arg, None);
+                Some(1)
+            }
+            ("abs", [arg])
+            | ("sqrt", [arg])
+            | ("ceil", [arg])
+            | ("floor", [arg])
+            | ("round", [arg]) => self.rvalue(arg, expected),
+            _ => {
+                for arg in call.args.iter_mut() {
+                    self.rvalue(arg, None);
+                }
+                expected
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, DiagnosticKind, LValue, RValue, Spec};
+
+    #[test]
+    fn test_infer_sizes() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=8 default;
+define space register type=register_space size=4;
+define register offset=0 size=8 [ RAX RCX ];
+define register offset=16 size=4 [ EAX ECX ];
+define token instr(8)
+    op = (4,7)
+    reg = (0,3)
+;
+
+:add RAX is op=0 { RAX = RAX + 1; }
+:load is op=1 { tmp = RCX; *[ram] tmp = RAX; }
+:bad is op=2 { RAX = EAX; }
+:ext is op=3 { RAX = zext(EAX); }
+:jmp is op=4 { goto [RCX]; }
+:jmp32 is op=5 { goto [EAX]; }
+"#,
+        );
+
+        // parsing already filled in the sizes below
+
+        if let Action::Assignment(assignment) = &spec.constructors[0].actions[0] {
+            if let RValue::Add(add) = &assignment.val {
+                assert!(matches!(&add.rhs, RValue::Constant(c) if c.size == Some(8)));
+            } else {
+                unreachable!()
+            }
+        } else {
+            unreachable!()
+        }
+
+        if let Action::Assignment(assignment) = &spec.constructors[1].actions[0] {
+            assert!(matches!(&assignment.name, LValue::Ident(i) if i.size == Some(8)));
+        } else {
+            unreachable!()
+        }
+
+        // a pointer has the size of an address, not of what it points to
+        let diagnostics = spec.validate();
+        let mismatches: Vec<_> = diagnostics
+            .iter()
+            .map(|d| match d.kind {
+                DiagnosticKind::SizeMismatch { expected, found } => {
+                    (d.constructor, expected, found)
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(mismatches, [(Some(2), 8, 4), (Some(5), 8, 4)]);
+    }
+}
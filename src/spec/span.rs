@@ -0,0 +1,54 @@
+use std::ops::Range;
+
+/// A byte range into the preprocessed source a node was parsed from.
+///
+/// Spans always compare equal, so nodes parsed from different places (or
+/// built programmatically with `Span::default()`) are still structurally `==`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Whether this span covers no source, e.g. for synthesized nodes.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The smallest span covering both `self` and `other`. Empty spans are
+    /// ignored.
+    pub fn to(self, other: Span) -> Span {
+        if self.is_empty() {
+            other
+        } else if other.is_empty() {
+            self
+        } else {
+            Span::new(self.start.min(other.start), self.end.max(other.end))
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _other: &Span) -> bool {
+        true
+    }
+}
+
+impl Eq for Span {}
+
+/// Trailing whitespace that pest includes in a pair is left out.
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        let start = span.start();
+        Span::new(start, start + span.as_str().trim_end().len())
+    }
+}
@@ -0,0 +1,627 @@
+use super::*;
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+};
+
+pub(super) const BUILTIN_SYMBOLS: &[&str] = &[
+    "inst_start",
+    "inst_next",
+    "inst_next2",
+    "const",
+    "unique",
+    "epsilon",
+    "instruction",
+];
+
+const BUILTIN_FUNCTIONS: &[&str] = &[
+    "zext",
+    "sext",
+    "carry",
+    "scarry",
+    "sborrow",
+    "nan",
+    "abs",
+    "sqrt",
+    "int2float",
+    "float2float",
+    "trunc",
+    "ceil",
+    "floor",
+    "round",
+    "cpool",
+    "newobject",
+    "popcount",
+    "lzcount",
+    "delayslot",
+];
+
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Index into `Spec::constructors` if the diagnostic belongs to a
+    /// constructor.
+    pub constructor: Option<usize>,
+    /// The node the diagnostic points at, empty if there's none.
+    pub span: Span,
+}
+
+pub enum DiagnosticKind {
+    UndefinedSymbol(String),
+    DuplicateDefinition(String),
+    FieldOutOfRange {
+        field: String,
+        range: Range<u16>,
+        size: u16,
+    },
+    AttachLengthMismatch {
+        field: String,
+        expected: usize,
+        found: usize,
+    },
+    MacroArgumentCount {
+        r#macro: String,
+        expected: usize,
+        found: usize,
+    },
+    RecursiveMacro(String),
+    SizeMismatch {
+        expected: u8,
+        found: u8,
+    },
+    DeadConstructor,
+    AmbiguousConstructors {
+        other: usize,
+    },
+    UncoveredEncoding {
+        table: String,
+        pattern: String,
+    },
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(constructor) = self.constructor {
+            write!(f, " in constructor #{}", constructor)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticKind::UndefinedSymbol(name) => write!(f, "undefined symbol `{}`", name),
+            DiagnosticKind::DuplicateDefinition(name) => {
+                write!(f, "duplicate definition of `{}`", name)
+            }
+            DiagnosticKind::FieldOutOfRange { field, range, size } => write!(
+                f,
+                "field `{}` ({},{}) doesn't fit into {} bits",
+                field, range.start, range.end, size
+            ),
+            DiagnosticKind::AttachLengthMismatch {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "field `{}` needs {} attached values, found {}",
+                field, expected, found
+            ),
+            DiagnosticKind::MacroArgumentCount {
+                r#macro,
+                expected,
+                found,
+            } => write!(
+                f,
+                "macro `{}` takes {} arguments, found {}",
+                r#macro, expected, found
+            ),
+            DiagnosticKind::RecursiveMacro(name) => write!(f, "macro `{}` is recursive", name),
+            DiagnosticKind::SizeMismatch { expected, found } => write!(
+                f,
+                "expected a value of size {}, found size {}",
+                expected, found
+            ),
+            DiagnosticKind::DeadConstructor => write!(f, "constructor can never match"),
+            DiagnosticKind::AmbiguousConstructors { other } => write!(
+                f,
+                "overlaps constructor #{} without either being more specific",
+                other
+            ),
+            DiagnosticKind::UncoveredEncoding { table, pattern } => {
+                write!(f, "no constructor of `{}` matches {}", table, pattern)
+            }
+        }
+    }
+}
+
+impl Spec {
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut validator = Validator::new(self);
+        validator.definitions();
+        validator.fields();
+        for (i, constructor) in self.constructors.iter().enumerate() {
+            validator.constructor(i, constructor);
+        }
+        let mut diagnostics = validator.diagnostics;
+        diagnostics.extend(self.size_mismatches());
+        diagnostics
+    }
+}
+
+struct Validator<'s> {
+    spec: &'s Spec,
+    fields: HashSet<&'s str>,
+    tables: HashSet<&'s str>,
+    symbols: HashSet<&'s str>,
+    locals: HashSet<&'s str>,
+    labels: HashSet<&'s str>,
+    constructor: Option<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'s> Validator<'s> {
+    fn new(spec: &'s Spec) -> Self {
+        let fields: HashSet<_> = spec
+            .tokens
+            .iter()
+            .flat_map(|t| t.fields.iter().map(|f| f.name.as_str()))
+            .chain(
+                spec.contexts
+                    .iter()
+                    .flat_map(|c| c.fields.iter().map(|f| f.name.as_str())),
+            )
+            .collect();
+        let tables: HashSet<_> = spec
+            .constructors
+            .iter()
+            .map(|c| c.header.table.as_str())
+            .chain(Some("instruction"))
+            .collect();
+        let symbols = fields
+            .iter()
+            .chain(tables.iter())
+            .copied()
+            .chain(spec.registers.iter().map(|r| r.name.as_str()))
+            .chain(spec.spaces.iter().map(|s| s.name.as_str()))
+            .chain(BUILTIN_SYMBOLS.iter().copied())
+            .collect();
+
+        Validator {
+            spec,
+            fields,
+            tables,
+            symbols,
+            locals: HashSet::new(),
+            labels: HashSet::new(),
+            constructor: None,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, kind: DiagnosticKind, span: Span) {
+        self.diagnostics.push(Diagnostic {
+            kind,
+            constructor: self.constructor,
+            span,
+        });
+    }
+
+    fn definitions(&mut self) {
+        let spec = self.spec;
+        let mut defined = HashSet::new();
+        let names = spec
+            .spaces
+            .iter()
+            .map(|s| s.name.as_str())
+            .chain(spec.registers.iter().map(|r| r.name.as_str()))
+            .chain(spec.tokens.iter().map(|t| t.name.as_str()))
+            .chain(
+                spec.tokens
+                    .iter()
+                    .flat_map(|t| t.fields.iter().map(|f| f.name.as_str())),
+            )
+            .chain(
+                spec.contexts
+                    .iter()
+                    .flat_map(|c| c.fields.iter().map(|f| f.name.as_str())),
+            )
+            .chain(spec.pcodeops.iter().map(|p| p.name.as_str()))
+            .chain(spec.macros.iter().map(|m| m.name.as_str()));
+        for name in names {
+            if !defined.insert(name) {
+                self.report(
+                    DiagnosticKind::DuplicateDefinition(name.to_string()),
+                    Span::default(),
+                );
+            }
+        }
+
+        let mut tables: Vec<_> = self.tables.iter().copied().collect();
+        tables.sort_unstable();
+        for table in tables {
+            if defined.contains(table) {
+                self.report(
+                    DiagnosticKind::DuplicateDefinition(table.to_string()),
+                    Span::default(),
+                );
+            }
+        }
+    }
+
+    fn fields(&mut self) {
+        let spec = self.spec;
+        for token in spec.tokens.iter() {
+            for field in token.fields.iter() {
+                self.field(&field.name, &field.range, token.size, &field.meaning);
+            }
+        }
+        for context in spec.contexts.iter() {
+            let size = spec
+                .registers
+                .iter()
+                .find(|r| r.name == context.register)
+                .map(|r| r.size * 8);
+            let size = if let Some(size) = size {
+                size
+            } else {
+                self.report(
+                    DiagnosticKind::UndefinedSymbol(context.register.clone()),
+                    Span::default(),
+                );
+                continue;
+            };
+            for field in context.fields.iter() {
+                self.field(&field.name, &field.range, size, &field.meaning);
+            }
+        }
+    }
+
+    fn field(&mut self, name: &str, range: &Range<u16>, size: u16, meaning: &'s FieldMeaning) {
+        if range.start > range.end || range.end >= size {
+            self.report(
+                DiagnosticKind::FieldOutOfRange {
+                    field: name.to_string(),
+                    range: range.clone(),
+                    size,
+                },
+                Span::default(),
+            );
+        }
+
+        let found = match meaning {
+            FieldMeaning::Default => return,
+            FieldMeaning::Variables(variables) => {
+                for variable in variables.iter().filter(|v| *v != "_") {
+                    if self.spec.registers.iter().all(|r| r.name != *variable) {
+                        self.report(
+                            DiagnosticKind::UndefinedSymbol(variable.clone()),
+                            Span::default(),
+                        );
+                    }
+                }
+                variables.len()
+            }
+            FieldMeaning::Values(values) => values.len(),
+            FieldMeaning::Names(names) => names.len(),
+        };
+        let expected = 1usize
+            .checked_shl(u32::from(range.end.saturating_sub(range.start)) + 1)
+            .unwrap_or(usize::MAX);
+        if found != expected {
+            self.report(
+                DiagnosticKind::AttachLengthMismatch {
+                    field: name.to_string(),
+                    expected,
+                    found,
+                },
+                Span::default(),
+            );
+        }
+    }
+
+    fn constructor(&mut self, i: usize, constructor: &'s Constructor) {
+        self.constructor = Some(i);
+        self.locals.clear();
+        self.labels.clear();
+
+        self.constraint(&constructor.constraint);
+
+        for calculation in constructor.calculations.iter() {
+            if let Calculation::Assignment(assignment) = calculation {
+                self.locals.insert(&assignment.lhs);
+            }
+        }
+        for action in constructor.actions.iter() {
+            self.declare(action);
+        }
+
+        for calculation in constructor.calculations.iter() {
+            match calculation {
+                Calculation::Assignment(assignment) => self.rvalue(&assignment.rhs),
+                Calculation::GlobalSet(global_set) => {
+                    self.rvalue(&global_set.lhs);
+                    self.rvalue(&global_set.rhs);
+                }
+            }
+        }
+        for action in constructor.actions.iter() {
+            self.action(action);
+        }
+    }
+
+    fn declare(&mut self, action: &'s Action) {
+        match action {
+            Action::Label(label) => {
+                self.labels.insert(&label.label);
+            }
+            Action::LocalDecl(inner) => {
+                self.locals.insert(&inner.name.field);
+            }
+            Action::Assignment(ActionAssignment {
+                name: LValue::Ident(ident),
+                ..
+            }) => {
+                self.locals.insert(&ident.field);
+            }
+            Action::If(inner) => self.declare(&inner.action),
+            _ => {}
+        }
+    }
+
+    fn symbol(&mut self, name: &str, span: Span) {
+        if !self.symbols.contains(name) && !self.locals.contains(name) {
+            self.report(DiagnosticKind::UndefinedSymbol(name.to_string()), span);
+        }
+    }
+
+    fn operand(&mut self, name: &str, span: Span) {
+        if !self.fields.contains(name) && !self.tables.contains(name) {
+            self.report(DiagnosticKind::UndefinedSymbol(name.to_string()), span);
+        }
+    }
+
+    fn constraint(&mut self, constraint: &Constraint) {
+        match constraint {
+            Constraint::Ellipsis(inner) => self.constraint(&inner.op),
+            Constraint::And(inner) => {
+                self.constraint(&inner.lhs);
+                self.constraint(&inner.rhs);
+            }
+            Constraint::Or(inner) => {
+                self.constraint(&inner.lhs);
+                self.constraint(&inner.rhs);
+            }
+            Constraint::Semi(inner) => {
+                self.constraint(&inner.lhs);
+                self.constraint(&inner.rhs);
+            }
+            Constraint::Parenthesized(inner) => self.constraint(inner),
+            Constraint::Comparison(inner) => {
+                self.constraint_rvalue(&inner.lhs, inner.span);
+                self.constraint_rvalue(&inner.rhs, inner.span);
+            }
+            Constraint::Exists(inner) => self.operand(&inner.name, inner.span),
+            Constraint::Constructor(inner) => self.operand(&inner.name, inner.span),
+        }
+    }
+
+    /// Constraint expressions carry no spans, `span` is the comparison's.
+    fn constraint_rvalue(&mut self, rvalue: &ConstraintRValue, span: Span) {
+        match rvalue {
+            ConstraintRValue::Field(name) => self.operand(name, span),
+            _ => {
+                for op in rvalue.operands() {
+                    self.constraint_rvalue(op, span);
+                }
+            }
+        }
+    }
+
+    fn action(&mut self, action: &Action) {
+        match action {
+            Action::Label(_) => {}
+            Action::LocalDecl(inner) => self.rvalue(&inner.val),
+            Action::Export(inner) => self.rvalue(&inner.op),
+            Action::Assignment(inner) => {
+                self.lvalue(&inner.name);
+                self.rvalue(&inner.val);
+            }
+            Action::Build(inner) => self.operand(&inner.field, inner.span),
+            Action::If(inner) => {
+                self.rvalue(&inner.cond);
+                self.action(&inner.action);
+            }
+            Action::Goto(ActionGoto::Label(label)) => {
+                if !self.labels.contains(label.label.as_str()) {
+                    self.report(
+                        DiagnosticKind::UndefinedSymbol(label.label.clone()),
+                        label.span,
+                    );
+                }
+            }
+            Action::Goto(ActionGoto::Address(address)) => self.rvalue(address),
+            // invocations that are left after `Spec::expand_macros`
+            Action::Macro(inner) => {
+                let name = &inner.r#macro;
+                let kind = match self.spec.macros.iter().find(|m| m.name == *name) {
+                    None => DiagnosticKind::UndefinedSymbol(name.clone()),
+                    Some(r#macro) if r#macro.args.len() != inner.args.len() => {
+                        DiagnosticKind::MacroArgumentCount {
+                            r#macro: name.clone(),
+                            expected: r#macro.args.len(),
+                            found: inner.args.len(),
+                        }
+                    }
+                    Some(_) => DiagnosticKind::RecursiveMacro(name.clone()),
+                };
+                self.report(kind, inner.span);
+                inner.args.iter().for_each(|arg| self.rvalue(arg));
+            }
+            Action::PCodeOp(inner) => inner.args.iter().for_each(|arg| self.rvalue(arg)),
+            Action::Call(inner) => self.rvalue(&inner.address),
+            Action::Return(inner) => self.rvalue(&inner.val),
+        }
+    }
+
+    fn rvalue(&mut self, rvalue: &RValue) {
+        match rvalue {
+            RValue::Add(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::Sub(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::Mult(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::Div(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::Rem(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::IntOr(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::IntAnd(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::IntXor(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::BoolOr(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::BoolAnd(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::BoolXor(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::RShift(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::LShift(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::Comparison(inner) => self.binary(&inner.lhs, &inner.rhs),
+            RValue::Not(inner) => self.rvalue(&inner.op),
+            RValue::Neg(inner) => self.rvalue(&inner.op),
+            RValue::Parenthesized(inner) => self.rvalue(&inner.op),
+            RValue::Constant(_) => {}
+            RValue::Call(inner) => {
+                if !BUILTIN_FUNCTIONS.contains(&inner.call.as_str())
+                    && self.spec.pcodeops.iter().all(|p| p.name != inner.call)
+                {
+                    self.report(
+                        DiagnosticKind::UndefinedSymbol(inner.call.clone()),
+                        inner.span,
+                    );
+                }
+                inner.args.iter().for_each(|arg| self.rvalue(arg));
+            }
+            RValue::Ref(inner) => self.symbol(&inner.field, inner.span),
+            RValue::Deref(inner) => self.rvalue(&inner.op),
+            RValue::LValue(inner) => self.lvalue(inner),
+        }
+    }
+
+    fn binary(&mut self, lhs: &RValue, rhs: &RValue) {
+        self.rvalue(lhs);
+        self.rvalue(rhs);
+    }
+
+    fn lvalue(&mut self, lvalue: &LValue) {
+        match lvalue {
+            LValue::Ident(ident) => self.symbol(&ident.field, ident.span),
+            LValue::Slice(slice) => self.symbol(&slice.field, slice.span),
+            LValue::Ref(r) => {
+                if let Some(space) = r.space.as_ref() {
+                    if self.spec.spaces.iter().all(|s| s.name != *space) {
+                        self.report(DiagnosticKind::UndefinedSymbol(space.clone()), r.span);
+                    }
+                }
+                self.rvalue(&r.op);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DiagnosticKind, Spec};
+
+    #[test]
+    fn test_validate() {
+        let source = r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define register offset=0 size=4 r0;
+define token instr(8)
+    op = (4,7)
+    reg = (0,2)
+    big = (6,8)
+;
+attach variables reg [ r0 r1 _ r3 ];
+
+:nop is op=0 { undefined_macro(); }
+:mov reg is op=1 & reg { reg = missing; }
+:ld reg is op=2 & reg & unknown_field { tmp:4 = *[ram] reg; reg = tmp; }
+"#;
+        let spec = Spec::parse(source);
+
+        let diagnostics: Vec<_> = spec
+            .validate()
+            .into_iter()
+            .map(|d| (d.kind.to_string(), d.constructor))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                ("duplicate definition of `r0`".to_string(), None),
+                (
+                    "field `reg` needs 8 attached values, found 4".to_string(),
+                    None
+                ),
+                (
+                    "field `big` (6,8) doesn't fit into 8 bits".to_string(),
+                    None
+                ),
+                ("undefined symbol `undefined_macro`".to_string(), Some(0)),
+                ("undefined symbol `missing`".to_string(), Some(1)),
+                ("undefined symbol `unknown_field`".to_string(), Some(2)),
+            ]
+        );
+        assert!(matches!(
+            spec.validate()[0].kind,
+            DiagnosticKind::DuplicateDefinition(_)
+        ));
+        let spans: Vec<_> = spec
+            .validate()
+            .iter()
+            .map(|d| &source[d.span.range()])
+            .collect();
+        assert_eq!(
+            spans,
+            ["", "", "", "undefined_macro()", "missing", "unknown_field"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_macros() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 ];
+define token instr(8)
+    op = (0,7)
+;
+
+macro one(a) { r0 = a; }
+macro ping(a) { pong(a); }
+macro pong(a) { ping(a); }
+
+:a is op=0 { one(r0, r1); }
+:b is op=1 { ping(r1); }
+:c is op=2 { one(r1); }
+"#,
+        );
+
+        let diagnostics: Vec<_> = spec
+            .validate()
+            .into_iter()
+            .map(|d| (d.kind.to_string(), d.constructor))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                (
+                    "macro `one` takes 1 arguments, found 2".to_string(),
+                    Some(0)
+                ),
+                ("macro `ping` is recursive".to_string(), Some(1)),
+            ]
+        );
+    }
+}
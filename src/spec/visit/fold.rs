@@ -0,0 +1,219 @@
+use crate::{
+    Action, ActionBuild, ActionGoto, Calculation, Constraint, ConstraintExists, ConstraintRValue,
+    Constructor, LValue, LValueIdent, RValue, RValueConstant, Span,
+};
+use std::mem::replace;
+
+/// Rewrites nodes by value. Each method returns the node that replaces the one
+/// it was given.
+pub trait Fold {
+    fn fold_constructor(&mut self, constructor: Constructor) -> Constructor {
+        walk_fold_constructor(self, constructor)
+    }
+
+    fn fold_constraint(&mut self, constraint: Constraint) -> Constraint {
+        walk_fold_constraint(self, constraint)
+    }
+
+    fn fold_constraint_rvalue(&mut self, rvalue: ConstraintRValue) -> ConstraintRValue {
+        walk_fold_constraint_rvalue(self, rvalue)
+    }
+
+    fn fold_action(&mut self, action: Action) -> Action {
+        walk_fold_action(self, action)
+    }
+
+    fn fold_rvalue(&mut self, rvalue: RValue) -> RValue {
+        walk_fold_rvalue(self, rvalue)
+    }
+
+    fn fold_lvalue(&mut self, lvalue: LValue) -> LValue {
+        walk_fold_lvalue(self, lvalue)
+    }
+
+    /// Called for names of fields, tables, locals and labels.
+    fn fold_ident(&mut self, ident: String) -> String {
+        ident
+    }
+}
+
+/// Replaces `node` by `f(node)`, leaving `placeholder` behind while `f` runs.
+fn fold_in_place<T>(node: &mut T, placeholder: T, f: impl FnOnce(T) -> T) {
+    let old = replace(node, placeholder);
+    *node = f(old);
+}
+
+fn constraint_placeholder() -> Constraint {
+    Constraint::Exists(ConstraintExists {
+        name: String::new(),
+        span: Span::default(),
+    })
+}
+
+fn rvalue_placeholder() -> RValue {
+    RValue::Constant(RValueConstant {
+        value: 0,
+        size: None,
+        span: Span::default(),
+    })
+}
+
+fn lvalue_placeholder() -> LValue {
+    LValue::Ident(LValueIdent {
+        field: String::new(),
+        size: None,
+        span: Span::default(),
+    })
+}
+
+fn action_placeholder() -> Action {
+    Action::Build(ActionBuild {
+        field: String::new(),
+        span: Span::default(),
+    })
+}
+
+fn fold_string<F: Fold + ?Sized>(f: &mut F, ident: &mut String) {
+    fold_in_place(ident, String::new(), |ident| f.fold_ident(ident));
+}
+
+fn fold_constraint_in_place<F: Fold + ?Sized>(f: &mut F, constraint: &mut Constraint) {
+    fold_in_place(constraint, constraint_placeholder(), |c| {
+        f.fold_constraint(c)
+    });
+}
+
+fn fold_constraint_rvalue_in_place<F: Fold + ?Sized>(f: &mut F, rvalue: &mut ConstraintRValue) {
+    fold_in_place(rvalue, ConstraintRValue::Integer(0), |r| {
+        f.fold_constraint_rvalue(r)
+    });
+}
+
+fn fold_rvalue_in_place<F: Fold + ?Sized>(f: &mut F, rvalue: &mut RValue) {
+    fold_in_place(rvalue, rvalue_placeholder(), |r| f.fold_rvalue(r));
+}
+
+pub fn walk_fold_constructor<F: Fold + ?Sized>(
+    f: &mut F,
+    mut constructor: Constructor,
+) -> Constructor {
+    constructor.constraint = f.fold_constraint(constructor.constraint);
+    for calculation in constructor.calculations.iter_mut() {
+        match calculation {
+            Calculation::Assignment(inner) => {
+                fold_string(f, &mut inner.lhs);
+                fold_rvalue_in_place(f, &mut inner.rhs);
+            }
+            Calculation::GlobalSet(inner) => {
+                fold_rvalue_in_place(f, &mut inner.lhs);
+                fold_rvalue_in_place(f, &mut inner.rhs);
+            }
+        }
+    }
+    constructor.actions = constructor
+        .actions
+        .into_iter()
+        .map(|action| f.fold_action(action))
+        .collect();
+    constructor
+}
+
+pub fn walk_fold_constraint<F: Fold + ?Sized>(f: &mut F, mut constraint: Constraint) -> Constraint {
+    match &mut constraint {
+        Constraint::Ellipsis(inner) => fold_constraint_in_place(f, &mut inner.op),
+        Constraint::And(inner) => {
+            fold_constraint_in_place(f, &mut inner.lhs);
+            fold_constraint_in_place(f, &mut inner.rhs);
+        }
+        Constraint::Or(inner) => {
+            fold_constraint_in_place(f, &mut inner.lhs);
+            fold_constraint_in_place(f, &mut inner.rhs);
+        }
+        Constraint::Semi(inner) => {
+            fold_constraint_in_place(f, &mut inner.lhs);
+            fold_constraint_in_place(f, &mut inner.rhs);
+        }
+        Constraint::Parenthesized(inner) => fold_constraint_in_place(f, inner),
+        Constraint::Comparison(inner) => {
+            fold_constraint_rvalue_in_place(f, &mut inner.lhs);
+            fold_constraint_rvalue_in_place(f, &mut inner.rhs);
+        }
+        Constraint::Exists(inner) => fold_string(f, &mut inner.name),
+        Constraint::Constructor(inner) => fold_string(f, &mut inner.name),
+    }
+    constraint
+}
+
+pub fn walk_fold_constraint_rvalue<F: Fold + ?Sized>(
+    f: &mut F,
+    mut rvalue: ConstraintRValue,
+) -> ConstraintRValue {
+    match &mut rvalue {
+        ConstraintRValue::Field(field) => fold_string(f, field),
+        _ => {
+            for op in rvalue.operands_mut() {
+                fold_constraint_rvalue_in_place(f, op);
+            }
+        }
+    }
+    rvalue
+}
+
+pub fn walk_fold_action<F: Fold + ?Sized>(f: &mut F, mut action: Action) -> Action {
+    match &mut action {
+        Action::Label(label) => fold_string(f, &mut label.label),
+        Action::LocalDecl(inner) => {
+            fold_string(f, &mut inner.name.field);
+            fold_rvalue_in_place(f, &mut inner.val);
+        }
+        Action::Export(inner) => fold_rvalue_in_place(f, &mut inner.op),
+        Action::Assignment(inner) => {
+            fold_in_place(&mut inner.name, lvalue_placeholder(), |l| f.fold_lvalue(l));
+            fold_rvalue_in_place(f, &mut inner.val);
+        }
+        Action::Build(inner) => fold_string(f, &mut inner.field),
+        Action::If(inner) => {
+            fold_rvalue_in_place(f, &mut inner.cond);
+            fold_in_place(&mut inner.action, action_placeholder(), |a| {
+                f.fold_action(a)
+            });
+        }
+        Action::Goto(ActionGoto::Label(label)) => fold_string(f, &mut label.label),
+        Action::Goto(ActionGoto::Address(address)) => fold_rvalue_in_place(f, address),
+        Action::Macro(inner) => {
+            for arg in inner.args.iter_mut() {
+                fold_rvalue_in_place(f, arg);
+            }
+        }
+        Action::PCodeOp(inner) => {
+            for arg in inner.args.iter_mut() {
+                fold_rvalue_in_place(f, arg);
+            }
+        }
+        Action::Call(inner) => fold_rvalue_in_place(f, &mut inner.address),
+        Action::Return(inner) => fold_rvalue_in_place(f, &mut inner.val),
+    }
+    action
+}
+
+pub fn walk_fold_rvalue<F: Fold + ?Sized>(f: &mut F, mut rvalue: RValue) -> RValue {
+    match &mut rvalue {
+        RValue::Ref(inner) => fold_string(f, &mut inner.field),
+        RValue::LValue(inner) => fold_in_place(inner, lvalue_placeholder(), |l| f.fold_lvalue(l)),
+        _ => {
+            for op in rvalue.operands_mut() {
+                fold_rvalue_in_place(f, op);
+            }
+        }
+    }
+    rvalue
+}
+
+pub fn walk_fold_lvalue<F: Fold + ?Sized>(f: &mut F, mut lvalue: LValue) -> LValue {
+    match &mut lvalue {
+        LValue::Ident(inner) => fold_string(f, &mut inner.field),
+        LValue::Slice(inner) => fold_string(f, &mut inner.field),
+        LValue::Ref(inner) => fold_rvalue_in_place(f, &mut inner.op),
+    }
+    lvalue
+}
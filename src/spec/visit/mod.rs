@@ -0,0 +1,220 @@
+//! Traversals over the constraint and semantic section ASTs.
+//!
+//! Every trait method defaults to the matching `walk_*` function, which
+//! recurses into the node's children. A pass overrides the methods for the
+//! nodes it cares about and calls `walk_*` itself to keep descending.
+
+mod fold;
+mod visit_mut;
+
+pub use fold::*;
+pub use visit_mut::*;
+
+use crate::{
+    Action, ActionGoto, Calculation, Constraint, ConstraintRValue, Constructor, LValue, RValue,
+};
+
+pub trait Visit {
+    fn visit_constructor(&mut self, constructor: &Constructor) {
+        walk_constructor(self, constructor)
+    }
+
+    fn visit_constraint(&mut self, constraint: &Constraint) {
+        walk_constraint(self, constraint)
+    }
+
+    fn visit_constraint_rvalue(&mut self, rvalue: &ConstraintRValue) {
+        walk_constraint_rvalue(self, rvalue)
+    }
+
+    fn visit_action(&mut self, action: &Action) {
+        walk_action(self, action)
+    }
+
+    fn visit_rvalue(&mut self, rvalue: &RValue) {
+        walk_rvalue(self, rvalue)
+    }
+
+    fn visit_lvalue(&mut self, lvalue: &LValue) {
+        walk_lvalue(self, lvalue)
+    }
+
+    /// Called for names of fields, tables, locals and labels.
+    fn visit_ident(&mut self, _ident: &str) {}
+}
+
+pub fn walk_constructor<V: Visit + ?Sized>(v: &mut V, constructor: &Constructor) {
+    v.visit_constraint(&constructor.constraint);
+    for calculation in constructor.calculations.iter() {
+        match calculation {
+            Calculation::Assignment(inner) => {
+                v.visit_ident(&inner.lhs);
+                v.visit_rvalue(&inner.rhs);
+            }
+            Calculation::GlobalSet(inner) => {
+                v.visit_rvalue(&inner.lhs);
+                v.visit_rvalue(&inner.rhs);
+            }
+        }
+    }
+    for action in constructor.actions.iter() {
+        v.visit_action(action);
+    }
+}
+
+pub fn walk_constraint<V: Visit + ?Sized>(v: &mut V, constraint: &Constraint) {
+    match constraint {
+        Constraint::Ellipsis(inner) => v.visit_constraint(&inner.op),
+        Constraint::And(inner) => {
+            v.visit_constraint(&inner.lhs);
+            v.visit_constraint(&inner.rhs);
+        }
+        Constraint::Or(inner) => {
+            v.visit_constraint(&inner.lhs);
+            v.visit_constraint(&inner.rhs);
+        }
+        Constraint::Semi(inner) => {
+            v.visit_constraint(&inner.lhs);
+            v.visit_constraint(&inner.rhs);
+        }
+        Constraint::Parenthesized(inner) => v.visit_constraint(inner),
+        Constraint::Comparison(inner) => {
+            v.visit_constraint_rvalue(&inner.lhs);
+            v.visit_constraint_rvalue(&inner.rhs);
+        }
+        Constraint::Exists(inner) => v.visit_ident(&inner.name),
+        Constraint::Constructor(inner) => v.visit_ident(&inner.name),
+    }
+}
+
+pub fn walk_constraint_rvalue<V: Visit + ?Sized>(v: &mut V, rvalue: &ConstraintRValue) {
+    match rvalue {
+        ConstraintRValue::Field(field) => v.visit_ident(field),
+        _ => {
+            for op in rvalue.operands() {
+                v.visit_constraint_rvalue(op);
+            }
+        }
+    }
+}
+
+pub fn walk_action<V: Visit + ?Sized>(v: &mut V, action: &Action) {
+    match action {
+        Action::Label(label) => v.visit_ident(&label.label),
+        Action::LocalDecl(inner) => {
+            v.visit_ident(&inner.name.field);
+            v.visit_rvalue(&inner.val);
+        }
+        Action::Export(inner) => v.visit_rvalue(&inner.op),
+        Action::Assignment(inner) => {
+            v.visit_lvalue(&inner.name);
+            v.visit_rvalue(&inner.val);
+        }
+        Action::Build(inner) => v.visit_ident(&inner.field),
+        Action::If(inner) => {
+            v.visit_rvalue(&inner.cond);
+            v.visit_action(&inner.action);
+        }
+        Action::Goto(ActionGoto::Label(label)) => v.visit_ident(&label.label),
+        Action::Goto(ActionGoto::Address(address)) => v.visit_rvalue(address),
+        Action::Macro(inner) => inner.args.iter().for_each(|arg| v.visit_rvalue(arg)),
+        Action::PCodeOp(inner) => inner.args.iter().for_each(|arg| v.visit_rvalue(arg)),
+        Action::Call(inner) => v.visit_rvalue(&inner.address),
+        Action::Return(inner) => v.visit_rvalue(&inner.val),
+    }
+}
+
+pub fn walk_rvalue<V: Visit + ?Sized>(v: &mut V, rvalue: &RValue) {
+    match rvalue {
+        RValue::Ref(inner) => v.visit_ident(&inner.field),
+        RValue::LValue(inner) => v.visit_lvalue(inner),
+        _ => {
+            for op in rvalue.operands() {
+                v.visit_rvalue(op);
+            }
+        }
+    }
+}
+
+pub fn walk_lvalue<V: Visit + ?Sized>(v: &mut V, lvalue: &LValue) {
+    match lvalue {
+        LValue::Ident(inner) => v.visit_ident(&inner.field),
+        LValue::Slice(inner) => v.visit_ident(&inner.field),
+        LValue::Ref(inner) => v.visit_rvalue(&inner.op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Spec;
+
+    #[test]
+    fn test_visit() {
+        let mut spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 ];
+define token instr(8)
+    op = (4,7)
+    reg = (0,3)
+;
+attach variables reg [ r0 r1 ];
+:inc reg is op=1 & reg { reg = reg + 1; }
+:ld Src is op=2 & Src { build Src; }
+Src: reg is reg { export reg; }
+"#,
+        );
+
+        struct Idents(Vec<String>);
+        impl Visit for Idents {
+            fn visit_ident(&mut self, ident: &str) {
+                self.0.push(ident.to_string());
+            }
+        }
+        let mut idents = Idents(Vec::new());
+        idents.visit_constructor(&spec.constructors[0]);
+        assert_eq!(idents.0, ["op", "reg", "reg", "reg"]);
+        let mut idents = Idents(Vec::new());
+        idents.visit_constructor(&spec.constructors[1]);
+        assert_eq!(idents.0, ["op", "Src", "Src"]);
+
+        struct Increment;
+        impl VisitMut for Increment {
+            fn visit_rvalue_mut(&mut self, rvalue: &mut RValue) {
+                match rvalue {
+                    RValue::Constant(constant) => constant.value += 1,
+                    _ => walk_rvalue_mut(self, rvalue),
+                }
+            }
+        }
+        Increment.visit_constructor_mut(&mut spec.constructors[0]);
+
+        struct RenameTable;
+        impl VisitMut for RenameTable {
+            fn visit_ident_mut(&mut self, ident: &mut String) {
+                if ident == "Src" {
+                    *ident = "Source".to_string();
+                }
+            }
+        }
+        RenameTable.visit_constructor_mut(&mut spec.constructors[1]);
+
+        struct Rename;
+        impl Fold for Rename {
+            fn fold_ident(&mut self, ident: String) -> String {
+                match ident.as_str() {
+                    "reg" => "rd".to_string(),
+                    _ => ident,
+                }
+            }
+        }
+        let constructor = spec.constructors.remove(0);
+        spec.constructors.push(Rename.fold_constructor(constructor));
+
+        let printed = spec.print();
+        assert!(printed.contains(":inc reg is op=0x1 & rd {\n    rd = rd + 2:4;\n}"));
+        assert!(printed.contains("build Source;"));
+    }
+}
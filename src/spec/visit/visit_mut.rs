@@ -0,0 +1,140 @@
+use crate::{
+    Action, ActionGoto, Calculation, Constraint, ConstraintRValue, Constructor, LValue, RValue,
+};
+
+/// Like `Visit`, but with mutable access to every node.
+pub trait VisitMut {
+    fn visit_constructor_mut(&mut self, constructor: &mut Constructor) {
+        walk_constructor_mut(self, constructor)
+    }
+
+    fn visit_constraint_mut(&mut self, constraint: &mut Constraint) {
+        walk_constraint_mut(self, constraint)
+    }
+
+    fn visit_constraint_rvalue_mut(&mut self, rvalue: &mut ConstraintRValue) {
+        walk_constraint_rvalue_mut(self, rvalue)
+    }
+
+    fn visit_action_mut(&mut self, action: &mut Action) {
+        walk_action_mut(self, action)
+    }
+
+    fn visit_rvalue_mut(&mut self, rvalue: &mut RValue) {
+        walk_rvalue_mut(self, rvalue)
+    }
+
+    fn visit_lvalue_mut(&mut self, lvalue: &mut LValue) {
+        walk_lvalue_mut(self, lvalue)
+    }
+
+    /// Called for names of fields, tables, locals and labels.
+    fn visit_ident_mut(&mut self, _ident: &mut String) {}
+}
+
+pub fn walk_constructor_mut<V: VisitMut + ?Sized>(v: &mut V, constructor: &mut Constructor) {
+    v.visit_constraint_mut(&mut constructor.constraint);
+    for calculation in constructor.calculations.iter_mut() {
+        match calculation {
+            Calculation::Assignment(inner) => {
+                v.visit_ident_mut(&mut inner.lhs);
+                v.visit_rvalue_mut(&mut inner.rhs);
+            }
+            Calculation::GlobalSet(inner) => {
+                v.visit_rvalue_mut(&mut inner.lhs);
+                v.visit_rvalue_mut(&mut inner.rhs);
+            }
+        }
+    }
+    for action in constructor.actions.iter_mut() {
+        v.visit_action_mut(action);
+    }
+}
+
+pub fn walk_constraint_mut<V: VisitMut + ?Sized>(v: &mut V, constraint: &mut Constraint) {
+    match constraint {
+        Constraint::Ellipsis(inner) => v.visit_constraint_mut(&mut inner.op),
+        Constraint::And(inner) => {
+            v.visit_constraint_mut(&mut inner.lhs);
+            v.visit_constraint_mut(&mut inner.rhs);
+        }
+        Constraint::Or(inner) => {
+            v.visit_constraint_mut(&mut inner.lhs);
+            v.visit_constraint_mut(&mut inner.rhs);
+        }
+        Constraint::Semi(inner) => {
+            v.visit_constraint_mut(&mut inner.lhs);
+            v.visit_constraint_mut(&mut inner.rhs);
+        }
+        Constraint::Parenthesized(inner) => v.visit_constraint_mut(inner),
+        Constraint::Comparison(inner) => {
+            v.visit_constraint_rvalue_mut(&mut inner.lhs);
+            v.visit_constraint_rvalue_mut(&mut inner.rhs);
+        }
+        Constraint::Exists(inner) => v.visit_ident_mut(&mut inner.name),
+        Constraint::Constructor(inner) => v.visit_ident_mut(&mut inner.name),
+    }
+}
+
+pub fn walk_constraint_rvalue_mut<V: VisitMut + ?Sized>(v: &mut V, rvalue: &mut ConstraintRValue) {
+    match rvalue {
+        ConstraintRValue::Field(field) => v.visit_ident_mut(field),
+        _ => {
+            for op in rvalue.operands_mut() {
+                v.visit_constraint_rvalue_mut(op);
+            }
+        }
+    }
+}
+
+pub fn walk_action_mut<V: VisitMut + ?Sized>(v: &mut V, action: &mut Action) {
+    match action {
+        Action::Label(label) => v.visit_ident_mut(&mut label.label),
+        Action::LocalDecl(inner) => {
+            v.visit_ident_mut(&mut inner.name.field);
+            v.visit_rvalue_mut(&mut inner.val);
+        }
+        Action::Export(inner) => v.visit_rvalue_mut(&mut inner.op),
+        Action::Assignment(inner) => {
+            v.visit_lvalue_mut(&mut inner.name);
+            v.visit_rvalue_mut(&mut inner.val);
+        }
+        Action::Build(inner) => v.visit_ident_mut(&mut inner.field),
+        Action::If(inner) => {
+            v.visit_rvalue_mut(&mut inner.cond);
+            v.visit_action_mut(&mut inner.action);
+        }
+        Action::Goto(ActionGoto::Label(label)) => v.visit_ident_mut(&mut label.label),
+        Action::Goto(ActionGoto::Address(address)) => v.visit_rvalue_mut(address),
+        Action::Macro(inner) => inner
+            .args
+            .iter_mut()
+            .for_each(|arg| v.visit_rvalue_mut(arg)),
+        Action::PCodeOp(inner) => inner
+            .args
+            .iter_mut()
+            .for_each(|arg| v.visit_rvalue_mut(arg)),
+        Action::Call(inner) => v.visit_rvalue_mut(&mut inner.address),
+        Action::Return(inner) => v.visit_rvalue_mut(&mut inner.val),
+    }
+}
+
+pub fn walk_rvalue_mut<V: VisitMut + ?Sized>(v: &mut V, rvalue: &mut RValue) {
+    match rvalue {
+        RValue::Ref(inner) => v.visit_ident_mut(&mut inner.field),
+        RValue::LValue(inner) => v.visit_lvalue_mut(inner),
+        _ => {
+            for op in rvalue.operands_mut() {
+                v.visit_rvalue_mut(op);
+            }
+        }
+    }
+}
+
+pub fn walk_lvalue_mut<V: VisitMut + ?Sized>(v: &mut V, lvalue: &mut LValue) {
+    match lvalue {
+        LValue::Ident(inner) => v.visit_ident_mut(&mut inner.field),
+        LValue::Slice(inner) => v.visit_ident_mut(&mut inner.field),
+        LValue::Ref(inner) => v.visit_rvalue_mut(&mut inner.op),
+    }
+}
@@ -1,10 +1,13 @@
-use crate::{ConstraintRValue, Constructor, ContextField, Endianness, Spec};
-use std::{collections::HashMap, iter::repeat, sync::Arc};
+use crate::{ConstraintRValue, Constructor, ContextField, Endianness, Spec, Token, TokenField};
+use std::{collections::HashMap, iter::repeat, ops::Range, sync::Arc};
 
 #[derive(Clone)]
 pub struct State<'s> {
     pub(crate) spec: &'s Spec,
     pub(crate) code: &'s [u8],
+    /// The token `code` starts with, its fields take precedence over fields
+    /// of the same name in other tokens.
+    pub(crate) token: Option<&'s Token>,
     registers: Arc<HashMap<String, Register>>,
 }
 
@@ -13,6 +16,7 @@ impl<'s> State<'s> {
         State {
             spec,
             code,
+            token: None,
             registers: Arc::new(
                 spec.registers
                     .iter()
@@ -42,83 +46,146 @@ impl<'s> State<'s> {
         }
     }
 
-    pub fn match_constructor(&self, table: Option<&str>) -> Option<&Constructor> {
+    /// Returns the most specific constructor of `table` that matches. If
+    /// several match without one being more specific than the others, the
+    /// first of them in source order is returned.
+    pub fn match_constructor(&self, table: Option<&str>) -> Option<&'s Constructor> {
+        match self.resolve_constructor(table) {
+            ConstructorMatch::NoMatch => None,
+            ConstructorMatch::Unique(constructor) => Some(constructor),
+            ConstructorMatch::Ambiguous(constructors) => Some(constructors[0]),
+        }
+    }
+
+    pub fn resolve_constructor(&self, table: Option<&str>) -> ConstructorMatch<'s> {
+        let spec = self.spec;
         let table = table.unwrap_or("instruction");
-        self.spec
-            .constructors
+        let node = match spec.decision_tree.table(table) {
+            Some(node) => node,
+            None => {
+                return spec
+                    .constructors
+                    .iter()
+                    .filter(|c| c.header.table == table)
+                    .find(|c| c.matches(self.clone()))
+                    .map_or(ConstructorMatch::NoMatch, ConstructorMatch::Unique)
+            }
+        };
+
+        let matches: Vec<_> = node
+            .candidates(self)
             .iter()
-            .filter(|c| c.header.table == table)
-            .find(|c| c.matches(self.clone()))
+            .filter(|candidate| spec.alternative(**candidate).matches(self.clone()))
+            .collect();
+
+        // candidates are ordered by specificity, so the first match is the
+        // most specific one unless others are incomparable with it
+        let mut most_specific: Vec<_> = matches
+            .iter()
+            .filter(|candidate| {
+                let pattern = spec.alternative(***candidate);
+                !matches
+                    .iter()
+                    .any(|other| spec.alternative(**other).specializes(pattern))
+            })
+            .map(|(constructor, _)| *constructor)
+            .collect();
+        most_specific.sort_unstable();
+        most_specific.dedup();
+        match most_specific.as_slice() {
+            [] => ConstructorMatch::NoMatch,
+            [constructor] => ConstructorMatch::Unique(&spec.constructors[*constructor]),
+            _ => ConstructorMatch::Ambiguous(
+                most_specific
+                    .iter()
+                    .map(|c| &spec.constructors[*c])
+                    .collect(),
+            ),
+        }
+    }
+
+    pub(crate) fn register_data(&self, name: &str) -> Option<&[u8]> {
+        self.registers.get(name).map(|r| &*r.data)
     }
 
     pub(crate) fn token_len(&self, name: &str) -> Option<usize> {
-        self.spec
-            .tokens
-            .iter()
-            .find(|token| token.fields.iter().any(|f| f.name == name))
-            .map(|token| token.size as usize / 8)
+        self.token_field(name)
+            .map(|(token, _)| token.size as usize / 8)
     }
 
-    pub(crate) fn field_value(&self, name: &str) -> Option<i128> {
+    fn token_field(&self, name: &str) -> Option<(&'s Token, &'s TokenField)> {
+        self.token
+            .into_iter()
+            .chain(self.spec.tokens.iter())
+            .find_map(|token| {
+                let field = token.fields.iter().find(|f| f.name == name)?;
+                Some((token, field))
+            })
+    }
+
+    /// Returns the value of a token or context field, sign extended if the
+    /// field is signed.
+    pub fn field_value(&self, name: &str) -> Option<i128> {
+        if let Some((token, field)) = self.token_field(name) {
+            let len = token.size as usize / 8;
+            let endianness = self.spec.token_endianness(token);
+            let raw = read_field(self.code, len, &field.range, endianness)?;
+            return Some(field.decode(raw));
+        }
+
         self.spec
-            .tokens
+            .contexts
             .iter()
-            .flat_map(|token| token.fields.iter().zip(repeat(token.size / 8)))
+            .flat_map(|context| context.fields.iter().zip(repeat(&context.register)))
             .find(|(f, _)| f.name == name)
-            .map(|(f, size)| (f.range.start, f.range.end, size, self.code))
-            .or_else(|| {
-                self.spec
-                    .contexts
-                    .iter()
-                    .flat_map(|context| {
-                        let register_size = self
-                            .spec
-                            .registers
-                            .iter()
-                            .find(|r| r.name == context.register)
-                            .map(|r| r.size)
-                            .unwrap();
-                        let register = self
-                            .registers
-                            .get(&context.register)
-                            .map(|r| &*r.data)
-                            .unwrap();
-                        context.fields.iter().zip(repeat((register_size, register)))
-                    })
-                    .find(|(f, _)| f.name == name)
-                    .map(|(f, (size, data))| (f.range.start, f.range.end, size, data))
+            .and_then(|(field, register)| {
+                let data = self.register_data(register).unwrap();
+                let raw = read_field(data, data.len(), &field.range, self.spec.endianness)?;
+                Some(field.decode(raw))
             })
-            .and_then(|(start, end, size, data)| {
-                if data.len() < size as usize {
-                    return None;
-                }
-                let mask = (1 << (end + 1)) - 1;
-
-                let mut value = 0;
-                for (i, b) in data[..size as usize].iter().enumerate() {
-                    let offset = if let Endianness::Little = self.spec.endianness {
-                        i * 8
-                    } else {
-                        size as usize - i * 8 - 1
-                    };
-                    value |= (*b as i128) << offset;
-                }
+    }
 
-                Some((value & mask) >> start)
-            })
+    /// Returns the value of field `name` as `constructor` sees it, reading
+    /// the token at the position the field appears in its constraint.
+    pub fn operand_value(&self, constructor: &Constructor, name: &str) -> Option<i128> {
+        match constructor.constraint.field_state(name, self.clone()) {
+            Some(state) => state.field_value(name),
+            None => self.field_value(name),
+        }
     }
 
     pub(crate) fn eval(&self, rvalue: &ConstraintRValue) -> Option<i128> {
-        match rvalue {
-            ConstraintRValue::Add(inner) => Some(self.eval(&inner.lhs)? + self.eval(&inner.rhs)?),
-            ConstraintRValue::Field(name) => self.field_value(&name),
-            ConstraintRValue::Integer(val) => Some(*val),
-        }
+        rvalue.evaluate(&mut |name| self.field_value(name))
     }
 }
 
+pub enum ConstructorMatch<'s> {
+    NoMatch,
+    Unique(&'s Constructor),
+    /// Constructors that match without one being more specific than the
+    /// others, in source order.
+    Ambiguous(Vec<&'s Constructor>),
+}
+
+/// Reads the bits `range` of a `len` byte token at the start of `data`, the
+/// inverse of `BitPattern::set_field`.
+pub(crate) fn read_field(
+    data: &[u8],
+    len: usize,
+    range: &Range<u16>,
+    endianness: Endianness,
+) -> Option<i128> {
+    let bytes = data.get(..len)?;
+    let value = match endianness {
+        Endianness::Little => bytes.iter().rev().fold(0, |v, b| v << 8 | *b as i128),
+        Endianness::Big => bytes.iter().fold(0, |v, b| v << 8 | *b as i128),
+    };
+    let bits = range.end - range.start + 1;
+    Some(value >> range.start & ((1 << bits) - 1))
+}
+
 #[derive(Clone)]
-pub struct Register {
+struct Register {
     data: Vec<u8>,
 }
 
@@ -127,13 +194,13 @@ impl Register {
         let start = field.range.start as usize;
         let end = field.range.end as usize;
 
-        for i in start..end {
+        for i in start..=end {
             let val = value >> (i - start);
             let offset = match endianness {
                 Endianness::Little => i / 8,
                 Endianness::Big => self.data.len() - i / 8 - 1,
             };
-            if val != 0 {
+            if val & 1 != 0 {
                 // set bit
                 self.data[offset] |= 1 << (i % 8);
             } else {
@@ -143,3 +210,308 @@ impl Register {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{Constraint, ConstraintEllipsis, Spec, State};
+
+    #[test]
+    fn test_signed_fields() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 ctx ];
+define token instr(16)
+    op = (8,15)
+    simm = (0,7) signed
+    imm = (0,7)
+;
+define context ctx
+    delta = (0,3) signed
+;
+
+:back is op=1 & simm<0 { }
+:fwd is op=1 & simm>0 { }
+:adj is op=2 & delta<0 { }
+"#,
+        );
+
+        let code = [0xfe, 0x01];
+        let mut state = State::new(&spec, &code);
+        assert_eq!(state.field_value("simm"), Some(-2));
+        assert_eq!(state.field_value("imm"), Some(0xfe));
+        assert_eq!(
+            state
+                .match_constructor(None)
+                .map(|c| c.header.mnemonic.trim()),
+            Some("back")
+        );
+
+        state.set_context("delta", -3);
+        assert_eq!(state.field_value("delta"), Some(-3));
+        let code = [0x00, 0x02];
+        let mut state = State::new(&spec, &code);
+        state.set_context("delta", -3);
+        assert_eq!(
+            state
+                .match_constructor(None)
+                .map(|c| c.header.mnemonic.trim()),
+            Some("adj")
+        );
+    }
+
+    #[test]
+    fn test_scoped_fields() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 ];
+define token instr(8)
+    op = (4,7)
+    reg = (0,3)
+;
+define token ext(16)
+    tag = (12,15)
+    reg = (0,7)
+;
+
+:a is op=1 & reg=2 { }
+:b is op=2; tag=3 & reg=0x45 { }
+:c reg is op=3; tag=4 & reg { }
+"#,
+        );
+
+        let mnemonic = |code: &[u8]| {
+            State::new(&spec, code)
+                .match_constructor(None)
+                .map(|c| c.header.mnemonic.trim().to_string())
+        };
+        assert_eq!(mnemonic(&[0x12]).as_deref(), Some("a"));
+        assert_eq!(mnemonic(&[0x20, 0x45, 0x30]).as_deref(), Some("b"));
+        assert_eq!(mnemonic(&[0x20, 0x05, 0x30]), None);
+
+        let code = [0x30, 0x99, 0x40];
+        let state = State::new(&spec, &code);
+        let constructor = state.match_constructor(None).unwrap();
+        assert_eq!(state.operand_value(constructor, "reg"), Some(0x99));
+    }
+
+    #[test]
+    fn test_big_endian_fields() {
+        let spec = Spec::parse(
+            r#"
+define endian=big;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 ctx ];
+define token prefix(8)
+    pre = (0,7)
+;
+define token instr(32)
+    opcd = (26,31)
+    rs = (21,25)
+    rt = (16,20)
+    simm = (0,15) signed
+    rc = (0,0)
+;
+define token wide(64)
+    hi = (32,63)
+    lo = (0,31)
+;
+define context ctx
+    mode = (8,11)
+;
+
+:addiu is opcd=9 & rt=2 & simm<0 { }
+:prefixed is pre=0x7f; opcd=15 & simm>0 { }
+:wide is hi=0x12345678 & lo=0x9abcdef0 { }
+"#,
+        );
+
+        // MIPS `addiu v0, zero, -4`
+        let code = [0x24, 0x02, 0xff, 0xfc];
+        let state = State::new(&spec, &code);
+        assert_eq!(state.field_value("opcd"), Some(9));
+        assert_eq!(state.field_value("rs"), Some(0));
+        assert_eq!(state.field_value("rt"), Some(2));
+        assert_eq!(state.field_value("simm"), Some(-4));
+        assert_eq!(state.field_value("rc"), Some(0));
+        assert_eq!(state.field_value("hi"), None);
+        let mnemonic = state
+            .match_constructor(None)
+            .map(|c| c.header.mnemonic.trim());
+        assert_eq!(mnemonic, Some("addiu"));
+
+        // PowerPC `addis r3, r4, 0x10` behind a prefix byte
+        let code = [0x7f, 0x3c, 0x64, 0x00, 0x10];
+        let state = State::new(&spec, &code);
+        let mnemonic = state
+            .match_constructor(None)
+            .map(|c| c.header.mnemonic.trim());
+        assert_eq!(mnemonic, Some("prefixed"));
+
+        let code = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+        let state = State::new(&spec, &code);
+        assert_eq!(state.field_value("hi"), Some(0x12345678));
+        assert_eq!(state.field_value("lo"), Some(0x9abcdef0));
+        let mnemonic = state
+            .match_constructor(None)
+            .map(|c| c.header.mnemonic.trim());
+        assert_eq!(mnemonic, Some("wide"));
+
+        let mut state = State::new(&spec, &code);
+        state.set_context("mode", 0xa);
+        assert_eq!(state.field_value("mode"), Some(0xa));
+        assert_eq!(
+            state.register_data("ctx"),
+            Some(&[0x00, 0x00, 0x0a, 0x00][..])
+        );
+    }
+
+    #[test]
+    fn test_token_endianness() {
+        let spec = Spec::parse(
+            r#"
+define endian=big;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 ];
+define token instr(16)
+    op = (8,15)
+;
+define token data(16) endian=little
+    lo = (0,7)
+    hi = (8,15)
+;
+
+:ld is op=0x12; hi=0x56 & lo=0x78 { }
+"#,
+        );
+
+        let code = [0x34, 0x12];
+        let state = State::new(&spec, &code);
+        assert_eq!(state.field_value("op"), Some(0x34));
+        assert_eq!(state.field_value("lo"), Some(0x34));
+        assert_eq!(state.field_value("hi"), Some(0x12));
+
+        let code = [0x12, 0x00, 0x78, 0x56];
+        let state = State::new(&spec, &code);
+        let mnemonic = state
+            .match_constructor(None)
+            .map(|c| c.header.mnemonic.trim());
+        assert_eq!(mnemonic, Some("ld"));
+        assert_eq!(spec.assemble("ld"), Some(code.to_vec()));
+    }
+
+    #[test]
+    fn test_operand_offsets() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 r1 r2 r3 ];
+define token instr(8)
+    op = (4,7)
+    reg = (0,3)
+;
+define token imm8(8)
+    imm = (0,7)
+;
+define token imm16(16)
+    disp = (0,15) signed
+;
+attach variables reg [ r0 r1 r2 r3 _ _ _ _ _ _ _ _ _ _ _ _ ];
+
+src: imm is op=0; imm { }
+src: reg is op=1 & reg { }
+
+:ld reg, disp is op=2 & reg; imm; disp { }
+:mov src, disp is op=3; src; disp { }
+"#,
+        );
+
+        let code = [0x21, 0xaa, 0xfe, 0xff];
+        let state = State::new(&spec, &code);
+        let ld = state.match_constructor(None).unwrap();
+        assert_eq!(state.operand_value(ld, "reg"), Some(1));
+        assert_eq!(state.operand_value(ld, "imm"), Some(0xaa));
+        assert_eq!(state.operand_value(ld, "disp"), Some(-2));
+
+        // the offset of `disp` depends on the length of `src`
+        let code = [0x30, 0x00, 0x7f, 0x10, 0x00];
+        let state = State::new(&spec, &code);
+        let mov = state.match_constructor(None).unwrap();
+        assert_eq!(state.operand_value(mov, "disp"), Some(0x10));
+        let code = [0x30, 0x12, 0x10, 0x00];
+        let state = State::new(&spec, &code);
+        assert_eq!(state.operand_value(mov, "disp"), Some(0x10));
+    }
+
+    #[test]
+    fn test_constraint_expressions() {
+        let spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 ];
+define token instr(16)
+    op = (12,15)
+    a = (8,11)
+    b = (4,7)
+    c = (0,3)
+;
+
+:sum is op=1 & c=a + b * 2 - 1 { }
+:shift is op=2 & c=(a << 1) $or (b >> 2) { }
+:mask is op=3 & c=(a & b | 1) $xor 2 { }
+:neg is op=4 & a=-c + 16 / 2 { }
+"#,
+        );
+
+        let matches = |word: u16| {
+            let code = word.to_le_bytes();
+            let state = State::new(&spec, &code);
+            state
+                .match_constructor(None)
+                .map(|c| c.header.mnemonic.trim().to_string())
+        };
+        assert_eq!(matches(0x1237).as_deref(), Some("sum"));
+        assert_eq!(matches(0x1236), None);
+        assert_eq!(matches(0x2386).as_deref(), Some("shift"));
+        assert_eq!(matches(0x36c7).as_deref(), Some("mask"));
+        assert_eq!(matches(0x4503).as_deref(), Some("neg"));
+    }
+
+    #[test]
+    fn test_ellipsis() {
+        let mut spec = Spec::parse(
+            r#"
+define endian=little;
+define space ram type=ram_space size=4 default;
+define register offset=0 size=4 [ r0 ];
+define token instr(8)
+    a = (4,7)
+    b = (0,3)
+;
+
+:x is (a=1) | b=2 { }
+"#,
+        );
+        // the parser drops `...`, so mark the left alternative by hand
+        if let Constraint::Or(or) = &mut spec.constructors[0].constraint {
+            or.lhs = ConstraintEllipsis {
+                op: or.lhs.clone(),
+                span: or.lhs.span(),
+            }
+            .into();
+        }
+
+        let constraint = &spec.constructors[0].constraint;
+        for code in [[0x10, 0xff], [0x22, 0xff]] {
+            let state = State::new(&spec, &code);
+            assert!(constraint.matches(state.clone()));
+            assert_eq!(constraint.len(state), Some(1));
+        }
+        assert!(!constraint.matches(State::new(&spec, &[0x33])));
+    }
+}
@@ -0,0 +1,138 @@
+//! Runs the specs in `tests/corpus` against their expected disassembly and
+//! p-code.
+//!
+//! Each `<name>.slaspec` has a `<name>.test` next to it listing instructions
+//! as `<address>: <bytes>`, followed by the expected text indented by four
+//! spaces and the p-code indented by eight. `context <field>=<value>` lines
+//! apply to the instructions after them. Run with `SLEIGH_BLESS=1` to rewrite
+//! the expectations from the current output. A `<name>.cspec` is loaded as
+//! the spec's compiler spec.
+
+use sleigh::{preprocess, Assembler, Disassembler, Generator, Spec};
+use std::{fs, path::Path};
+
+#[test]
+fn corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut specs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("slaspec".as_ref()))
+        .collect();
+    specs.sort();
+    assert!(!specs.is_empty());
+
+    let mut failed = String::new();
+    for path in specs {
+        let mut spec = Spec::parse(&preprocess(&dir, path.file_name().unwrap()));
+        let cspec = path.with_extension("cspec");
+        if cspec.exists() {
+            spec.load_compiler_spec(&cspec).unwrap();
+            assert!(spec.stack_pointer().is_some(), "{}", cspec.display());
+        }
+        if let Some(diagnostic) = spec.validate().first() {
+            panic!("{}: {}", path.display(), diagnostic);
+        }
+
+        let test = path.with_extension("test");
+        let expected = fs::read_to_string(&test).unwrap();
+        let actual = run(&spec, &expected);
+        if actual != expected {
+            if std::env::var_os("SLEIGH_BLESS").is_some() {
+                fs::write(&test, actual).unwrap();
+            } else {
+                failed += &format!("{}:\n{}\n", test.display(), actual);
+            }
+        }
+    }
+    assert!(failed.is_empty(), "mismatched output in\n{}", failed);
+}
+
+/// Re-renders a test file with the output of the current disassembler.
+fn run(spec: &Spec, test: &str) -> String {
+    let mut context = Vec::new();
+    let mut out = String::new();
+    for line in test.lines() {
+        if line.starts_with(' ') {
+            continue;
+        }
+        out += line;
+        out += "\n";
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(assignment) = line.strip_prefix("context ") {
+            let (name, value) = assignment.split_once('=').unwrap();
+            context.push((name.trim().to_string(), parse_int(value.trim())));
+            continue;
+        }
+
+        let (address, bytes) = line.split_once(':').unwrap();
+        let code: Vec<u8> = bytes
+            .split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).unwrap())
+            .collect();
+        let mut disassembler = Disassembler::new(spec);
+        disassembler.set_address(parse_int(address.trim()) as u64);
+        for (name, value) in context.iter() {
+            disassembler.set_context(name, *value);
+        }
+        match disassembler.disassemble(&code) {
+            Some(instruction) => {
+                assert_eq!(instruction.len, code.len(), "length of `{}`", line);
+                out += &format!("    {}\n", instruction.text);
+                for pcode in instruction.pcode {
+                    out += &format!("        {}\n", pcode);
+                }
+            }
+            None => out += "    <invalid>\n",
+        }
+    }
+    out
+}
+
+fn parse_int(s: &str) -> i128 {
+    match s.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).unwrap(),
+        None => s.parse().unwrap(),
+    }
+}
+
+/// Random encodings of every instruction decode to text that assembles back
+/// to an encoding with the same text.
+#[test]
+fn round_trip() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("slaspec".as_ref()) {
+            continue;
+        }
+        let spec = Spec::parse(&preprocess(&dir, path.file_name().unwrap()));
+        for (index, constructor) in spec.constructors.iter().enumerate() {
+            if constructor.header.table != "instruction" {
+                continue;
+            }
+            for seed in 0..16 {
+                let mut generator = Generator::new(&spec, seed);
+                let encoding = generator
+                    .constructor(index)
+                    .unwrap_or_else(|| panic!("{}: no encoding for #{}", path.display(), index));
+
+                let mut disassembler = Disassembler::new(&spec);
+                let mut assembler = Assembler::new(&spec);
+                for (name, value) in encoding.context.iter() {
+                    disassembler.set_context(name, *value);
+                    assembler.set_context(name, *value);
+                }
+                let text = disassembler.disassemble(&encoding.bytes).unwrap().text;
+                // the assembler can't solve for operands relative to
+                // `inst_next` yet
+                if let Some(bytes) = assembler.assemble(&text) {
+                    let again = disassembler.disassemble(&bytes).map(|i| i.text);
+                    assert_eq!(again.as_ref(), Some(&text), "{}", path.display());
+                }
+            }
+        }
+    }
+}